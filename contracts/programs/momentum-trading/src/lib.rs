@@ -1,22 +1,150 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::compute_units::sol_remaining_compute_units;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program::invoke_signed;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::{ed25519_program, pubkey, sysvar};
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token::{self, Burn, CloseAccount, Mint, MintTo, SetAuthority, Token, TokenAccount, Transfer};
 
 declare_id!("FuTsTar11111111111111111111111111111111111");
 
+/// Minimum number of seconds between a momentum commitment and its reveal
+pub const MIN_REVEAL_DELAY: i64 = 2;
+
+/// Seconds after a momentum update during which it may still be corrected
+pub const MOMENTUM_CORRECTION_WINDOW: i64 = 60;
+
+/// Top of the momentum index range for new pools
+pub const DEFAULT_INDEX_SCALE_MAX: u16 = 100;
+
+/// Default base fee charged on winning profits, in basis points; the protocol
+/// config's limits set the fee in force
+pub const PROFIT_FEE_BPS: u64 = 200;
+
+/// Share of the profit fee paid to a position's referrer, in basis points
+pub const REFERRAL_SHARE_BPS: u64 = 2_500;
+
+/// Open positions listed in a trader's per-pool TraderPositions index
+pub const MAX_INDEXED_POSITIONS: usize = 16;
+
+/// Recent momentum updates kept on the pool to measure index volatility
+pub const MOMENTUM_SAMPLE_COUNT: usize = 8;
+
+/// Oracle confidence, in percent, assumed when an update doesn't report one
+pub const FULL_CONFIDENCE: u8 = 100;
+
+/// Payout tiers a pool may configure
+pub const MAX_PAYOUT_BANDS: usize = 4;
+
+/// Longest accepted match_id, in bytes; it also seeds the pool PDA, whose
+/// seeds are capped at 32 bytes each
+pub const MAX_MATCH_ID_LEN: usize = 32;
+
+/// Longest accepted team name, in bytes
+pub const MAX_TEAM_NAME_LEN: usize = 24;
+
+/// Oracle slots per pool: the primary feeder, its designated backup and
+/// further oracles whose readings can be aggregated
+pub const MAX_ORACLES: usize = 5;
+
+/// Seconds an oracle's reading counts towards the next aggregated update
+pub const MAX_READING_AGE: i64 = 60;
+
+/// Longest window a position may commit to, and the largest time offset a
+/// pool config may add to one, in seconds
+pub const MAX_WINDOW_DURATION: i64 = 30 * 24 * 60 * 60;
+
+/// Pools a league registry can hold at once
+pub const MAX_LEAGUE_POOLS: usize = 64;
+
+/// Compute units kept in reserve by settle_positions_batch; it stops taking
+/// new positions once fewer remain, so the transaction can still finish
+pub const SETTLE_BATCH_CU_RESERVE: u64 = 40_000;
+
+/// Fixed-point scale of the pool's funding indices, per unit of stake
+pub const FUNDING_INDEX_SCALE: i64 = 1_000_000_000;
+
+/// Metaplex Token Metadata program, which holds tokenized positions' metadata
+pub const TOKEN_METADATA_PROGRAM_ID: Pubkey = pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+/// Switchboard V2 program, which owns the aggregator accounts feed-driven
+/// pools read
+pub const SWITCHBOARD_PROGRAM_ID: Pubkey = pubkey!("SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f");
+
+/// Pyth oracle program, which owns the price accounts pools value their
+/// collateral with
+pub const PYTH_PROGRAM_ID: Pubkey = pubkey!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+
+/// SPL token-swap program, the only AMM winners may swap their payout through
+pub const SPL_TOKEN_SWAP_PROGRAM_ID: Pubkey = pubkey!("SwaPpA9LAaLfeLi3a68M4DjnLqgtticKg6CnyNwgAC8");
+
+/// Longest metadata URI the Token Metadata program accepts
+pub const MAX_METADATA_URI_LEN: usize = 200;
+
+/// Staking tiers the protocol config holds
+pub const MAX_FEE_TIERS: usize = 3;
+
+/// Time staked tokens stay locked after the owner last adds to their stake,
+/// so a stake can't be taken out right after settling at a discount
+pub const STAKE_LOCKUP_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+/// Seconds an oracle's stake stays bonded after it last published, leaving
+/// time to dispute its submissions once the pool finalizes
+pub const ORACLE_UNBONDING_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+/// Updates a pool's momentum history account retains
+pub const MOMENTUM_HISTORY_LEN: usize = 512;
+
+/// Current MomentumPool account layout version
+pub const POOL_VERSION: u8 = 1;
+
+/// Current TradingPosition account layout version
+pub const POSITION_VERSION: u8 = 1;
+
 #[program]
 pub mod futstar_momentum_trading {
     use super::*;
 
-    /// Initialize a new momentum trading pool for a match
+    /// Initialize a new momentum trading pool for a match. Its settlement
+    /// mode is fixed for the pool's lifetime.
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize_pool(
         ctx: Context<InitializePool>,
         match_id: String,
         start_time: i64,
         home_team: String,
         away_team: String,
+        config: PoolConfig,
+        kind: PoolKind,
+        settlement_mode: SettlementMode,
+        switchboard_feed: Option<Pubkey>, // read by update_momentum_from_switchboard instead of trusting a signer
     ) -> Result<()> {
         let pool = &mut ctx.accounts.momentum_pool;
         
+        let protocol_config = &ctx.accounts.protocol_config;
+        config.validate(&protocol_config.limits)?;
+        require!(
+            config.max_profit_fee_bps(&protocol_config.limits) <= protocol_config.max_profit_fee_bps as u64,
+            TradingError::FeeExceedsCeiling
+        );
+        require!(
+            !match_id.is_empty() && match_id.len() <= MAX_MATCH_ID_LEN,
+            TradingError::InvalidPoolMetadata
+        );
+        for team in [&home_team, &away_team] {
+            require!(
+                !team.is_empty() && team.len() <= MAX_TEAM_NAME_LEN,
+                TradingError::InvalidPoolMetadata
+            );
+        }
+        
         pool.authority = ctx.accounts.authority.key();
         pool.match_id = match_id;
         pool.start_time = start_time;
@@ -24,117 +152,331 @@ pub mod futstar_momentum_trading {
         pool.away_team = away_team;
         pool.total_long_volume = 0;
         pool.total_short_volume = 0;
-        pool.current_momentum_index = 50; // Start at neutral
-        pool.is_active = true;
+        pool.index_scale_max = DEFAULT_INDEX_SCALE_MAX;
+        pool.current_momentum_index = (pool.index_scale_max / 2) as u8; // Start at neutral
+        pool.status = PoolStatus::Active;
+        pool.kind = kind;
+        pool.protocol_limits = ctx.accounts.protocol_config.limits;
+        pool.settlement_mode = settlement_mode;
+        (pool.oracle_source, pool.switchboard_feed) = match switchboard_feed {
+            Some(feed) => (OracleSource::Switchboard, feed),
+            None => (OracleSource::Signer, Pubkey::default()),
+        };
+        pool.collateral_mint = ctx.accounts.collateral_mint.key();
+        pool.vault_bump = ctx.bumps.pool_token_account;
+        pool.pending_commitment = None;
+        pool.commitment_time = 0;
+        pool.config = config;
+        pool.version = POOL_VERSION;
         pool.created_at = Clock::get()?.unix_timestamp;
+        pool.last_update = pool.created_at;
+        pool.last_funding_time = pool.created_at;
+        pool.previous_momentum_index = pool.current_momentum_index;
+        pool.previous_update = pool.created_at;
+        pool.oracle_confidence = FULL_CONFIDENCE;
+        // The authority feeds the index until it designates another oracle
+        pool.oracles[0] = OracleEntry {
+            key: pool.authority,
+            enabled: true,
+        };
+        let (neutral_index, created_at) = (pool.current_momentum_index, pool.created_at);
+        pool.record_sample(neutral_index, created_at);
+        if let Some(league) = ctx.accounts.league_registry.as_mut() {
+            league.record_created(pool.key())?;
+            pool.league_id = league.league_id;
+        }
+        check_account_size(&pool.fully_populated(), &pool.to_account_info())?;
         
         msg!("Momentum pool initialized for match: {}", pool.match_id);
         Ok(())
     }
 
+    /// Accept another collateral mint in this pool by creating its vault.
+    /// Positions settle in the mint they were opened with. Pool-level volume
+    /// and liability counters sum raw base units across mints, so caps should
+    /// be set with the mix of mints in mind.
+    pub fn add_collateral_mint(ctx: Context<AddCollateralMint>) -> Result<()> {
+        msg!(
+            "Collateral mint {} added to pool {}",
+            ctx.accounts.collateral_mint.key(),
+            ctx.accounts.momentum_pool.match_id
+        );
+        Ok(())
+    }
+
     /// Open a long position (bet on momentum increase)
+    #[allow(clippy::too_many_arguments)]
     pub fn open_long_position(
         ctx: Context<OpenPosition>,
         amount: u64,
         window_duration: i64, // in seconds (300 for 5 minutes)
+        checkpoint: Option<MatchCheckpoint>, // settle at a match checkpoint instead
+        max_entry_index: Option<u8>, // reject if momentum has already risen past this
+        client_tag: [u8; 16], // opaque client-side strategy identifier
+        referrer: Option<Pubkey>, // earns a share of the profit fee at settlement
+        payout_recipient: Option<Pubkey>, // wallet paid at settlement, defaults to the trader
+        stop_loss_index: Option<u8>, // close once momentum falls to this
+        take_profit_index: Option<u8>, // close once momentum rises to this
     ) -> Result<()> {
-        let position = &mut ctx.accounts.trading_position;
-        let pool = &mut ctx.accounts.momentum_pool;
-        let clock = Clock::get()?;
-        
-        require!(pool.is_active, TradingError::PoolNotActive);
-        require!(amount > 0, TradingError::InvalidAmount);
-        
-        // Transfer tokens from user to pool
-        token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_token_account.to_account_info(),
-                    to: ctx.accounts.pool_token_account.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
-                },
-            ),
+        let args = OpenPositionArgs {
             amount,
-        )?;
-        
-        position.trader = ctx.accounts.user.key();
-        position.pool = pool.key();
-        position.position_type = PositionType::Long;
-        position.amount = amount;
-        position.entry_momentum_index = pool.current_momentum_index;
-        position.entry_time = clock.unix_timestamp;
-        position.window_end_time = clock.unix_timestamp + window_duration;
-        position.is_settled = false;
-        position.pnl = 0;
-        
-        pool.total_long_volume += amount;
-        
-        msg!("Long position opened: {} SOL at momentum index {}", 
-             amount as f64 / 1e9 as f64, 
-             position.entry_momentum_index);
-        
-        Ok(())
+            window_duration,
+            checkpoint,
+            entry_index_limit: max_entry_index,
+            client_tag,
+            referrer,
+            payout_recipient,
+            stop_loss_index,
+            take_profit_index,
+        };
+        ctx.accounts.open(PositionType::Long, &args, ctx.bumps.trader_positions)
     }
 
     /// Open a short position (bet on momentum decrease)
+    #[allow(clippy::too_many_arguments)]
     pub fn open_short_position(
         ctx: Context<OpenPosition>,
         amount: u64,
         window_duration: i64,
+        checkpoint: Option<MatchCheckpoint>,
+        min_entry_index: Option<u8>, // reject if momentum has already fallen past this
+        client_tag: [u8; 16],
+        referrer: Option<Pubkey>,
+        payout_recipient: Option<Pubkey>,
+        stop_loss_index: Option<u8>,
+        take_profit_index: Option<u8>,
     ) -> Result<()> {
-        let position = &mut ctx.accounts.trading_position;
-        let pool = &mut ctx.accounts.momentum_pool;
-        let clock = Clock::get()?;
-        
-        require!(pool.is_active, TradingError::PoolNotActive);
-        require!(amount > 0, TradingError::InvalidAmount);
-        
-        // Transfer tokens from user to pool
+        let args = OpenPositionArgs {
+            amount,
+            window_duration,
+            checkpoint,
+            entry_index_limit: min_entry_index,
+            client_tag,
+            referrer,
+            payout_recipient,
+            stop_loss_index,
+            take_profit_index,
+        };
+        ctx.accounts.open(PositionType::Short, &args, ctx.bumps.trader_positions)
+    }
+
+    /// Open a position represented by a freshly minted NFT, with Metaplex
+    /// metadata pointing at `metadata_uri`. The NFT can be traded freely;
+    /// whoever holds it at settlement is paid, and the NFT is burned.
+    pub fn open_tokenized_position(
+        ctx: Context<OpenTokenizedPosition>,
+        position_type: PositionType,
+        args: OpenPositionArgs,
+        metadata_uri: String,
+    ) -> Result<()> {
+        ctx.accounts.open(position_type, &args, metadata_uri, ctx.bumps.momentum_pool)
+    }
+
+    /// Open a straddle position (bet on momentum moving far in either
+    /// direction). It enters at the current index without a spread and pays
+    /// on the absolute change, at the pool's straddle fee.
+    pub fn open_straddle_position(
+        ctx: Context<OpenPosition>,
+        amount: u64,
+        window_duration: i64,
+        checkpoint: Option<MatchCheckpoint>,
+        client_tag: [u8; 16],
+        referrer: Option<Pubkey>,
+        payout_recipient: Option<Pubkey>,
+    ) -> Result<()> {
+        let args = OpenPositionArgs {
+            amount,
+            window_duration,
+            checkpoint,
+            entry_index_limit: None,
+            client_tag,
+            referrer,
+            payout_recipient,
+            stop_loss_index: None,
+            take_profit_index: None,
+        };
+        ctx.accounts.open(PositionType::Straddle, &args, ctx.bumps.trader_positions)
+    }
+
+    /// Open a position for `trader`, signed by a delegate the trader approved
+    /// with approve_delegate. The stake is pulled from the trader's token
+    /// account under its SPL delegation to the signer.
+    pub fn open_delegated_position(
+        ctx: Context<OpenDelegatedPosition>,
+        position_type: PositionType,
+        args: OpenPositionArgs,
+    ) -> Result<()> {
+        let accounts = ctx.accounts;
+        let delegate = accounts.delegate.key();
+        let user_token_account = &accounts.user_token_account;
+
+        require!(
+            user_token_account.delegate == COption::Some(delegate),
+            TradingError::InvalidDelegation
+        );
+        require!(
+            user_token_account.delegated_amount >= args.amount,
+            TradingError::InvalidDelegation
+        );
+
+        accounts.trading_position.collateral_mint = user_token_account.mint;
+        open_position(
+            &mut accounts.trading_position,
+            &mut accounts.momentum_pool,
+            &accounts.protocol_config,
+            Some((&mut accounts.trader_positions, ctx.bumps.trader_positions)),
+            accounts.trader.key(),
+            accounts.allowlist_entry.is_some(),
+            position_type,
+            &args,
+        )?;
+
         token::transfer(
             CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
+                accounts.token_program.to_account_info(),
                 Transfer {
-                    from: ctx.accounts.user_token_account.to_account_info(),
-                    to: ctx.accounts.pool_token_account.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
+                    from: accounts.user_token_account.to_account_info(),
+                    to: accounts.pool_token_account.to_account_info(),
+                    authority: accounts.delegate.to_account_info(),
                 },
             ),
-            amount,
+            args.amount,
         )?;
-        
-        position.trader = ctx.accounts.user.key();
-        position.pool = pool.key();
-        position.position_type = PositionType::Short;
-        position.amount = amount;
-        position.entry_momentum_index = pool.current_momentum_index;
-        position.entry_time = clock.unix_timestamp;
-        position.window_end_time = clock.unix_timestamp + window_duration;
-        position.is_settled = false;
-        position.pnl = 0;
-        
-        pool.total_short_volume += amount;
-        
-        msg!("Short position opened: {} SOL at momentum index {}", 
-             amount as f64 / 1e9 as f64, 
-             position.entry_momentum_index);
-        
+
+        msg!("Position opened for {} by delegate {}", accounts.trader.key(), delegate);
+        Ok(())
+    }
+
+    /// Let `delegate` open positions in this pool on the signer's behalf.
+    /// The delegate also needs an SPL token delegation on the signer's
+    /// collateral account to fund them.
+    pub fn approve_delegate(ctx: Context<ApproveDelegate>, delegate: Pubkey) -> Result<()> {
+        let approval = &mut ctx.accounts.delegate_approval;
+
+        approval.pool = ctx.accounts.momentum_pool.key();
+        approval.trader = ctx.accounts.trader.key();
+        approval.delegate = delegate;
+        approval.approved_at = Clock::get()?.unix_timestamp;
+        approval.bump = ctx.bumps.delegate_approval;
+
+        msg!("Delegate {} approved for {}", delegate, approval.trader);
+        Ok(())
+    }
+
+    /// Withdraw a delegate approval, returning its rent to the trader
+    pub fn revoke_delegate(ctx: Context<RevokeDelegate>) -> Result<()> {
+        msg!("Delegate {} revoked", ctx.accounts.delegate_approval.delegate);
+        Ok(())
+    }
+
+    /// Commit to the next momentum update as hash(new_index || nonce).
+    /// The following update_momentum_index call must reveal the matching
+    /// index and nonce, no sooner than MIN_REVEAL_DELAY seconds later.
+    pub fn commit_momentum(
+        ctx: Context<UpdateMomentum>,
+        commitment: [u8; 32],
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+
+        pool.check_oracle(&ctx.accounts.oracle.key())?;
+
+        pool.pending_commitment = Some(commitment);
+        pool.commitment_time = Clock::get()?.unix_timestamp;
+
+        msg!("Momentum update committed for match: {}", pool.match_id);
+
         Ok(())
     }
 
     /// Update momentum index from oracle
+    #[allow(clippy::too_many_arguments)]
     pub fn update_momentum_index(
         ctx: Context<UpdateMomentum>,
         new_index: u8,
+        nonce: Option<[u8; 32]>,
+        checkpoint: Option<MatchCheckpoint>,
+        signed_timestamp: Option<i64>, // relayed update signed off-chain by oracle_pubkey
+        confidence: Option<u8>, // oracle's confidence in the update, 1-100 percent
+        signed_sequence: Option<u64>, // required with signed_timestamp; above every sequence applied so far
     ) -> Result<()> {
         let pool = &mut ctx.accounts.momentum_pool;
+        let clock = Clock::get()?;
+        
+        match signed_timestamp {
+            // Any relayer may submit an update carrying the oracle's signature,
+            // verified by an Ed25519 program instruction just before this one
+            Some(timestamp) => {
+                require!(
+                    pool.oracle_pubkey != Pubkey::default(),
+                    TradingError::InvalidOracleSignature
+                );
+                require!(
+                    timestamp > pool.last_update && timestamp <= clock.unix_timestamp,
+                    TradingError::InvalidOracleSignature
+                );
+                // Each signed payload can land once, and only in the order
+                // the signing service produced them
+                let sequence = signed_sequence.ok_or(TradingError::InvalidOracleSignature)?;
+                require!(sequence > pool.oracle_sequence, TradingError::OracleSequenceReplayed);
+                let instructions_sysvar = ctx
+                    .accounts
+                    .instructions_sysvar
+                    .as_ref()
+                    .ok_or(TradingError::InvalidOracleSignature)?;
+                verify_ed25519_signature(
+                    instructions_sysvar,
+                    &pool.oracle_pubkey,
+                    &oracle_update_message(&pool.match_id, new_index, timestamp, sequence, confidence),
+                )?;
+                pool.oracle_sequence = sequence;
+            }
+            None => {
+                pool.check_oracle(&ctx.accounts.oracle.key())?;
+                check_oracle_stake(pool, ctx.accounts.oracle_stake.as_mut(), clock.unix_timestamp)?;
+            }
+        }
+        require!(new_index as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+        require!(pool.status != PoolStatus::Finalized, TradingError::PoolFinalized);
+        require!(pool.oracle_source == OracleSource::Signer, TradingError::WrongOracleSource);
+        require!(pool.config.update_quorum <= 1, TradingError::OracleQuorumRequired);
+        if let Some(confidence) = confidence {
+            require!(
+                (1..=FULL_CONFIDENCE).contains(&confidence),
+                TradingError::InvalidConfidence
+            );
+        }
+        
+        // A pending commitment must be revealed before the index can move
+        if let Some(commitment) = pool.pending_commitment {
+            let nonce = nonce.ok_or(TradingError::CommitmentMismatch)?;
+            require!(
+                clock.unix_timestamp >= pool.commitment_time + MIN_REVEAL_DELAY,
+                TradingError::RevealTooEarly
+            );
+            require!(
+                hashv(&[&[new_index], &nonce]).to_bytes() == commitment,
+                TradingError::CommitmentMismatch
+            );
+            pool.pending_commitment = None;
+        }
         
-        require!(ctx.accounts.oracle.key() == pool.authority, TradingError::UnauthorizedOracle);
-        require!(new_index <= 100, TradingError::InvalidMomentumIndex);
+        if !pool.check_circuit_breaker(new_index, clock.unix_timestamp)? {
+            return Ok(());
+        }
+        let old_index = pool.apply_momentum_update(new_index, clock.unix_timestamp, confidence);
+        if let Some(history) = &ctx.accounts.momentum_history {
+            history.load_mut()?.push(new_index, clock.unix_timestamp);
+        }
         
-        let old_index = pool.current_momentum_index;
-        pool.current_momentum_index = new_index;
-        pool.last_update = Clock::get()?.unix_timestamp;
+        if let Some(checkpoint) = checkpoint {
+            let snapshot = &mut pool.checkpoints[checkpoint as usize];
+            require!(snapshot.is_none(), TradingError::CheckpointAlreadyRecorded);
+            *snapshot = Some(CheckpointSnapshot {
+                momentum_index: new_index,
+                timestamp: clock.unix_timestamp,
+            });
+            msg!("Checkpoint {:?} recorded at momentum index {}", checkpoint, new_index);
+        }
         
         msg!("Momentum index updated: {} -> {}", old_index, new_index);
         
@@ -143,48 +485,244 @@ pub mod futstar_momentum_trading {
             old_index,
             new_index,
             timestamp: pool.last_update,
+            confidence: pool.oracle_confidence,
         });
         
         Ok(())
     }
 
-    /// Settle a position after window ends
-    pub fn settle_position(ctx: Context<SettlePosition>) -> Result<()> {
-        let position = &mut ctx.accounts.trading_position;
-        let pool = &ctx.accounts.momentum_pool;
-        let clock = Clock::get()?;
+    /// Update several pools in one transaction. Pools are passed as writable
+    /// remaining accounts, in the same order as `new_indices`. Pools this
+    /// oracle doesn't currently feed, finalized pools, pools awaiting a
+    /// commit-reveal and pools fed by readings or a feed are skipped; an out-of-range index fails the whole batch.
+    /// Batched updates aren't appended to the pools' history accounts.
+    /// Returns the number of pools updated.
+    pub fn update_momentum_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateMomentumBatch<'info>>,
+        new_indices: Vec<u8>,
+    ) -> Result<u32> {
+        require!(
+            new_indices.len() == ctx.remaining_accounts.len(),
+            TradingError::BatchLengthMismatch
+        );
+        let oracle = ctx.accounts.oracle.key();
+        let now = Clock::get()?.unix_timestamp;
+        let mut updated = 0;
         
-        require!(!position.is_settled, TradingError::AlreadySettled);
-        require!(clock.unix_timestamp >= position.window_end_time, TradingError::WindowNotEnded);
-        
-        let momentum_change = pool.current_momentum_index as i16 - position.entry_momentum_index as i16;
-        let mut payout = 0u64;
-        
-        match position.position_type {
-            PositionType::Long => {
-                if momentum_change > 0 {
-                    // Long position wins if momentum increased
-                    let profit_multiplier = momentum_change.abs() as u64;
-                    payout = position.amount + (position.amount * profit_multiplier / 100);
-                    // Apply 2% fee on profits
-                    let fee = (payout - position.amount) * 2 / 100;
-                    payout -= fee;
+        for (pool_info, &new_index) in ctx.remaining_accounts.iter().zip(new_indices.iter()) {
+            let mut pool: Account<MomentumPool> = Account::try_from(pool_info)?;
+            if pool.check_oracle(&oracle).is_err()
+                || pool.status == PoolStatus::Finalized
+                || pool.pending_commitment.is_some()
+                || pool.config.update_quorum > 1
+                || pool.oracle_source != OracleSource::Signer
+            {
+                msg!("Skipping pool {}", pool.match_id);
+                continue;
+            }
+            require!(new_index as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+            match pool.check_circuit_breaker(new_index, now) {
+                Ok(true) => {}
+                Ok(false) => {
+                    // Persist the halt
+                    pool.exit(ctx.program_id)?;
+                    continue;
                 }
-            },
-            PositionType::Short => {
-                if momentum_change < 0 {
-                    // Short position wins if momentum decreased
-                    let profit_multiplier = momentum_change.abs() as u64;
-                    payout = position.amount + (position.amount * profit_multiplier / 100);
-                    // Apply 2% fee on profits
-                    let fee = (payout - position.amount) * 2 / 100;
-                    payout -= fee;
+                Err(_) => {
+                    msg!("Skipping pool {}", pool.match_id);
+                    continue;
                 }
             }
+            
+            let old_index = pool.apply_momentum_update(new_index, now, None);
+            pool.exit(ctx.program_id)?;
+            updated += 1;
+            
+            emit!(MomentumUpdateEvent {
+                match_id: pool.match_id.clone(),
+                old_index,
+                new_index,
+                timestamp: now,
+                confidence: pool.oracle_confidence,
+            });
         }
         
+        msg!("Batch momentum update applied to {} pools", updated);
+        Ok(updated)
+    }
+
+    /// Settle a position after window ends. With `compound_window` set, the
+    /// trader lets it ride: the payout stays in the vault as the stake of a
+    /// new position (passed as `compound_position`) on the same side for
+    /// that many seconds, opened at the current index. With `swap_min_out`
+    /// set on a swap-enabled pool, the vault-funded part of the payout is
+    /// swapped through the configured AMM into `stable_token_account`,
+    /// failing unless at least that much arrives; any insurance top-up is
+    /// still paid in collateral.
+    ///
+    /// Settling is idempotent: when the position is already settled the
+    /// call succeeds without touching any state, so a client racing two
+    /// settles or retrying after a dropped confirmation sees success either
+    /// way. The exception is a compounding retry, which fails with
+    /// AlreadySettled rather than leave an empty follow-on position behind.
+    pub fn settle_position(
+        ctx: Context<SettlePosition>,
+        compound_window: Option<i64>,
+        swap_min_out: Option<u64>,
+    ) -> Result<()> {
+        let position = &ctx.accounts.trading_position;
+        if position.is_settled {
+            require!(ctx.accounts.compound_position.is_none(), TradingError::AlreadySettled);
+            msg!("Position already settled at {}; nothing to do", position.settled_at);
+            return Ok(());
+        }
+        ctx.accounts.settle(&ctx.bumps, compound_window, swap_min_out)
+    }
+
+    /// Settle a position abandoned long after its window ended, so keepers can
+    /// clean up losers whose owners have no reason to settle. Winnings still
+    /// go to the payout recipient; with `close_account` the position account
+    /// is closed and its rent returned to the trader, unless it holds an
+    /// escrowed payout, which is claimed from it later.
+    pub fn force_settle_expired(ctx: Context<ForceSettleExpired>, close_account: bool) -> Result<()> {
+        let settle = &mut ctx.accounts.settle;
+        let delay = settle.momentum_pool.config.force_settle_delay;
+
+        require!(delay > 0, TradingError::PositionNotExpired);
+        require!(
+            Clock::get()?.unix_timestamp >= settle.trading_position.window_end_time + delay,
+            TradingError::PositionNotExpired
+        );
+
+        settle.settle(&ctx.bumps.settle, None, None)?;
+
+        if close_account && settle.trading_position.escrowed_payout == 0 {
+            settle.trading_position.close(ctx.accounts.trader.to_account_info())?;
+        } else if close_account {
+            msg!("Position kept open until its escrowed payout is claimed");
+        }
+
+        msg!("Expired position {} force-settled", settle.trading_position.key());
+        Ok(())
+    }
+
+    /// Settle someone else's position once its window has ended, earning the
+    /// pool's keeper reward: `keeper_reward_bps` of the position's stake,
+    /// paid by the pool out of its unreserved balance, never from what it
+    /// owes positions, fees, the winners' pot or seeded liquidity. Winners
+    /// are still paid in full; the reward exists so losing positions, which
+    /// no trader is motivated to settle, get cleared too.
+    pub fn crank_settle(ctx: Context<CrankSettle>) -> Result<()> {
+        let settle = &mut ctx.accounts.settle;
+        settle.settle(&ctx.bumps.settle, None, None)?;
+
+        let pool = &settle.momentum_pool;
+        settle.pool_token_account.reload()?;
+        let available = pool.unreserved_balance(settle.pool_token_account.amount);
+        let reward = mul_div(
+            settle.trading_position.amount,
+            pool.config.keeper_reward_bps as u64,
+            10_000,
+            RoundingMode::Truncate,
+        )?
+        .min(available);
+
+        if reward > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    settle.token_program.to_account_info(),
+                    Transfer {
+                        from: settle.pool_token_account.to_account_info(),
+                        to: ctx.accounts.keeper_token_account.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    &[&[
+                        b"momentum_pool",
+                        pool.match_id.as_bytes(),
+                        &[ctx.bumps.settle.momentum_pool],
+                    ]],
+                ),
+                reward,
+            )?;
+        }
+
+        msg!("Position {} cranked, keeper reward {}", settle.trading_position.key(), reward);
+
+        emit!(KeeperRewardEvent {
+            pool: pool.key(),
+            position: settle.trading_position.key(),
+            keeper: settle.payer.key(),
+            reward,
+        });
+
+        Ok(())
+    }
+
+    /// Close part of an open position at the current momentum index,
+    /// leaving the remainder open at its original entry index. Like
+    /// close_position_early this is only possible while the window is open,
+    /// and the closed portion pays the early-exit fee.
+    pub fn partial_close(ctx: Context<PartialClose>, close_amount: u64) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(!position.is_tokenized(), TradingError::PositionTokenized);
+        require!(
+            pool.settlement_mode == SettlementMode::FixedMultiplier,
+            TradingError::NotSupportedInPariMutuel
+        );
+        require!(position.pool_epoch == pool.epoch, TradingError::StalePosition);
+        require!(
+            !pool.is_claim_expired(position.window_end_time, now),
+            TradingError::ClaimDeadlinePassed
+        );
+        // Past the window end the exit index is the settlement index, not
+        // whatever the current index has drifted to
+        require!(pool.status != PoolStatus::Finalized, TradingError::WindowEnded);
+        require!(now < position.window_end_time, TradingError::WindowEnded);
+        require!(!pool.is_heartbeat_lost(now), TradingError::OracleHeartbeatLost);
+        require!(pool.pending_commitment.is_none(), TradingError::CommitmentPending);
+        require!(!position.is_leveraged(), TradingError::LeverageNotSupported);
+        require!(
+            close_amount > 0 && close_amount < position.amount,
+            TradingError::InvalidAmount
+        );
+
+        let (payout, _) = compute_payout(
+            &position.position_type,
+            position.entry_momentum_index,
+            pool.current_momentum_index,
+            close_amount,
+            &pool.payout_config(position),
+        )?;
+        let closed_liability = mul_div(
+            position.max_liability,
+            close_amount,
+            position.amount,
+            RoundingMode::Truncate,
+        )?;
+        let payout = apply_funding(payout, pool.funding_owed(position, close_amount), closed_liability);
+        let early_exit_fee = mul_div(
+            payout,
+            pool.config.early_exit_fee_bps as u64,
+            10_000,
+            pool.config.rounding_mode,
+        )?;
+        let payout = payout - early_exit_fee;
+
+        position.amount = position.amount.checked_sub(close_amount).ok_or(TradingError::MathOverflow)?;
+        position.record_pnl(payout, close_amount)?;
+        pool.collect_fee(early_exit_fee, 0)?;
+        pool.record_outcome(close_amount, payout + early_exit_fee)?;
+        pool.last_settlement_time = now;
+        pool.remove_volume(position.position_type, close_amount)?;
+        let remaining_liability = pool.max_liability(position)?;
+        pool.replace_liability(position.max_liability, remaining_liability)?;
+        position.max_liability = remaining_liability;
+
         if payout > 0 {
-            // Transfer winnings to user
             token::transfer(
                 CpiContext::new_with_signer(
                     ctx.accounts.token_program.to_account_info(),
@@ -202,177 +740,9669 @@ pub mod futstar_momentum_trading {
                 payout,
             )?;
         }
-        
-        position.is_settled = true;
-        position.pnl = payout as i64 - position.amount as i64;
-        position.exit_momentum_index = pool.current_momentum_index;
-        position.settled_at = clock.unix_timestamp;
-        
-        msg!("Position settled. PnL: {} SOL", position.pnl as f64 / 1e9 as f64);
-        
-        emit!(PositionSettledEvent {
+
+        msg!("Position partially closed: {} closed, {} remaining",
+             close_amount,
+             position.amount);
+
+        emit!(PositionPartiallyClosedEvent {
             trader: position.trader,
-            position_type: position.position_type.clone(),
-            pnl: position.pnl,
-            entry_index: position.entry_momentum_index,
-            exit_index: position.exit_momentum_index,
+            position_type: position.position_type,
+            close_amount,
+            payout,
+            remaining_amount: position.amount,
+            exit_index: pool.current_momentum_index,
+            early_exit_fee,
         });
-        
+
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-#[instruction(match_id: String)]
-pub struct InitializePool<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + MomentumPool::LEN,
-        seeds = [b"momentum_pool", match_id.as_bytes()],
-        bump
-    )]
-    pub momentum_pool: Account<'info, MomentumPool>,
-    
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    
-    pub system_program: Program<'info, System>,
-}
+    /// Create the program-wide insurance fund backing underfunded payouts,
+    /// under the protocol admin's authority
+    pub fn initialize_insurance_fund(
+        ctx: Context<InitializeInsuranceFund>,
+        max_draw_per_settlement: u64,
+    ) -> Result<()> {
+        let fund = &mut ctx.accounts.insurance_fund;
 
-#[derive(Accounts)]
-pub struct OpenPosition<'info> {
-    #[account(
-        init,
-        payer = user,
-        space = 8 + TradingPosition::LEN,
-        seeds = [
-            b"position",
-            momentum_pool.key().as_ref(),
-            user.key().as_ref(),
-            &Clock::get()?.unix_timestamp.to_le_bytes()
-        ],
-        bump
-    )]
-    pub trading_position: Account<'info, TradingPosition>,
-    
-    #[account(mut)]
-    pub momentum_pool: Account<'info, MomentumPool>,
-    
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub pool_token_account: Account<'info, TokenAccount>,
-    
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+        fund.authority = ctx.accounts.protocol_config.admin;
+        fund.vault = ctx.accounts.insurance_vault.key();
+        fund.max_draw_per_settlement = max_draw_per_settlement;
+        fund.total_deposited = 0;
+        fund.total_withdrawn = 0;
+        fund.total_drawn = 0;
+        fund.bump = ctx.bumps.insurance_fund;
 
-#[derive(Accounts)]
-pub struct UpdateMomentum<'info> {
-    #[account(mut)]
-    pub momentum_pool: Account<'info, MomentumPool>,
-    
-    pub oracle: Signer<'info>,
-}
+        msg!("Insurance fund initialized with vault: {}", fund.vault);
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct SettlePosition<'info> {
+    /// Deposit tokens into the insurance fund vault
+    pub fn deposit_insurance(ctx: Context<ManageInsurance>, amount: u64) -> Result<()> {
+        require!(amount > 0, TradingError::InvalidAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.authority_token_account.to_account_info(),
+                    to: ctx.accounts.insurance_vault.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        ctx.accounts.insurance_fund.record_deposit(amount)?;
+
+        msg!("Insurance fund deposit: {}", amount);
+        Ok(())
+    }
+
+    /// Withdraw tokens from the insurance fund vault
+    pub fn withdraw_insurance(ctx: Context<ManageInsurance>, amount: u64) -> Result<()> {
+        let fund = &mut ctx.accounts.insurance_fund;
+
+        require!(
+            amount > 0 && amount <= ctx.accounts.insurance_vault.amount,
+            TradingError::InvalidAmount
+        );
+
+        fund.record_withdrawal(amount)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.insurance_vault.to_account_info(),
+                    to: ctx.accounts.authority_token_account.to_account_info(),
+                    authority: fund.to_account_info(),
+                },
+                &[&[b"insurance_fund", &[fund.bump]]],
+            ),
+            amount,
+        )?;
+
+        msg!("Insurance fund withdrawal: {}", amount);
+        Ok(())
+    }
+
+    /// Move insurance funds into a pool's vault to recapitalize it, e.g. ahead
+    /// of settlements its vault can't cover. Only the fund's authority may
+    /// draw; settlement also draws automatically for individual shortfalls.
+    pub fn draw_insurance(ctx: Context<DrawInsurance>, amount: u64) -> Result<()> {
+        let fund = &mut ctx.accounts.insurance_fund;
+        let vault = &ctx.accounts.insurance_vault;
+
+        require!(amount > 0 && amount <= vault.amount, TradingError::InvalidAmount);
+
+        fund.record_draw(amount)?;
+        ctx.accounts.momentum_pool.record_insurance_draw(amount)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: vault.to_account_info(),
+                    to: ctx.accounts.pool_token_account.to_account_info(),
+                    authority: fund.to_account_info(),
+                },
+                &[&[b"insurance_fund", &[fund.bump]]],
+            ),
+            amount,
+        )?;
+
+        msg!("Insurance fund drew {} into pool {}", amount, ctx.accounts.momentum_pool.match_id);
+
+        emit!(InsurancePoolDrawEvent {
+            pool: ctx.accounts.momentum_pool.key(),
+            amount,
+            fund_balance: vault.amount - amount,
+        });
+
+        Ok(())
+    }
+
+    /// Upgrade a pool created under an older account layout to the current
+    /// one, reallocating space and filling new fields with defaults
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+        let pool_info = ctx.accounts.momentum_pool.to_account_info();
+
+        let migrated = {
+            let data = pool_info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == MomentumPool::DISCRIMINATOR,
+                TradingError::UnsupportedAccountVersion
+            );
+
+            // Newer fields are appended after the legacy ones, so a legacy
+            // account's zeroed tail reads back as version 0
+            let version = MomentumPool::try_deserialize(&mut &data[..])
+                .map(|pool| pool.version)
+                .unwrap_or(0);
+            require!(version <= POOL_VERSION, TradingError::UnsupportedAccountVersion);
+            if version == POOL_VERSION {
+                msg!("Pool already at version {}", POOL_VERSION);
+                return Ok(());
+            }
+
+            let legacy = MomentumPoolV0::deserialize(&mut &data[8..])?;
+            require_keys_eq!(
+                legacy.authority,
+                ctx.accounts.authority.key(),
+                TradingError::Unauthorized
+            );
+            MomentumPool::from(legacy)
+        };
+
+        let new_len = 8 + MomentumPool::space(
+            &migrated.match_id,
+            &migrated.home_team,
+            &migrated.away_team,
+        );
+        if pool_info.data_len() < new_len {
+            let required = Rent::get()?.minimum_balance(new_len);
+            let top_up = required.saturating_sub(pool_info.lamports());
+            if top_up > 0 {
+                system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        system_program::Transfer {
+                            from: ctx.accounts.authority.to_account_info(),
+                            to: pool_info.clone(),
+                        },
+                    ),
+                    top_up,
+                )?;
+            }
+            pool_info.realloc(new_len, true)?;
+        }
+
+        let mut data = pool_info.try_borrow_mut_data()?;
+        migrated.try_serialize(&mut &mut data[..])?;
+
+        msg!("Pool {} migrated to version {}", migrated.match_id, POOL_VERSION);
+        Ok(())
+    }
+
+    /// Re-enter a settled position into a fresh window at the current index,
+    /// reusing its account. The prior stake was paid out or forfeited at
+    /// settlement, so the full new stake (previous amount plus any top-up)
+    /// is transferred in again.
+    pub fn roll_position(
+        ctx: Context<RollPosition>,
+        window_duration: i64,
+        top_up_amount: u64,
+    ) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let clock = Clock::get()?;
+
+        require!(!ctx.accounts.protocol_config.trading_paused, TradingError::ProtocolPaused);
+        require!(pool.status == PoolStatus::Active, TradingError::PoolNotActive);
+        require!(
+            !pool.config.allowlist_enabled || ctx.accounts.allowlist_entry.is_some(),
+            TradingError::NotAllowlisted
+        );
+        require!(
+            position.is_settled && position.settled_at > 0,
+            TradingError::PositionNotSettled
+        );
+        // Its margin and leverage covered the window that settled
+        require!(!position.is_leveraged(), TradingError::LeverageNotSupported);
+
+        let amount = position.amount.checked_add(top_up_amount).ok_or(TradingError::MathOverflow)?;
+        require!(amount > 0, TradingError::InvalidAmount);
+        let window_end_time = window_end_time(clock.unix_timestamp, window_duration)?;
+        require!(
+            pool.protocol_limits.allows_window(window_duration),
+            TradingError::WindowDurationOutOfRange
+        );
+        // The settled stake left the pool's volume, so all of it re-enters
+        check_new_stake(pool, amount, amount, clock.unix_timestamp)?;
+        require!(pool.has_position_slot(), TradingError::MaxPositionsReached);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: ctx.accounts.pool_token_account.to_account_info(),
+                    authority: ctx.accounts.trader.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        position.amount = amount;
+        (position.entry_momentum_index, position.entry_spread) = pool.entry_index(&position.position_type);
+        position.exit_momentum_index = 0;
+        position.entry_time = clock.unix_timestamp;
+        position.window_end_time = window_end_time;
+        position.window_duration = window_duration;
+        position.checkpoint = None;
+        position.stop_loss_index = None;
+        position.take_profit_index = None;
+        // Its token was burned at settlement; the rolled position is the trader's
+        if position.is_tokenized() {
+            position.payout_recipient = position.trader;
+            position.position_mint = None;
+        }
+        position.is_settled = false;
+        position.pnl = 0;
+        position.settled_at = 0;
+        position.exit_snapshot_index = None;
+        position.opened_during_commit = pool.pending_commitment.is_some();
+        position.profit_fee_bps = pool.entry_fee_bps(&position.position_type);
+        position.index_scale_max = pool.index_scale_max;
+        position.max_liability = pool.max_liability(position)?;
+        position.entry_total_long_volume = pool.total_long_volume;
+        position.entry_total_short_volume = pool.total_short_volume;
+        position.pool_epoch = pool.epoch;
+        position.entry_funding_index = pool.funding_index(&position.position_type);
+
+        pool.add_volume(position.position_type, amount)?;
+        pool.replace_liability(0, position.max_liability)?;
+        pool.open_positions += 1;
+
+        if let Some(trader_positions) = ctx.accounts.trader_positions.as_mut() {
+            let bump = trader_positions.bump;
+            trader_positions.record_open(pool.key(), position.trader, position.key(), bump);
+        }
+
+        msg!("Position rolled: {} at momentum index {}",
+             amount,
+             position.entry_momentum_index);
+
+        emit!(PositionRolledEvent {
+            trader: position.trader,
+            position_type: position.position_type,
+            amount,
+            entry_index: position.entry_momentum_index,
+            window_end_time: position.window_end_time,
+        });
+
+        Ok(())
+    }
+
+    /// Record the index a position will settle against once its grace period
+    /// has passed, before further oracle updates make it unrecoverable. The
+    /// pool's history account, if passed, covers updates the pool's own
+    /// sample buffer no longer holds, and is required for TWAP pools.
+    pub fn snapshot_exit_index(ctx: Context<SnapshotExitIndex>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &ctx.accounts.momentum_pool;
+        let clock = Clock::get()?;
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(position.exit_snapshot_index.is_none(), TradingError::SnapshotAlreadyTaken);
+
+        let settlement_time = pool.settlement_time(position.window_end_time);
+        require!(clock.unix_timestamp > settlement_time, TradingError::InGracePeriod);
+
+        let history = ctx.accounts.momentum_history.as_ref().map(|h| h.load()).transpose()?;
+        let exit_index = pool
+            .settlement_index_at(settlement_time, history.as_deref())
+            .ok_or(TradingError::SnapshotUnavailable)?;
+        position.exit_snapshot_index = Some(exit_index);
+
+        msg!("Exit index snapshot: {}", exit_index);
+        Ok(())
+    }
+
+    /// Record an open position's mark-to-market at the current index for
+    /// dashboards. Marking only writes the mark fields and never moves funds;
+    /// `pnl` stays the realized value set at settlement.
+    pub fn mark_position(ctx: Context<MarkPosition>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &ctx.accounts.momentum_pool;
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+
+        position.mark_pnl = pool.unrealized_pnl(position)?;
+        position.mark_index = pool.current_momentum_index;
+        position.marked_at = Clock::get()?.unix_timestamp;
+
+        msg!("Position marked at index {}: {}", position.mark_index, position.mark_pnl);
+        Ok(())
+    }
+
+    /// Allow a trader to open positions in an allowlist-mode pool
+    pub fn add_to_allowlist(ctx: Context<AddToAllowlist>, trader: Pubkey) -> Result<()> {
+        let entry = &mut ctx.accounts.allowlist_entry;
+
+        entry.pool = ctx.accounts.momentum_pool.key();
+        entry.trader = trader;
+        entry.added_at = Clock::get()?.unix_timestamp;
+        entry.bump = ctx.bumps.allowlist_entry;
+
+        msg!("Trader {} allowlisted", trader);
+        Ok(())
+    }
+
+    /// Revoke a trader's allowlist entry, returning its rent to the authority
+    pub fn remove_from_allowlist(ctx: Context<RemoveFromAllowlist>) -> Result<()> {
+        msg!("Trader {} removed from allowlist", ctx.accounts.allowlist_entry.trader);
+        Ok(())
+    }
+
+    /// Move a pool through its lifecycle: Active <-> ClosingToNewPositions -> Closed,
+    /// or Finalized -> Closed.
+    /// Settlement keeps working in every state; only new positions are refused.
+    /// A league pool that closes leaves its registry, passed as `league_registry`.
+    pub fn set_pool_status(ctx: Context<SetPoolStatus>, status: PoolStatus) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+
+        require!(pool.can_transition_to(status), TradingError::InvalidStatusTransition);
+        require!(
+            pool.league_id == 0 || status != PoolStatus::Closed || ctx.accounts.league_registry.is_some(),
+            TradingError::InvalidLeague
+        );
+        change_pool_status(pool, status)?;
+        if status == PoolStatus::Closed {
+            if let Some(league) = ctx.accounts.league_registry.as_mut() {
+                league.record_closed(&pool.key());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Void the most recent momentum update, reverting to the prior index or
+    /// replacing it with `corrected_index`. Only allowed shortly after the
+    /// update and before anything has settled against it.
+    pub fn correct_momentum_index(
+        ctx: Context<UpdateMomentum>,
+        corrected_index: Option<u8>,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let clock = Clock::get()?;
+
+        pool.check_oracle(&ctx.accounts.oracle.key())?;
+        require!(pool.status != PoolStatus::Halted, TradingError::PoolHalted);
+        require!(pool.previous_update < pool.last_update, TradingError::CorrectionNotAllowed);
+        require!(
+            clock.unix_timestamp - pool.last_update <= MOMENTUM_CORRECTION_WINDOW,
+            TradingError::CorrectionNotAllowed
+        );
+        require!(
+            pool.last_settlement_time < pool.last_update,
+            TradingError::CorrectionNotAllowed
+        );
+
+        let voided_index = pool.current_momentum_index;
+        let voided_update = pool.last_update;
+        let new_index = match corrected_index {
+            Some(index) => {
+                require!(index as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+                index
+            }
+            None => {
+                // Revert to the prior update entirely, including its timestamp
+                pool.last_update = pool.previous_update;
+                pool.previous_momentum_index
+            }
+        };
+        pool.current_momentum_index = new_index;
+        pool.amend_latest_sample(corrected_index);
+        if let Some(history) = &ctx.accounts.momentum_history {
+            history.load_mut()?.amend_latest(voided_update, corrected_index);
+        }
+
+        // Any checkpoint tagged on the voided update follows it
+        for snapshot in pool.checkpoints.iter_mut() {
+            if snapshot.as_ref().is_some_and(|s| s.timestamp == voided_update) {
+                *snapshot = corrected_index.map(|index| CheckpointSnapshot {
+                    momentum_index: index,
+                    timestamp: voided_update,
+                });
+            }
+        }
+
+        msg!("Momentum index corrected: {} -> {}", voided_index, new_index);
+
+        emit!(MomentumCorrectedEvent {
+            match_id: pool.match_id.clone(),
+            voided_index,
+            corrected_index: new_index,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Submit this oracle's view of the final index for a quorum pool. Any
+    /// enabled oracle may submit, and may resubmit until the pool finalizes.
+    pub fn submit_final_index(ctx: Context<UpdateMomentum>, final_index: u8) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+
+        require!(final_index as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+        require!(
+            pool.status != PoolStatus::Finalized && pool.status != PoolStatus::Closed,
+            TradingError::InvalidStatusTransition
+        );
+        let slot = pool.oracle_slot(&ctx.accounts.oracle.key())?;
+        check_oracle_stake(pool, ctx.accounts.oracle_stake.as_mut(), Clock::get()?.unix_timestamp)?;
+        pool.final_submissions[slot] = Some(FinalSubmission {
+            oracle: ctx.accounts.oracle.key(),
+            index: final_index,
+        });
+
+        msg!("Oracle {} submitted final index {}", ctx.accounts.oracle.key(), final_index);
+        Ok(())
+    }
+
+    /// Record the match's final momentum index. Every position still open
+    /// settles against it, regardless of when settlement lands.
+    pub fn finalize_pool(ctx: Context<UpdateMomentum>, final_index: u8) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let clock = Clock::get()?;
+
+        pool.check_oracle(&ctx.accounts.oracle.key())?;
+        check_oracle_stake(pool, ctx.accounts.oracle_stake.as_mut(), clock.unix_timestamp)?;
+        require!(final_index as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+        require!(
+            pool.status != PoolStatus::Finalized && pool.status != PoolStatus::Closed,
+            TradingError::InvalidStatusTransition
+        );
+        require!(pool.status != PoolStatus::Halted, TradingError::PoolHalted);
+        
+        // High-stakes pools settle on the median of agreeing oracles instead
+        // of the caller's value alone; the caller's value counts as its vote
+        let final_index = if pool.config.finalize_quorum > 1 {
+            let slot = pool.oracle_slot(&ctx.accounts.oracle.key())?;
+            pool.final_submissions[slot] = Some(FinalSubmission {
+                oracle: ctx.accounts.oracle.key(),
+                index: final_index,
+            });
+            pool.quorum_final_index()?
+        } else {
+            final_index
+        };
+
+        let old_status = pool.status;
+        pool.status = PoolStatus::Finalized;
+        pool.final_momentum_index = final_index;
+        pool.finalized_at = clock.unix_timestamp;
+        pool.final_long_volume = pool.total_long_volume;
+        pool.final_short_volume = pool.total_short_volume;
+
+        msg!("Pool {} finalized at momentum index {}", pool.match_id, final_index);
+
+        emit!(PoolStatusChangedEvent {
+            match_id: pool.match_id.clone(),
+            old_status,
+            new_status: PoolStatus::Finalized,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Move collateral the pool holds beyond what its open positions could
+    /// ever be owed (rounding dust, forfeited stakes) to the treasury. Seeded
+    /// liquidity belongs to the authority and is left alone, as are fees
+    /// awaiting withdraw_fees.
+    pub fn sweep_dust(ctx: Context<SweepDust>) -> Result<()> {
+        let pool = &ctx.accounts.momentum_pool;
+        let excess = pool.unreserved_balance(ctx.accounts.pool_token_account.amount);
+        require!(excess > 0, TradingError::NothingToSweep);
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_token_account.to_account_info(),
+                    to: ctx.accounts.treasury_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[
+                    b"momentum_pool",
+                    pool.match_id.as_bytes(),
+                    &[ctx.bumps.momentum_pool],
+                ]],
+            ),
+            excess,
+        )?;
+
+        msg!("Swept {} to treasury", excess);
+
+        emit!(DustSweptEvent {
+            pool: pool.key(),
+            amount: excess,
+            outstanding_liability: pool.outstanding_liability,
+        });
+
+        Ok(())
+    }
+
+    /// Collect up to `amount` of the pool's accrued fees into the treasury.
+    /// Fees the vault needs to cover outstanding liabilities stay put.
+    pub fn withdraw_fees(ctx: Context<SweepDust>, amount: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let withdrawable = pool.pending_fees().min(
+            ctx.accounts
+                .pool_token_account
+                .amount
+                .saturating_sub(pool.outstanding_liability),
+        );
+        require!(amount > 0 && amount <= withdrawable, TradingError::InvalidAmount);
+
+        pool.fees_withdrawn = pool.fees_withdrawn.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_token_account.to_account_info(),
+                    to: ctx.accounts.treasury_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[
+                    b"momentum_pool",
+                    pool.match_id.as_bytes(),
+                    &[ctx.bumps.momentum_pool],
+                ]],
+            ),
+            amount,
+        )?;
+
+        msg!("Withdrew {} in fees to treasury", amount);
+
+        emit!(FeesWithdrawnEvent {
+            pool: pool.key(),
+            amount,
+            fees_collected: pool.fees_collected,
+            fees_withdrawn: pool.fees_withdrawn,
+        });
+
+        Ok(())
+    }
+
+    /// Value the pool's collateral with `price_feed`, a Pyth price account
+    /// quoting it in USD (Pubkey::default() for stablecoin collateral). The
+    /// cached price is cleared until the next refresh_collateral_price.
+    pub fn set_price_feed(ctx: Context<PoolAdmin>, price_feed: Pubkey) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        pool.price_feed = price_feed;
+        pool.collateral_price = 0;
+        pool.collateral_price_expo = 0;
+        pool.collateral_price_time = 0;
+
+        msg!("Collateral price feed set: {}", price_feed);
+        Ok(())
+    }
+
+    /// Set the key whose off-chain signatures are accepted for relayed momentum
+    /// updates (Pubkey::default() disables relaying)
+    pub fn set_oracle_pubkey(ctx: Context<PoolAdmin>, oracle_pubkey: Pubkey) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        pool.oracle_pubkey = oracle_pubkey;
+        // A new signing service numbers its payloads afresh
+        pool.oracle_sequence = 0;
+
+        msg!("Oracle signing key set: {}", oracle_pubkey);
+        Ok(())
+    }
+
+    /// Return tokens sent to a pool-owned account by mistake. Collateral is
+    /// never recoverable this way: neither the primary collateral mint nor any
+    /// account sitting at a collateral vault address may be swept.
+    pub fn recover_tokens(ctx: Context<RecoverTokens>) -> Result<()> {
+        let pool = &ctx.accounts.momentum_pool;
+        let source = &ctx.accounts.source_token_account;
+
+        require_keys_neq!(source.mint, pool.collateral_mint, TradingError::CollateralMintProtected);
+        let (vault, _) = Pubkey::find_program_address(
+            &[b"vault", pool.key().as_ref(), source.mint.as_ref()],
+            ctx.program_id,
+        );
+        require_keys_neq!(source.key(), vault, TradingError::CollateralMintProtected);
+        require!(source.amount > 0, TradingError::NothingToSweep);
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: source.to_account_info(),
+                    to: ctx.accounts.recipient_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[
+                    b"momentum_pool",
+                    pool.match_id.as_bytes(),
+                    &[ctx.bumps.momentum_pool],
+                ]],
+            ),
+            source.amount,
+        )?;
+
+        msg!("Recovered {} of mint {}", source.amount, source.mint);
+        Ok(())
+    }
+
+    /// Deposit authority collateral into the pool vault to back payouts. It is
+    /// tracked apart from trader stakes so it can be withdrawn with defund_pool.
+    pub fn fund_pool(ctx: Context<FundPool>, amount: u64) -> Result<()> {
+        require!(amount > 0, TradingError::InvalidAmount);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.authority_token_account.to_account_info(),
+                    to: ctx.accounts.pool_token_account.to_account_info(),
+                    authority: ctx.accounts.authority.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let pool = &mut ctx.accounts.momentum_pool;
+        pool.seeded_liquidity = pool.seeded_liquidity.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+
+        msg!("Pool funded with {}; seeded liquidity {}", amount, pool.seeded_liquidity);
+        Ok(())
+    }
+
+    /// Withdraw seeded liquidity once every position has settled. Seed used
+    /// to cover payouts is gone, so at most the vault balance is returned.
+    pub fn defund_pool(ctx: Context<FundPool>, amount: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+
+        require!(pool.open_positions == 0, TradingError::PositionsStillOpen);
+        require!(
+            amount > 0 && amount <= pool.seeded_liquidity,
+            TradingError::InvalidAmount
+        );
+        require!(
+            amount <= ctx.accounts.pool_token_account.amount,
+            TradingError::InsufficientLiquidity
+        );
+
+        pool.seeded_liquidity = pool.seeded_liquidity.checked_sub(amount).ok_or(TradingError::MathOverflow)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_token_account.to_account_info(),
+                    to: ctx.accounts.authority_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[
+                    b"momentum_pool",
+                    pool.match_id.as_bytes(),
+                    &[ctx.bumps.momentum_pool],
+                ]],
+            ),
+            amount,
+        )?;
+
+        msg!("Pool defunded by {}; seeded liquidity {}", amount, pool.seeded_liquidity);
+        Ok(())
+    }
+
+    /// Hand the primary oracle slot, which the authority holds from pool
+    /// creation, to a dedicated feeder key so the authority can stay cold.
+    /// Any final index the replaced oracle submitted is discarded.
+    pub fn set_oracle(ctx: Context<PoolAdmin>, oracle: Pubkey) -> Result<()> {
+        require!(oracle != Pubkey::default(), TradingError::InvalidPoolConfig);
+        let pool = &mut ctx.accounts.momentum_pool;
+        let previous = pool.oracles[0].key;
+        pool.oracles[0] = OracleEntry {
+            key: oracle,
+            enabled: true,
+        };
+        pool.final_submissions[0] = None;
+        pool.oracle_readings[0] = None;
+
+        msg!("Primary oracle changed: {} -> {}", previous, oracle);
+        Ok(())
+    }
+
+    /// Register another oracle in the first free slot. Beyond the primary
+    /// and backup, registered oracles take over in slot order when those
+    /// are disabled, and contribute readings to aggregated updates.
+    pub fn add_oracle(ctx: Context<PoolAdmin>, oracle: Pubkey) -> Result<()> {
+        require!(oracle != Pubkey::default(), TradingError::InvalidPoolConfig);
+        let pool = &mut ctx.accounts.momentum_pool;
+        require!(
+            pool.oracles.iter().all(|entry| entry.key != oracle),
+            TradingError::InvalidPoolConfig
+        );
+        let slot = pool
+            .oracles
+            .iter()
+            .position(|entry| entry.key == Pubkey::default())
+            .ok_or(TradingError::OracleSlotsFull)?;
+        pool.oracles[slot] = OracleEntry {
+            key: oracle,
+            enabled: true,
+        };
+
+        msg!("Oracle {} registered in slot {}", oracle, slot);
+        Ok(())
+    }
+
+    /// Deregister an oracle, freeing its slot and discarding its readings.
+    /// The primary slot can only be reassigned, with set_oracle.
+    pub fn remove_oracle(ctx: Context<PoolAdmin>, oracle: Pubkey) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let slot = pool
+            .oracles
+            .iter()
+            .position(|entry| entry.key == oracle && oracle != Pubkey::default())
+            .ok_or(TradingError::UnauthorizedOracle)?;
+        require!(slot != 0, TradingError::InvalidPoolConfig);
+        pool.oracles[slot] = OracleEntry::default();
+        pool.final_submissions[slot] = None;
+        pool.oracle_readings[slot] = None;
+
+        msg!("Oracle {} removed from slot {}", oracle, slot);
+        Ok(())
+    }
+
+    /// Designate the oracle that takes over updates while the primary is
+    /// disabled. Any final index or reading the replaced backup submitted is
+    /// discarded.
+    pub fn set_backup_oracle(ctx: Context<PoolAdmin>, backup: Pubkey) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        pool.oracles[1] = OracleEntry {
+            key: backup,
+            enabled: true,
+        };
+        pool.final_submissions[1] = None;
+        pool.oracle_readings[1] = None;
+
+        msg!("Backup oracle set: {}", backup);
+        Ok(())
+    }
+
+    /// Stop accepting updates from one oracle without pausing the pool. If it
+    /// was feeding the index, the next enabled oracle takes over.
+    pub fn disable_oracle(ctx: Context<PoolAdmin>, oracle: Pubkey) -> Result<()> {
+        set_oracle_enabled(&mut ctx.accounts.momentum_pool, oracle, false)
+    }
+
+    /// Accept updates from a previously disabled oracle again
+    pub fn enable_oracle(ctx: Context<PoolAdmin>, oracle: Pubkey) -> Result<()> {
+        set_oracle_enabled(&mut ctx.accounts.momentum_pool, oracle, true)
+    }
+
+    /// Create the program-wide settings account, making the signer its admin
+    pub fn initialize_protocol(
+        ctx: Context<InitializeProtocol>,
+        max_profit_fee_bps: u16,
+        limits: ProtocolLimits,
+    ) -> Result<()> {
+        require!(max_profit_fee_bps <= 10_000, TradingError::InvalidPoolConfig);
+        limits.validate(max_profit_fee_bps)?;
+        let protocol_config = &mut ctx.accounts.protocol_config;
+
+        protocol_config.admin = ctx.accounts.admin.key();
+        protocol_config.max_profit_fee_bps = max_profit_fee_bps;
+        protocol_config.bump = ctx.bumps.protocol_config;
+        protocol_config.limits = limits;
+        protocol_config.trading_paused = false;
+        protocol_config.settlement_paused = false;
+        protocol_config.stake_mint = Pubkey::default();
+        protocol_config.fee_tiers = Default::default();
+        protocol_config.oracle_slashing = OracleSlashing::default();
+
+        msg!("Protocol initialized with fee ceiling {} bps", max_profit_fee_bps);
+        Ok(())
+    }
+
+    /// Change the profit fee ceiling every pool must stay under. Existing
+    /// pools keep their configuration; the ceiling applies when fees are set.
+    pub fn set_fee_ceiling(ctx: Context<ProtocolAdmin>, max_profit_fee_bps: u16) -> Result<()> {
+        require!(max_profit_fee_bps <= 10_000, TradingError::InvalidPoolConfig);
+        require!(
+            ctx.accounts.protocol_config.limits.profit_fee_bps <= max_profit_fee_bps,
+            TradingError::FeeExceedsCeiling
+        );
+        ctx.accounts.protocol_config.max_profit_fee_bps = max_profit_fee_bps;
+
+        msg!("Fee ceiling set to {} bps", max_profit_fee_bps);
+        Ok(())
+    }
+
+    /// Change the protocol-wide trading limits. Like the fee ceiling, they
+    /// reach each pool at its next config update.
+    pub fn set_protocol_limits(ctx: Context<ProtocolAdmin>, limits: ProtocolLimits) -> Result<()> {
+        let protocol_config = &mut ctx.accounts.protocol_config;
+        limits.validate(protocol_config.max_profit_fee_bps)?;
+        protocol_config.limits = limits;
+
+        msg!("Protocol limits updated: {:?}", limits);
+        Ok(())
+    }
+
+    /// Pause or resume opening and settling positions across every pool
+    pub fn set_protocol_paused(
+        ctx: Context<ProtocolAdmin>,
+        trading_paused: bool,
+        settlement_paused: bool,
+    ) -> Result<()> {
+        let protocol_config = &mut ctx.accounts.protocol_config;
+        protocol_config.trading_paused = trading_paused;
+        protocol_config.settlement_paused = settlement_paused;
+
+        msg!("Protocol paused: trading {}, settlement {}", trading_paused, settlement_paused);
+
+        emit!(ProtocolPauseEvent {
+            trading_paused,
+            settlement_paused,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Set the token traders stake for fee discounts and the discount each
+    /// staked balance earns. A default `stake_mint` turns discounts off.
+    pub fn set_fee_tiers(
+        ctx: Context<ProtocolAdmin>,
+        stake_mint: Pubkey,
+        fee_tiers: [FeeTier; MAX_FEE_TIERS],
+    ) -> Result<()> {
+        for tier in fee_tiers.iter() {
+            require!(tier.discount_bps <= 10_000, TradingError::InvalidPoolConfig);
+        }
+        let protocol_config = &mut ctx.accounts.protocol_config;
+        protocol_config.stake_mint = stake_mint;
+        protocol_config.fee_tiers = fee_tiers;
+
+        msg!("Fee tiers set for stake mint {}: {:?}", stake_mint, fee_tiers);
+        Ok(())
+    }
+
+    /// Stake the protocol token to earn a profit fee discount at settlement.
+    /// Adding to a stake restarts its lockup.
+    pub fn stake_tokens(ctx: Context<StakeTokens>, amount: u64) -> Result<()> {
+        require!(amount > 0, TradingError::InvalidAmount);
+        let stake_account = &mut ctx.accounts.stake_account;
+        let stake_mint = ctx.accounts.stake_mint.key();
+        let clock = Clock::get()?;
+
+        // Freshly created by init_if_needed
+        if stake_account.owner == Pubkey::default() {
+            stake_account.owner = ctx.accounts.owner.key();
+            stake_account.bump = ctx.bumps.stake_account;
+        }
+        // A stake left in a previous stake mint must be withdrawn first
+        require!(
+            stake_account.amount == 0 || stake_account.mint == stake_mint,
+            TradingError::InvalidStakeMint
+        );
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.owner_token_account.to_account_info(),
+                    to: ctx.accounts.stake_vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        stake_account.mint = stake_mint;
+        stake_account.amount = stake_account
+            .amount
+            .checked_add(amount)
+            .ok_or(TradingError::MathOverflow)?;
+        stake_account.staked_at = clock.unix_timestamp;
+
+        msg!("Staked {} (balance {})", amount, stake_account.amount);
+
+        emit!(StakeUpdatedEvent {
+            owner: stake_account.owner,
+            mint: stake_mint,
+            delta: amount as i64,
+            balance: stake_account.amount,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw staked tokens once the lockup has passed
+    pub fn unstake_tokens(ctx: Context<UnstakeTokens>, amount: u64) -> Result<()> {
+        let stake_account = &mut ctx.accounts.stake_account;
+        let clock = Clock::get()?;
+
+        require!(amount > 0, TradingError::InvalidAmount);
+        require!(amount <= stake_account.amount, TradingError::InsufficientStake);
+        require!(
+            clock.unix_timestamp >= stake_account.staked_at + STAKE_LOCKUP_SECONDS,
+            TradingError::StakeLocked
+        );
+
+        stake_account.amount = stake_account.amount.checked_sub(amount).ok_or(TradingError::MathOverflow)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.stake_vault.to_account_info(),
+                    to: ctx.accounts.owner_token_account.to_account_info(),
+                    authority: ctx.accounts.protocol_config.to_account_info(),
+                },
+                &[&[b"protocol_config", &[ctx.accounts.protocol_config.bump]]],
+            ),
+            amount,
+        )?;
+
+        msg!("Unstaked {} (balance {})", amount, stake_account.amount);
+
+        emit!(StakeUpdatedEvent {
+            owner: stake_account.owner,
+            mint: stake_account.mint,
+            delta: -(amount as i64),
+            balance: stake_account.amount,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Set how much of a disputed oracle's stake is slashed and where it goes
+    pub fn set_oracle_slashing(ctx: Context<ProtocolAdmin>, oracle_slashing: OracleSlashing) -> Result<()> {
+        oracle_slashing.validate()?;
+        ctx.accounts.protocol_config.oracle_slashing = oracle_slashing;
+
+        msg!("Oracle slashing set: {:?}", oracle_slashing);
+        Ok(())
+    }
+
+    /// Bond stake in the protocol's stake mint as an oracle operator, for
+    /// pools that require one to publish
+    pub fn stake_oracle(ctx: Context<StakeOracle>, amount: u64) -> Result<()> {
+        require!(amount > 0, TradingError::InvalidAmount);
+        let oracle_stake = &mut ctx.accounts.oracle_stake;
+        let stake_mint = ctx.accounts.stake_mint.key();
+
+        // Freshly created by init_if_needed
+        if oracle_stake.oracle == Pubkey::default() {
+            oracle_stake.oracle = ctx.accounts.oracle.key();
+            oracle_stake.bump = ctx.bumps.oracle_stake;
+        }
+        require!(
+            oracle_stake.amount == 0 || oracle_stake.mint == stake_mint,
+            TradingError::InvalidStakeMint
+        );
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.oracle_token_account.to_account_info(),
+                    to: ctx.accounts.oracle_stake_vault.to_account_info(),
+                    authority: ctx.accounts.oracle.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        oracle_stake.mint = stake_mint;
+        oracle_stake.amount = oracle_stake
+            .amount
+            .checked_add(amount)
+            .ok_or(TradingError::MathOverflow)?;
+
+        msg!("Oracle staked {} (balance {})", amount, oracle_stake.amount);
+        Ok(())
+    }
+
+    /// Withdraw an oracle's stake once it has gone ORACLE_UNBONDING_SECONDS
+    /// without publishing
+    pub fn unstake_oracle(ctx: Context<UnstakeOracle>, amount: u64) -> Result<()> {
+        let oracle_stake = &mut ctx.accounts.oracle_stake;
+
+        require!(amount > 0, TradingError::InvalidAmount);
+        require!(amount <= oracle_stake.amount, TradingError::InsufficientStake);
+        require!(
+            Clock::get()?.unix_timestamp >= oracle_stake.last_published_at + ORACLE_UNBONDING_SECONDS,
+            TradingError::StakeLocked
+        );
+
+        oracle_stake.amount = oracle_stake.amount.checked_sub(amount).ok_or(TradingError::MathOverflow)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.oracle_stake_vault.to_account_info(),
+                    to: ctx.accounts.oracle_token_account.to_account_info(),
+                    authority: ctx.accounts.protocol_config.to_account_info(),
+                },
+                &[&[b"protocol_config", &[ctx.accounts.protocol_config.bump]]],
+            ),
+            amount,
+        )?;
+
+        msg!("Oracle unstaked {} (balance {})", amount, oracle_stake.amount);
+        Ok(())
+    }
+
+    /// Dispute an oracle's final index submission on a finalized pool. If it
+    /// strayed from the finalized index by more than the pool's quorum
+    /// tolerance, the protocol's slash share of the oracle's stake is split
+    /// between the disputer and the insurance fund, and the rest burned. Each
+    /// submission can be slashed once.
+    ///
+    /// Stakes are bonded protocol-wide while anyone can create a pool, so
+    /// only pools run by the protocol admin can slash: elsewhere the pool
+    /// authority decides the final index and could fabricate a dispute.
+    pub fn slash_oracle(ctx: Context<SlashOracle>) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let oracle_stake = &mut ctx.accounts.oracle_stake;
+        let slashing = ctx.accounts.protocol_config.oracle_slashing;
+
+        require!(slashing.slash_bps > 0, TradingError::SlashingDisabled);
+        require_keys_eq!(pool.authority, ctx.accounts.protocol_config.admin, TradingError::NotSlashable);
+        require!(pool.status == PoolStatus::Finalized, TradingError::NotSlashable);
+        // Match on the key that signed the submission, not whoever holds the
+        // slot now
+        let slot = pool
+            .final_submissions
+            .iter()
+            .position(|submission| submission.is_some_and(|s| s.oracle == oracle_stake.oracle))
+            .ok_or(TradingError::NotSlashable)?;
+        let submission = pool.final_submissions[slot].ok_or(TradingError::NotSlashable)?.index;
+        let deviation = submission.abs_diff(pool.final_momentum_index);
+        require!(deviation > pool.config.quorum_tolerance, TradingError::NotSlashable);
+
+        let slashed = mul_div(oracle_stake.amount, slashing.slash_bps as u64, 10_000, RoundingMode::Truncate)?;
+        let to_disputer = mul_div(slashed, slashing.disputer_share_bps as u64, 10_000, RoundingMode::Truncate)?;
+        // The fund only takes its share in its own mint; otherwise it's burned
+        let to_insurance = if ctx.accounts.insurance_vault.mint == oracle_stake.mint {
+            mul_div(slashed, slashing.insurance_share_bps as u64, 10_000, RoundingMode::Truncate)?
+        } else {
+            0
+        };
+        let burned = slashed - to_disputer - to_insurance;
+
+        pool.final_submissions[slot] = None;
+        oracle_stake.amount = oracle_stake.amount.checked_sub(slashed).ok_or(TradingError::MathOverflow)?;
+        oracle_stake.total_slashed = oracle_stake
+            .total_slashed
+            .checked_add(slashed)
+            .ok_or(TradingError::MathOverflow)?;
+        ctx.accounts.insurance_fund.record_deposit(to_insurance)?;
+
+        let signer_seeds: &[&[u8]] = &[b"protocol_config", &[ctx.accounts.protocol_config.bump]];
+        for (to, amount) in [
+            (ctx.accounts.disputer_token_account.to_account_info(), to_disputer),
+            (ctx.accounts.insurance_vault.to_account_info(), to_insurance),
+        ] {
+            if amount > 0 {
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer {
+                            from: ctx.accounts.oracle_stake_vault.to_account_info(),
+                            to,
+                            authority: ctx.accounts.protocol_config.to_account_info(),
+                        },
+                        &[signer_seeds],
+                    ),
+                    amount,
+                )?;
+            }
+        }
+        if burned > 0 {
+            token::burn(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: ctx.accounts.stake_mint.to_account_info(),
+                        from: ctx.accounts.oracle_stake_vault.to_account_info(),
+                        authority: ctx.accounts.protocol_config.to_account_info(),
+                    },
+                    &[signer_seeds],
+                ),
+                burned,
+            )?;
+        }
+
+        msg!(
+            "Oracle {} slashed {}: submitted {} vs final {}",
+            oracle_stake.oracle,
+            slashed,
+            submission,
+            pool.final_momentum_index
+        );
+
+        emit!(OracleSlashedEvent {
+            pool: pool.key(),
+            oracle: oracle_stake.oracle,
+            disputer: ctx.accounts.disputer.key(),
+            submitted_index: submission,
+            final_index: pool.final_momentum_index,
+            slashed,
+            disputer_reward: to_disputer,
+            insurance_amount: to_insurance,
+            burned,
+        });
+
+        Ok(())
+    }
+
+    /// Return a Paper pool to a fresh state for another demo run: volumes and
+    /// liabilities cleared, the index back at neutral and trading reopened.
+    /// Positions from before the reset can no longer settle. A pool's kind is
+    /// fixed at creation, so Live pools can never be reset.
+    pub fn reset_pool(ctx: Context<PoolAdmin>) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let clock = Clock::get()?;
+
+        require!(pool.kind == PoolKind::Paper, TradingError::LivePoolNotResettable);
+
+        let old_status = pool.status;
+        pool.epoch += 1;
+        pool.status = PoolStatus::Active;
+        pool.total_long_volume = 0;
+        pool.total_short_volume = 0;
+        pool.total_straddle_volume = 0;
+        pool.outstanding_liability = 0;
+        pool.open_positions = 0;
+        pool.pending_commitment = None;
+        pool.checkpoints = Default::default();
+        pool.final_momentum_index = 0;
+        pool.finalized_at = 0;
+        pool.final_long_volume = 0;
+        pool.final_short_volume = 0;
+        pool.final_submissions = [None; MAX_ORACLES];
+        pool.oracle_readings = [None; MAX_ORACLES];
+        pool.current_momentum_index = (pool.index_scale_max / 2) as u8;
+        pool.previous_momentum_index = pool.current_momentum_index;
+        pool.last_update = clock.unix_timestamp;
+        pool.previous_update = clock.unix_timestamp;
+        pool.sample_count = 0;
+        let neutral_index = pool.current_momentum_index;
+        pool.record_sample(neutral_index, clock.unix_timestamp);
+
+        msg!("Paper pool {} reset (epoch {})", pool.match_id, pool.epoch);
+
+        emit!(PoolStatusChangedEvent {
+            match_id: pool.match_id.clone(),
+            old_status,
+            new_status: PoolStatus::Active,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Pay out an escrowed settlement once its timelock has passed, unless the
+    /// authority has put it on hold
+    pub fn claim_payout(ctx: Context<ClaimPayout>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let amount = position.escrowed_payout;
+
+        require!(amount > 0, TradingError::NothingToClaim);
+        require!(!position.payout_held, TradingError::PayoutHeld);
+        require!(
+            Clock::get()?.unix_timestamp >= position.claimable_at,
+            TradingError::PayoutLocked
+        );
+        require!(
+            ctx.accounts.pool_token_account.amount >= amount,
+            TradingError::InsufficientLiquidity
+        );
+
+        position.escrowed_payout = 0;
+        pool.replace_liability(amount, 0)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_token_account.to_account_info(),
+                    to: ctx.accounts.user_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[
+                    b"momentum_pool",
+                    pool.match_id.as_bytes(),
+                    &[ctx.bumps.momentum_pool],
+                ]],
+            ),
+            amount,
+        )?;
+
+        msg!("Escrowed payout claimed: {}", amount);
+        Ok(())
+    }
+
+    /// Freeze or release an escrowed payout while a suspected oracle error is
+    /// investigated
+    pub fn set_payout_hold(ctx: Context<PayoutHold>, held: bool) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        require!(position.escrowed_payout > 0, TradingError::NothingToClaim);
+        position.payout_held = held;
+
+        msg!("Payout hold for {} set to {}", position.key(), held);
+        Ok(())
+    }
+
+    /// Sum what the pool could owe the positions passed as remaining accounts,
+    /// both at the current index and in the worst case, against the vault
+    /// balance. Escrowed payouts awaiting a claim count in full. Only positions
+    /// in the vault's mint are counted.
+    pub fn report_solvency<'info>(ctx: Context<'_, '_, 'info, 'info, ReportSolvency<'info>>) -> Result<()> {
+        let pool = &ctx.accounts.momentum_pool;
+        let vault = &ctx.accounts.pool_token_account;
+        let mut positions = 0u32;
+        let mut current_liability = 0u64;
+        let mut max_liability = 0u64;
+
+        for position_info in ctx.remaining_accounts.iter() {
+            let position: Account<TradingPosition> = Account::try_from(position_info)?;
+            require_keys_eq!(position.pool, pool.key(), TradingError::PositionPoolMismatch);
+            if position.collateral_mint != vault.mint {
+                continue;
+            }
+            let (current, max) = if position.is_settled {
+                (position.escrowed_payout, position.escrowed_payout)
+            } else {
+                let current = (position.stake() as i64 + pool.unrealized_pnl(&position)?).max(0) as u64;
+                (current, pool.max_liability(&position)?)
+            };
+            current_liability = current_liability
+                .checked_add(current)
+                .ok_or(TradingError::MathOverflow)?;
+            max_liability = max_liability.checked_add(max).ok_or(TradingError::MathOverflow)?;
+            positions += 1;
+        }
+
+        let coverage_bps = if max_liability == 0 {
+            u64::MAX
+        } else {
+            mul_div(vault.amount, 10_000, max_liability, RoundingMode::Truncate).unwrap_or(u64::MAX)
+        };
+
+        msg!(
+            "Solvency: vault {} vs max liability {} ({} bps)",
+            vault.amount,
+            max_liability,
+            coverage_bps
+        );
+
+        emit!(SolvencyReportEvent {
+            pool: pool.key(),
+            positions,
+            vault_balance: vault.amount,
+            current_liability,
+            max_liability,
+            coverage_bps,
+            winners_pot: pool.winners_pot,
+        });
+
+        Ok(())
+    }
+
+    /// Bring a position's window end forward so it can settle sooner. The new
+    /// end must be far enough ahead that the exit index is still unknown, and
+    /// not before the minimum hold; windows can never be extended this way. The
+    /// entry bonus shrinks with the shorter commitment.
+    pub fn shorten_window(ctx: Context<ShortenWindow>, new_window_end_time: i64) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(position.checkpoint.is_none(), TradingError::InvalidWindow);
+        require!(new_window_end_time < position.window_end_time, TradingError::InvalidWindow);
+        require!(pool.is_unknown_exit(new_window_end_time, now), TradingError::InvalidWindow);
+        require!(new_window_end_time > position.entry_time, TradingError::InvalidTimestamp);
+        require!(
+            new_window_end_time >= position.entry_time + pool.config.min_hold_seconds,
+            TradingError::MinHoldNotMet
+        );
+
+        let old_window_end_time = position.window_end_time;
+        position.window_end_time = new_window_end_time;
+        position.window_duration = new_window_end_time - position.entry_time;
+        let max_liability = pool.max_liability(position)?;
+        pool.replace_liability(position.max_liability, max_liability)?;
+        position.max_liability = max_liability;
+
+        msg!("Window shortened: {} -> {}", old_window_end_time, new_window_end_time);
+
+        emit!(WindowShortenedEvent {
+            trader: position.trader,
+            position: position.key(),
+            old_window_end_time,
+            new_window_end_time,
+        });
+
+        Ok(())
+    }
+
+    /// Replace the pool's trading parameters. Identity (match, teams,
+    /// collateral) is not part of the config and can't change. Open positions
+    /// keep the fee and index scale they were opened under; everything else
+    /// applies from now on.
+    pub fn update_pool_config(ctx: Context<UpdatePoolConfig>, config: PoolConfig) -> Result<()> {
+        let protocol_config = &ctx.accounts.protocol_config;
+        config.validate(&protocol_config.limits)?;
+        require!(
+            config.max_profit_fee_bps(&protocol_config.limits) <= protocol_config.max_profit_fee_bps as u64,
+            TradingError::FeeExceedsCeiling
+        );
+
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+        let old_config = std::mem::replace(&mut pool.config, config);
+        pool.protocol_limits = ctx.accounts.protocol_config.limits;
+        // Funding starts accruing when enabled, not back-dated to the last accrual
+        if old_config.funding_rate_bps == 0 && pool.config.funding_rate_bps > 0 {
+            pool.last_funding_time = now;
+        }
+
+        msg!("Config updated for pool {}", pool.match_id);
+
+        emit!(ConfigUpdatedEvent {
+            pool: pool.key(),
+            old_config,
+            new_config: pool.config.clone(),
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    /// Create the registry grouping an operator's pools for a league or
+    /// tournament. Pools join by passing it to initialize_pool.
+    pub fn initialize_league(ctx: Context<InitializeLeague>, league_id: u64) -> Result<()> {
+        require!(league_id != 0, TradingError::InvalidLeague);
+
+        let league = &mut ctx.accounts.league_registry;
+        league.authority = ctx.accounts.authority.key();
+        league.league_id = league_id;
+        league.pools = Vec::new();
+        league.total_pools = 0;
+        league.closed_pools = 0;
+        league.bump = ctx.bumps.league_registry;
+
+        msg!("League {} initialized", league_id);
+        Ok(())
+    }
+
+    /// Move every member pool passed as remaining accounts to `status`, e.g.
+    /// ClosingToNewPositions to pause a whole tournament. Pools for which the
+    /// transition isn't allowed are skipped. Returns how many changed.
+    pub fn set_league_status<'info>(
+        ctx: Context<'_, '_, 'info, 'info, LeagueAdmin<'info>>,
+        status: PoolStatus,
+    ) -> Result<u32> {
+        let league = &mut ctx.accounts.league_registry;
+        let mut changed = 0;
+
+        for pool_info in ctx.remaining_accounts.iter() {
+            let mut pool: Account<MomentumPool> = Account::try_from(pool_info)?;
+            require!(league.pools.contains(&pool.key()), TradingError::InvalidLeague);
+            if !pool.can_transition_to(status) {
+                msg!("Skipping pool {}", pool.match_id);
+                continue;
+            }
+            change_pool_status(&mut pool, status)?;
+            pool.exit(ctx.program_id)?;
+            if status == PoolStatus::Closed {
+                league.record_closed(&pool.key());
+            }
+            changed += 1;
+        }
+
+        msg!("League {} status set to {:?} on {} pools", league.league_id, status, changed);
+        Ok(changed)
+    }
+
+    /// Sum open interest and open positions across the member pools passed as
+    /// remaining accounts
+    pub fn report_league<'info>(ctx: Context<'_, '_, 'info, 'info, ReportLeague<'info>>) -> Result<()> {
+        let league = &ctx.accounts.league_registry;
+        let mut pools = 0u32;
+        let mut total_long_volume = 0u64;
+        let mut total_short_volume = 0u64;
+        let mut open_positions = 0u64;
+
+        for pool_info in ctx.remaining_accounts.iter() {
+            let pool: Account<MomentumPool> = Account::try_from(pool_info)?;
+            require!(league.pools.contains(&pool.key()), TradingError::InvalidLeague);
+            total_long_volume = total_long_volume
+                .checked_add(pool.total_long_volume)
+                .ok_or(TradingError::MathOverflow)?;
+            total_short_volume = total_short_volume
+                .checked_add(pool.total_short_volume)
+                .ok_or(TradingError::MathOverflow)?;
+            open_positions += pool.open_positions as u64;
+            pools += 1;
+        }
+
+        emit!(LeagueReportEvent {
+            league_id: league.league_id,
+            pools,
+            total_long_volume,
+            total_short_volume,
+            open_positions,
+        });
+
+        Ok(())
+    }
+
+    /// Exit a position before its window ends at the current momentum index,
+    /// paying the pool's early-exit fee out of the payout. The position is
+    /// settled and can't be settled again.
+    pub fn close_position_early(ctx: Context<ClosePositionEarly>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(!position.is_tokenized(), TradingError::PositionTokenized);
+
+        let exit_index = pool.current_momentum_index;
+        let (payout, early_exit_fee) = close_early(position, pool, exit_index, now)?;
+        if let Some(trader_positions) = ctx.accounts.trader_positions.as_mut() {
+            trader_positions.record_settled(&position.key());
+        }
+        if let Some(trader_stats) = ctx.accounts.trader_stats.as_mut() {
+            let bump = trader_stats.bump;
+            trader_stats.record_settlement(
+                position.trader,
+                position.amount,
+                payout as i64 - position.stake() as i64,
+                bump,
+            )?;
+        }
+
+        if payout > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.pool_token_account.to_account_info(),
+                        to: ctx.accounts.user_token_account.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    &[&[
+                        b"momentum_pool",
+                        pool.match_id.as_bytes(),
+                        &[ctx.bumps.momentum_pool],
+                    ]],
+                ),
+                payout,
+            )?;
+        }
+
+        msg!("Position closed early. PnL: {}", position.pnl);
+
+        emit!(PositionClosedEarlyEvent {
+            trader: position.trader,
+            position_type: position.position_type,
+            entry_index: position.entry_momentum_index,
+            exit_index,
+            amount: position.amount,
+            payout,
+            early_exit_fee,
+            pnl: position.pnl,
+        });
+
+        Ok(())
+    }
+
+    /// Settle many positions of one pool in a single transaction. Remaining
+    /// accounts are (position, payout token account, trader's stake PDA)
+    /// triples; positions that can't settle yet are skipped, and the batch
+    /// stops early when compute runs low. Returns how many settled.
+    ///
+    /// This is the lean path for mass clean-up: winnings come from the vault
+    /// only, and positions with a referrer, a shortfall the insurance fund
+    /// would cover, or a held payout are skipped for settle_position. Fees,
+    /// staker discounts and the insurance slice are as in settle_position.
+    /// The trader's position index and stats aren't updated.
+    pub fn settle_positions_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettlePositionsBatch<'info>>,
+    ) -> Result<u32> {
+        let protocol_config = &ctx.accounts.protocol_config;
+        require!(!protocol_config.settlement_paused, TradingError::ProtocolPaused);
+        let triples = ctx.remaining_accounts.chunks_exact(3);
+        require!(triples.remainder().is_empty(), TradingError::BatchLengthMismatch);
+        let pool = &mut ctx.accounts.momentum_pool;
+        let vault = &mut ctx.accounts.pool_token_account;
+        let insurance = ctx.accounts.insurance_fund.as_ref().zip(ctx.accounts.insurance_vault.as_ref());
+        let now = Clock::get()?.unix_timestamp;
+        let mut available = vault.amount;
+        let mut insurance_fees = 0u64;
+        let mut settled = 0;
+
+        for triple in triples {
+            if sol_remaining_compute_units() < SETTLE_BATCH_CU_RESERVE {
+                msg!("Compute budget low; stopping after {} positions", settled);
+                break;
+            }
+            let mut position: Account<TradingPosition> = Account::try_from(&triple[0])?;
+            let recipient: Account<TokenAccount> = Account::try_from(&triple[1])?;
+            require_keys_eq!(position.pool, pool.key(), TradingError::PositionPoolMismatch);
+            require_keys_eq!(recipient.mint, vault.mint, TradingError::InvalidTokenAccount);
+            require_keys_eq!(
+                recipient.owner,
+                position.payout_recipient,
+                TradingError::InvalidPayoutRecipient
+            );
+            if position.is_settled
+                || position.collateral_mint != vault.mint
+                || position.pool_epoch != pool.epoch
+                || position.referrer.is_some()
+                || position.is_tokenized()
+                || position.payout_held
+                || now - position.entry_time < pool.config.min_hold_seconds
+                || pool.is_oracle_stale(now)
+                || pool.status == PoolStatus::Halted
+            {
+                continue;
+            }
+            let Ok(exit_index) = pool.exit_index(&position, now, None) else {
+                continue;
+            };
+
+            // The trader's stake PDA must be passed, even if it was never
+            // created, so a keeper can't withhold a staker's discount
+            let (stake_key, _) =
+                Pubkey::find_program_address(&[b"stake", position.trader.as_ref()], ctx.program_id);
+            require_keys_eq!(triple[2].key(), stake_key, TradingError::InvalidStakeAccount);
+            let stake_account = if triple[2].owner == ctx.program_id {
+                Some(Account::<StakeAccount>::try_from(&triple[2])?)
+            } else {
+                None
+            };
+            let fee_discount_bps = protocol_config.fee_discount_bps(stake_account.as_deref());
+            let payout_config = pool.settlement_payout_config(&position, fee_discount_bps);
+            let (payout, fee) = position_payout(&position, exit_index, &payout_config)?;
+            let forfeited = pool.is_claim_expired(position.window_end_time, now);
+            let (payout, fee) = if forfeited { (0, 0) } else { (payout, fee) };
+            let full_payout = payout;
+            let payout = pool.solvent_payout(full_payout, available)?;
+            if payout < full_payout {
+                emit!(PayoutScaledEvent {
+                    pool: pool.key(),
+                    position: position.key(),
+                    full_payout,
+                    payout,
+                    outstanding_liability: pool.outstanding_liability,
+                    vault_balance: available,
+                });
+            }
+            let escrowed_payout = if pool.config.is_escrowed(payout) { payout } else { 0 };
+            let due_now = payout - escrowed_payout;
+            if due_now > available {
+                continue;
+            }
+            available -= due_now;
+            let insurance_fee = insurance_fee_share(
+                pool,
+                fee,
+                insurance,
+                vault.mint,
+                available.saturating_sub(escrowed_payout),
+            )?;
+            available -= insurance_fee;
+            insurance_fees = insurance_fees.checked_add(insurance_fee).ok_or(TradingError::MathOverflow)?;
+
+            position.is_settled = true;
+            let stake = position.stake();
+            position.record_pnl(payout, stake)?;
+            position.exit_momentum_index = exit_index;
+            position.settled_at = now;
+            pool.remove_volume(position.position_type, position.amount)?;
+            pool.replace_liability(position.max_liability, 0)?;
+            if escrowed_payout > 0 {
+                pool.replace_liability(0, escrowed_payout)?;
+                position.escrowed_payout = escrowed_payout;
+                position.claimable_at = now + pool.config.escrow_delay;
+            }
+            pool.open_positions = pool.open_positions.saturating_sub(1);
+            pool.collect_fee(fee, insurance_fee)?;
+            pool.record_outcome(position.stake(), payout + fee)?;
+            position.exit(ctx.program_id)?;
+
+            if due_now > 0 {
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer {
+                            from: vault.to_account_info(),
+                            to: recipient.to_account_info(),
+                            authority: pool.to_account_info(),
+                        },
+                        &[&[
+                            b"momentum_pool",
+                            pool.match_id.as_bytes(),
+                            &[ctx.bumps.momentum_pool],
+                        ]],
+                    ),
+                    due_now,
+                )?;
+            }
+            settled += 1;
+
+            emit!(PositionSettledEvent {
+                trader: position.trader,
+                position_type: position.position_type,
+                pnl: position.pnl,
+                entry_index: position.entry_momentum_index,
+                exit_index,
+                amount: position.amount,
+                gross_payout: payout + fee,
+                fee_paid: fee,
+                window_duration: position.window_duration,
+                client_tag: position.client_tag,
+                referrer: None,
+                referral_fee: 0,
+                stable_received: 0,
+                pnl_usd: pool.usd_value(position.pnl, now),
+            });
+        }
+
+        if settled > 0 {
+            pool.last_settlement_time = now;
+        }
+        // One transfer for the whole batch's insurance slice
+        if let Some((fund, insurance_vault)) = ctx.accounts.insurance_fund.as_mut().zip(ctx.accounts.insurance_vault.as_ref()) {
+            if insurance_fees > 0 {
+                fund.record_deposit(insurance_fees)?;
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer {
+                            from: vault.to_account_info(),
+                            to: insurance_vault.to_account_info(),
+                            authority: pool.to_account_info(),
+                        },
+                        &[&[
+                            b"momentum_pool",
+                            pool.match_id.as_bytes(),
+                            &[ctx.bumps.momentum_pool],
+                        ]],
+                    ),
+                    insurance_fees,
+                )?;
+                emit!(InsuranceFeeEvent {
+                    pool: pool.key(),
+                    amount: insurance_fees,
+                    fund_balance: insurance_vault.amount + insurance_fees,
+                });
+            }
+        }
+        msg!("Batch settled {} positions", settled);
+        Ok(settled)
+    }
+
+    /// Close a position whose stop-loss has triggered: the current index has
+    /// reached its stop-loss index while the window is still open. Anyone may
+    /// call it. The position exits at the current index like
+    /// close_position_early, so past the deadband it returns nothing; the
+    /// stop only stops it from riding a recovery.
+    pub fn execute_stop_loss(ctx: Context<ExecuteExitOrder>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        let stop_loss_index = position.stop_loss_index.ok_or(TradingError::StopLossNotTriggered)?;
+        let current = pool.current_momentum_index;
+        let triggered = match position.position_type {
+            PositionType::Long => current <= stop_loss_index,
+            PositionType::Short => current >= stop_loss_index,
+            PositionType::Straddle => false,
+        };
+        require!(triggered, TradingError::StopLossNotTriggered);
+
+        let (payout, early_exit_fee) = close_early(position, pool, current, now)?;
+        if payout > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.pool_token_account.to_account_info(),
+                        to: ctx.accounts.user_token_account.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    &[&[
+                        b"momentum_pool",
+                        pool.match_id.as_bytes(),
+                        &[ctx.bumps.momentum_pool],
+                    ]],
+                ),
+                payout,
+            )?;
+        }
+
+        msg!("Stop-loss executed at index {}", current);
+
+        emit!(StopLossExecutedEvent {
+            trader: position.trader,
+            position: position.key(),
+            stop_loss_index,
+            exit_index: current,
+            payout,
+            early_exit_fee,
+        });
+
+        Ok(())
+    }
+
+    /// Set or clear an open position's take-profit index
+    pub fn set_take_profit(ctx: Context<SetTakeProfit>, take_profit_index: Option<u8>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(!position.is_tokenized(), TradingError::PositionTokenized);
+        if let Some(take_profit_index) = take_profit_index {
+            require!(
+                is_valid_take_profit(
+                    position.position_type,
+                    position.entry_momentum_index,
+                    take_profit_index
+                ),
+                TradingError::InvalidTakeProfit
+            );
+        }
+        position.take_profit_index = take_profit_index;
+
+        msg!("Take-profit set to {:?}", take_profit_index);
+        Ok(())
+    }
+
+    /// Close a position whose take-profit has been reached while the window is
+    /// still open. Anyone may call it. The payout is capped at the take-profit
+    /// index, even if momentum has moved further, less the early-exit fee.
+    pub fn execute_take_profit(ctx: Context<ExecuteExitOrder>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        let take_profit_index = position
+            .take_profit_index
+            .ok_or(TradingError::TakeProfitNotReached)?;
+        let current = pool.current_momentum_index;
+        let reached = match position.position_type {
+            PositionType::Long => current >= take_profit_index,
+            PositionType::Short => current <= take_profit_index,
+            PositionType::Straddle => false,
+        };
+        require!(reached, TradingError::TakeProfitNotReached);
+
+        let (payout, early_exit_fee) = close_early(position, pool, take_profit_index, now)?;
+        if payout > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.pool_token_account.to_account_info(),
+                        to: ctx.accounts.user_token_account.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    &[&[
+                        b"momentum_pool",
+                        pool.match_id.as_bytes(),
+                        &[ctx.bumps.momentum_pool],
+                    ]],
+                ),
+                payout,
+            )?;
+        }
+
+        msg!("Take-profit executed at index {}", take_profit_index);
+
+        emit!(TakeProfitExecutedEvent {
+            trader: position.trader,
+            position: position.key(),
+            take_profit_index,
+            current_index: current,
+            payout,
+            early_exit_fee,
+        });
+
+        Ok(())
+    }
+
+    /// Queue a position that opens only once the momentum index reaches
+    /// `trigger_index`: at or below it for longs, at or above it for shorts.
+    /// The stake moves into the vault now and is held against the pool's
+    /// liabilities until the order triggers or is cancelled.
+    pub fn place_limit_order(
+        ctx: Context<PlaceLimitOrder>,
+        order_id: u64,
+        position_type: PositionType,
+        trigger_index: u8,
+        args: OpenPositionArgs,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+
+        require!(!ctx.accounts.protocol_config.trading_paused, TradingError::ProtocolPaused);
+        require!(pool.status == PoolStatus::Active, TradingError::PoolNotActive);
+        require!(args.amount > 0, TradingError::InvalidAmount);
+        require!(position_type != PositionType::Straddle, TradingError::InvalidLimitOrder);
+        require!(trigger_index as u16 <= pool.index_scale_max, TradingError::InvalidLimitOrder);
+        // Validated again when the order triggers, but fail fast on a bad
+        // window or an index too stale to judge the trigger against
+        let now = Clock::get()?.unix_timestamp;
+        window_end_time(now, args.window_duration)?;
+        require!(!pool.is_oracle_stale(now), TradingError::StaleOracle);
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: ctx.accounts.pool_token_account.to_account_info(),
+                    authority: ctx.accounts.trader.to_account_info(),
+                },
+            ),
+            args.amount,
+        )?;
+        pool.replace_liability(0, args.amount)?;
+
+        let order = &mut ctx.accounts.pending_order;
+        order.trader = ctx.accounts.trader.key();
+        order.pool = pool.key();
+        order.order_id = order_id;
+        order.position_type = position_type;
+        order.trigger_index = trigger_index;
+        order.collateral_mint = ctx.accounts.user_token_account.mint;
+        order.args = args;
+        order.created_at = now;
+        order.bump = ctx.bumps.pending_order;
+
+        msg!("Limit order {} placed at index {}", order_id, trigger_index);
+
+        emit!(LimitOrderPlacedEvent {
+            pool: pool.key(),
+            trader: order.trader,
+            order_id,
+            position_type,
+            trigger_index,
+            amount: order.args.amount,
+        });
+
+        Ok(())
+    }
+
+    /// Cancel a pending limit order, refunding its stake
+    pub fn cancel_limit_order(ctx: Context<CancelLimitOrder>) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let order = &ctx.accounts.pending_order;
+        let amount = order.args.amount;
+
+        pool.replace_liability(amount, 0)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_token_account.to_account_info(),
+                    to: ctx.accounts.user_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[
+                    b"momentum_pool",
+                    pool.match_id.as_bytes(),
+                    &[ctx.bumps.momentum_pool],
+                ]],
+            ),
+            amount,
+        )?;
+
+        msg!("Limit order {} cancelled", order.order_id);
+        Ok(())
+    }
+
+    /// Open the position for a limit order whose trigger index has been
+    /// reached. Anyone may call it; the keeper pays the new position's rent
+    /// and receives the order account's rent.
+    pub fn trigger_order(ctx: Context<TriggerOrder>) -> Result<()> {
+        let order = &ctx.accounts.pending_order;
+        let pool = &mut ctx.accounts.momentum_pool;
+
+        require!(!ctx.accounts.protocol_config.trading_paused, TradingError::ProtocolPaused);
+        let current = pool.current_momentum_index;
+        let triggered = match order.position_type {
+            PositionType::Long => current <= order.trigger_index,
+            PositionType::Short => current >= order.trigger_index,
+            PositionType::Straddle => false,
+        };
+        require!(triggered, TradingError::OrderNotTriggered);
+
+        // The stake is already in the vault, now backing the position instead
+        pool.replace_liability(order.args.amount, 0)?;
+        let position = &mut ctx.accounts.trading_position;
+        position.collateral_mint = order.collateral_mint;
+        let trader_positions = ctx.accounts.trader_positions.as_mut().map(|trader_positions| {
+            let bump = trader_positions.bump;
+            (trader_positions, bump)
+        });
+        open_position(
+            position,
+            pool,
+            &ctx.accounts.protocol_config,
+            trader_positions,
+            order.trader,
+            ctx.accounts.allowlist_entry.is_some(),
+            order.position_type,
+            &order.args,
+        )?;
+
+        msg!("Limit order {} triggered at index {}", order.order_id, current);
+
+        emit!(LimitOrderTriggeredEvent {
+            pool: pool.key(),
+            trader: order.trader,
+            order_id: order.order_id,
+            position: position.key(),
+            entry_index: position.entry_momentum_index,
+        });
+
+        Ok(())
+    }
+
+    /// Deposit collateral into the trader's margin account for this pool. It
+    /// is held in the pool's vault and owed back until used or withdrawn.
+    pub fn deposit_margin(ctx: Context<ManageMargin>, amount: u64) -> Result<()> {
+        require!(amount > 0, TradingError::InvalidAmount);
+        let pool = &mut ctx.accounts.momentum_pool;
+        let margin_account = &mut ctx.accounts.margin_account;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: ctx.accounts.pool_token_account.to_account_info(),
+                    authority: ctx.accounts.trader.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        // Freshly created by init_if_needed
+        if margin_account.trader == Pubkey::default() {
+            margin_account.trader = ctx.accounts.trader.key();
+            margin_account.pool = pool.key();
+            margin_account.bump = ctx.bumps.margin_account;
+        }
+        margin_account.balance = margin_account
+            .balance
+            .checked_add(amount)
+            .ok_or(TradingError::MathOverflow)?;
+        pool.replace_liability(0, amount)?;
+
+        msg!("Margin deposited: {} (balance {})", amount, margin_account.balance);
+        Ok(())
+    }
+
+    /// Withdraw free collateral from the trader's margin account
+    pub fn withdraw_margin(ctx: Context<ManageMargin>, amount: u64) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let margin_account = &mut ctx.accounts.margin_account;
+
+        require!(amount > 0, TradingError::InvalidAmount);
+        require!(amount <= margin_account.balance, TradingError::InsufficientMargin);
+
+        margin_account.balance = margin_account
+            .balance
+            .checked_sub(amount)
+            .ok_or(TradingError::MathOverflow)?;
+        pool.replace_liability(amount, 0)?;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.pool_token_account.to_account_info(),
+                    to: ctx.accounts.user_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[
+                    b"momentum_pool",
+                    pool.match_id.as_bytes(),
+                    &[ctx.bumps.momentum_pool],
+                ]],
+            ),
+            amount,
+        )?;
+
+        msg!("Margin withdrawn: {} (balance {})", amount, margin_account.balance);
+        Ok(())
+    }
+
+    /// Open a position with a notional of `leverage` times `args.amount`,
+    /// which is taken from the trader's margin account as the position's
+    /// margin. Gains and losses accrue on the notional; losses are capped at
+    /// the margin, and the position can be liquidated once its equity falls
+    /// below the pool's maintenance margin.
+    pub fn open_leveraged_position(
+        ctx: Context<OpenLeveragedPosition>,
+        position_type: PositionType,
+        args: OpenPositionArgs,
+        leverage: u8,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let margin_account = &mut ctx.accounts.margin_account;
+        let margin = args.amount;
+
+        require!(position_type != PositionType::Straddle, TradingError::LeverageNotSupported);
+        require!(
+            pool.settlement_mode == SettlementMode::FixedMultiplier,
+            TradingError::NotSupportedInPariMutuel
+        );
+        require!(
+            leverage > 1 && leverage <= pool.config.max_leverage,
+            TradingError::InvalidLeverage
+        );
+        require!(margin > 0, TradingError::InvalidAmount);
+        require!(margin <= margin_account.balance, TradingError::InsufficientMargin);
+        let notional = margin
+            .checked_mul(leverage as u64)
+            .ok_or(TradingError::MathOverflow)?;
+
+        // The margin stops being owed back as a deposit and backs the
+        // position instead, whose liability open_position records
+        margin_account.balance = margin_account
+            .balance
+            .checked_sub(margin)
+            .ok_or(TradingError::MathOverflow)?;
+        pool.replace_liability(margin, 0)?;
+
+        let position = &mut ctx.accounts.trading_position;
+        position.collateral_mint = pool.collateral_mint;
+        position.leverage = leverage;
+        position.margin = margin;
+        position.maintenance_margin_bps = pool.config.maintenance_margin_bps;
+        let trader_positions = ctx.accounts.trader_positions.as_mut().map(|trader_positions| {
+            let bump = trader_positions.bump;
+            (trader_positions, bump)
+        });
+        open_position(
+            position,
+            pool,
+            &ctx.accounts.protocol_config,
+            trader_positions,
+            ctx.accounts.trader.key(),
+            ctx.accounts.allowlist_entry.is_some(),
+            position_type,
+            &OpenPositionArgs {
+                amount: notional,
+                ..args
+            },
+        )?;
+
+        msg!("Leveraged position opened: {}x on {} margin", leverage, margin);
+        Ok(())
+    }
+
+    /// Liquidate a leveraged position whose equity at the current index has
+    /// fallen below its maintenance margin. Anyone may call it. The trader
+    /// gets nothing; the liquidator earns `liquidation_bonus_bps` of the
+    /// remaining equity and the rest goes to the insurance fund, or, in pools
+    /// whose collateral the fund's vault doesn't hold, to the winners' pot.
+    pub fn liquidate_position(ctx: Context<LiquidatePosition>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        check_open_window(position, pool, now)?;
+        require!(pool.is_undercollateralized(position)?, TradingError::NotLiquidatable);
+
+        let exit_index = pool.current_momentum_index;
+        let (equity, _) = position_payout(position, exit_index, &pool.payout_config(position))?;
+        let bonus = mul_div(
+            equity,
+            pool.config.liquidation_bonus_bps as u64,
+            10_000,
+            RoundingMode::Truncate,
+        )?;
+        // The insurance fund's single vault may be in another mint, in which
+        // case the remainder stays in the pool, credited to the winners' pot
+        let insurance = match (ctx.accounts.insurance_fund.as_mut(), ctx.accounts.insurance_vault.as_ref()) {
+            (Some(fund), Some(vault)) => {
+                require_keys_eq!(vault.key(), fund.vault, TradingError::InvalidInsuranceVault);
+                require_keys_eq!(vault.mint, position.collateral_mint, TradingError::InvalidInsuranceVault);
+                Some((fund, vault))
+            }
+            (None, None) => None,
+            _ => return err!(TradingError::InvalidInsuranceVault),
+        };
+        let to_insurance = if insurance.is_some() { equity - bonus } else { 0 };
+        release_position(position, pool, 0, exit_index, now)?;
+        pool.record_outcome(position.stake(), bonus + to_insurance)?;
+
+        let signer: &[&[&[u8]]] = &[&[
+            b"momentum_pool",
+            pool.match_id.as_bytes(),
+            &[ctx.bumps.momentum_pool],
+        ]];
+        if bonus > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.pool_token_account.to_account_info(),
+                        to: ctx.accounts.liquidator_token_account.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    signer,
+                ),
+                bonus,
+            )?;
+        }
+        if let Some((fund, vault)) = insurance.filter(|_| to_insurance > 0) {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.pool_token_account.to_account_info(),
+                        to: vault.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    signer,
+                ),
+                to_insurance,
+            )?;
+            fund.record_deposit(to_insurance)?;
+        }
+
+        msg!("Position liquidated at index {}: bonus {}, insurance {}", exit_index, bonus, to_insurance);
+
+        emit!(LiquidationEvent {
+            pool: pool.key(),
+            trader: position.trader,
+            liquidator: ctx.accounts.liquidator.key(),
+            exit_index,
+            margin: position.margin,
+            liquidator_bonus: bonus,
+            insurance_amount: to_insurance,
+        });
+
+        Ok(())
+    }
+
+    /// Accrue funding for every whole interval since the last accrual. The
+    /// crowded side pays `funding_rate_bps`, scaled by how lopsided the pool
+    /// is, and the other side receives the same total pro rata to its
+    /// stake. Anyone may crank it; balances move at settlement.
+    pub fn accrue_funding(ctx: Context<AccrueFunding>) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(pool.config.funding_rate_bps > 0, TradingError::FundingDisabled);
+        require!(pool.status != PoolStatus::Finalized, TradingError::PoolNotActive);
+        let intervals = (now - pool.last_funding_time) / pool.config.funding_interval;
+        require!(intervals > 0, TradingError::FundingNotDue);
+        pool.last_funding_time += intervals * pool.config.funding_interval;
+
+        let (long, short) = (pool.total_long_volume as i128, pool.total_short_volume as i128);
+        // Funding needs someone to pay and someone to receive it
+        let mut rate = 0;
+        if long > 0 && short > 0 && long != short {
+            // Per unit of the crowded side's stake, over all elapsed intervals
+            rate = pool.config.funding_rate_bps as i128 * (long - short).abs()
+                * FUNDING_INDEX_SCALE as i128 * intervals as i128
+                / ((long + short) * 10_000);
+            let (long_delta, short_delta) = if long > short {
+                (rate, -(rate * long / short))
+            } else {
+                (-(rate * short / long), rate)
+            };
+            pool.long_funding_index = i64::try_from(pool.long_funding_index as i128 + long_delta)
+                .map_err(|_| error!(TradingError::MathOverflow))?;
+            pool.short_funding_index = i64::try_from(pool.short_funding_index as i128 + short_delta)
+                .map_err(|_| error!(TradingError::MathOverflow))?;
+        }
+
+        msg!("Funding accrued over {} intervals: long index {}, short index {}",
+             intervals,
+             pool.long_funding_index,
+             pool.short_funding_index);
+
+        emit!(FundingAccruedEvent {
+            pool: pool.key(),
+            intervals,
+            crowded_rate: rate as i64,
+            long_funding_index: pool.long_funding_index,
+            short_funding_index: pool.short_funding_index,
+            timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    /// Add `amount` to an open position. Its entry index, fee rate, funding
+    /// snapshot and entry time become volume-weighted averages of the
+    /// existing stake and the addition, which enters at the current index
+    /// like a new position would. Averaging the entry time keeps a late
+    /// top-up from earning the early-entry bonus of the original stake.
+    pub fn increase_position(
+        ctx: Context<IncreasePosition>,
+        amount: u64,
+        entry_index_limit: Option<u8>,
+    ) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(!ctx.accounts.protocol_config.trading_paused, TradingError::ProtocolPaused);
+        require!(pool.status == PoolStatus::Active, TradingError::PoolNotActive);
+        check_open_window(position, pool, now)?;
+        require!(
+            !pool.config.allowlist_enabled || ctx.accounts.allowlist_entry.is_some(),
+            TradingError::NotAllowlisted
+        );
+        require!(!position.is_leveraged(), TradingError::LeverageNotSupported);
+        require!(amount > 0, TradingError::InvalidAmount);
+        let total = position
+            .amount
+            .checked_add(amount)
+            .ok_or(TradingError::MathOverflow)?;
+        check_new_stake(pool, amount, total, now)?;
+
+        let (entry_index, _) = pool.entry_index(&position.position_type);
+        check_entry_limit(position.position_type, entry_index, entry_index_limit)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.user_token_account.to_account_info(),
+                    to: ctx.accounts.pool_token_account.to_account_info(),
+                    authority: ctx.accounts.trader.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let old_amount = position.amount;
+        let weighted = |old: u64, added: u64| -> Result<u64> {
+            let sum = old as u128 * old_amount as u128 + added as u128 * amount as u128;
+            let average = match pool.config.rounding_mode {
+                RoundingMode::Truncate => sum / total as u128,
+                RoundingMode::RoundHalfUp => (sum + total as u128 / 2) / total as u128,
+            };
+            u64::try_from(average).map_err(|_| error!(TradingError::MathOverflow))
+        };
+        let average_entry = weighted(position.entry_momentum_index as u64, entry_index as u64)? as u8;
+        let profit_fee_bps =
+            weighted(position.profit_fee_bps as u64, pool.entry_fee_bps(&position.position_type) as u64)? as u16;
+        // Keeps the funding already accrued on the existing stake owed, and
+        // starts the addition from the current index
+        let entry_funding_index = ((position.entry_funding_index as i128 * old_amount as i128
+            + pool.funding_index(&position.position_type) as i128 * amount as i128)
+            / total as i128) as i64;
+        let entry_time = weighted(position.entry_time as u64, now as u64)? as i64;
+
+        position.amount = total;
+        position.entry_time = entry_time;
+        position.window_duration = position.window_end_time - entry_time;
+        position.entry_momentum_index = average_entry;
+        position.profit_fee_bps = profit_fee_bps;
+        position.entry_funding_index = entry_funding_index;
+        pool.add_volume(position.position_type, amount)?;
+        let max_liability = pool.max_liability(position)?;
+        pool.replace_liability(position.max_liability, max_liability)?;
+        position.max_liability = max_liability;
+
+        msg!("Position increased by {} to {}, average entry {}",
+             amount,
+             position.amount,
+             position.entry_momentum_index);
+
+        emit!(PositionIncreasedEvent {
+            trader: position.trader,
+            pool: position.pool,
+            position_type: position.position_type,
+            added_amount: amount,
+            added_entry_index: entry_index,
+            amount: position.amount,
+            average_entry_index: position.entry_momentum_index,
+        });
+
+        Ok(())
+    }
+
+    /// Hand an open position to another wallet, which signs to accept it.
+    /// The new owner also becomes the payout recipient.
+    pub fn transfer_position(ctx: Context<TransferPosition>) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &ctx.accounts.momentum_pool;
+        let from = ctx.accounts.trader.key();
+        let to = ctx.accounts.new_trader.key();
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(position.pool_epoch == pool.epoch, TradingError::StalePosition);
+        require!(to != from, TradingError::InvalidTransfer);
+        // Tokenized positions change hands by moving the token
+        require!(!position.is_tokenized(), TradingError::PositionTokenized);
+        require!(
+            !pool.config.allowlist_enabled || ctx.accounts.allowlist_entry.is_some(),
+            TradingError::NotAllowlisted
+        );
+
+        position.trader = to;
+        position.payout_recipient = to;
+        if position.referrer == Some(to) {
+            position.referrer = None;
+        }
+
+        if let Some(from_positions) = ctx.accounts.from_positions.as_mut() {
+            from_positions.record_settled(&position.key());
+        }
+        ctx.accounts.to_positions.record_open(
+            pool.key(),
+            to,
+            position.key(),
+            ctx.bumps.to_positions,
+        );
+
+        msg!("Position transferred from {} to {}", from, to);
+
+        emit!(PositionTransferredEvent {
+            position: position.key(),
+            pool: pool.key(),
+            from,
+            to,
+        });
+
+        Ok(())
+    }
+
+    /// Offer an open position for sale at `price`, in its collateral mint.
+    /// The listing takes ownership of the position until it is bought or
+    /// the listing is cancelled; winnings from settling it meanwhile still go
+    /// to the seller's payout recipient.
+    pub fn list_position_for_sale(ctx: Context<ListPositionForSale>, price: u64) -> Result<()> {
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &ctx.accounts.momentum_pool;
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(position.pool_epoch == pool.epoch, TradingError::StalePosition);
+        // Tokenized positions change hands by moving the token
+        require!(!position.is_tokenized(), TradingError::PositionTokenized);
+        require!(price > 0, TradingError::InvalidAmount);
+
+        let listing = &mut ctx.accounts.listing;
+        listing.seller = position.trader;
+        listing.position = position.key();
+        listing.pool = pool.key();
+        listing.price = price;
+        listing.created_at = Clock::get()?.unix_timestamp;
+        listing.bump = ctx.bumps.listing;
+        position.trader = listing.key();
+
+        msg!("Position listed for {}", price);
+
+        emit!(PositionListedEvent {
+            position: listing.position,
+            pool: listing.pool,
+            seller: listing.seller,
+            price,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw a listing, returning the position to the seller
+    pub fn cancel_listing(ctx: Context<CancelListing>) -> Result<()> {
+        let listing = &ctx.accounts.listing;
+        ctx.accounts.trading_position.trader = listing.seller;
+
+        msg!("Listing cancelled");
+
+        emit!(ListingCancelledEvent {
+            position: listing.position,
+            seller: listing.seller,
+        });
+
+        Ok(())
+    }
+
+    /// Buy a listed position: the price goes to the seller and the position,
+    /// along with its future payout, to the buyer, in one step. `max_price`
+    /// guards against paying more than the buyer saw.
+    pub fn buy_position(ctx: Context<BuyPosition>, max_price: u64) -> Result<()> {
+        let listing = &ctx.accounts.listing;
+        let position = &mut ctx.accounts.trading_position;
+        let pool = &ctx.accounts.momentum_pool;
+        let buyer = ctx.accounts.buyer.key();
+
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(position.pool_epoch == pool.epoch, TradingError::StalePosition);
+        require!(buyer != listing.seller, TradingError::InvalidTransfer);
+        require!(listing.price <= max_price, TradingError::SlippageExceeded);
+        require!(
+            !pool.config.allowlist_enabled || ctx.accounts.allowlist_entry.is_some(),
+            TradingError::NotAllowlisted
+        );
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.buyer_token_account.to_account_info(),
+                    to: ctx.accounts.seller_token_account.to_account_info(),
+                    authority: ctx.accounts.buyer.to_account_info(),
+                },
+            ),
+            listing.price,
+        )?;
+
+        position.trader = buyer;
+        position.payout_recipient = buyer;
+        if position.referrer == Some(buyer) {
+            position.referrer = None;
+        }
+        if let Some(seller_positions) = ctx.accounts.seller_positions.as_mut() {
+            seller_positions.record_settled(&position.key());
+        }
+        ctx.accounts.buyer_positions.record_open(
+            pool.key(),
+            buyer,
+            position.key(),
+            ctx.bumps.buyer_positions,
+        );
+
+        msg!("Position sold for {}", listing.price);
+
+        emit!(PositionSoldEvent {
+            position: position.key(),
+            pool: pool.key(),
+            seller: listing.seller,
+            buyer,
+            price: listing.price,
+        });
+
+        Ok(())
+    }
+
+    /// Create the pool's momentum history account. From then on each
+    /// update_momentum_index appends to it, for settlement lookups older
+    /// than the pool's sample buffer, TWAPs and charting.
+    pub fn initialize_momentum_history(ctx: Context<InitializeMomentumHistory>) -> Result<()> {
+        let pool = &ctx.accounts.momentum_pool;
+        let mut history = ctx.accounts.momentum_history.load_init()?;
+        history.pool = pool.key();
+        // Start from the index in force now, so lookups between here and the
+        // next update resolve
+        history.push(pool.current_momentum_index, pool.last_update);
+
+        msg!("Momentum history initialized for {}", pool.match_id);
+        Ok(())
+    }
+
+    /// Submit one oracle's reading of the index on a pool aggregating its
+    /// oracles. Once `update_quorum` enabled oracles have submitted readings
+    /// within MAX_READING_AGE seconds of each other, the index moves to their
+    /// median and the readings are cleared, so no single compromised feed can
+    /// set it alone. Returns whether the index moved.
+    pub fn submit_momentum_reading(ctx: Context<UpdateMomentum>, reading: u8) -> Result<bool> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(pool.config.update_quorum > 1, TradingError::OracleQuorumNotConfigured);
+        require!(pool.oracle_source == OracleSource::Signer, TradingError::WrongOracleSource);
+        require!(reading as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+        require!(pool.status != PoolStatus::Finalized, TradingError::PoolFinalized);
+        require!(pool.pending_commitment.is_none(), TradingError::CommitmentPending);
+        let slot = pool.oracle_slot(&ctx.accounts.oracle.key())?;
+        check_oracle_stake(pool, ctx.accounts.oracle_stake.as_mut(), now)?;
+        pool.oracle_readings[slot] = Some(OracleReading {
+            index: reading,
+            timestamp: now,
+        });
+
+        let Some(new_index) = pool.quorum_reading(now) else {
+            msg!("Oracle {} submitted reading {}", ctx.accounts.oracle.key(), reading);
+            return Ok(false);
+        };
+        pool.oracle_readings = [None; MAX_ORACLES];
+        if !pool.check_circuit_breaker(new_index, now)? {
+            return Ok(false);
+        }
+        let old_index = pool.apply_momentum_update(new_index, now, None);
+        if let Some(history) = &ctx.accounts.momentum_history {
+            history.load_mut()?.push(new_index, now);
+        }
+
+        msg!("Momentum index aggregated: {} -> {}", old_index, new_index);
+
+        emit!(MomentumUpdateEvent {
+            match_id: pool.match_id.clone(),
+            old_index,
+            new_index,
+            timestamp: now,
+            confidence: pool.oracle_confidence,
+        });
+
+        Ok(true)
+    }
+
+    /// Move the index of a Switchboard-fed pool to the latest confirmed
+    /// round of its aggregator. Anyone may crank it; the round must be newer
+    /// than the pool's last update, no older than `feed_max_age` and, when
+    /// `feed_max_std_dev` is set, agreed on by the feed's oracles to within
+    /// that many index points. The pool's oracles still finalize it.
+    pub fn update_momentum_from_switchboard(ctx: Context<UpdateMomentumFromSwitchboard>) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        require!(pool.oracle_source == OracleSource::Switchboard, TradingError::WrongOracleSource);
+        require!(pool.status != PoolStatus::Finalized, TradingError::PoolFinalized);
+        require!(pool.pending_commitment.is_none(), TradingError::CommitmentPending);
+
+        let round = SwitchboardRound::read(&ctx.accounts.switchboard_feed)?;
+        require!(round.num_success > 0, TradingError::InvalidSwitchboardFeed);
+        require!(round.open_timestamp > pool.last_update, TradingError::StaleFeed);
+        require!(
+            pool.config.feed_max_age == 0 || now - round.open_timestamp <= pool.config.feed_max_age,
+            TradingError::StaleFeed
+        );
+        let new_index = round.result.to_index()?;
+        require!(new_index as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+        if pool.config.feed_max_std_dev > 0 {
+            require!(
+                round.std_deviation.to_index()? <= pool.config.feed_max_std_dev,
+                TradingError::FeedConfidenceTooLow
+            );
+        }
+        if !pool.check_circuit_breaker(new_index, now)? {
+            return Ok(());
+        }
+
+        let old_index = pool.apply_momentum_update(new_index, now, None);
+        if let Some(history) = &ctx.accounts.momentum_history {
+            history.load_mut()?.push(new_index, now);
+        }
+
+        msg!("Momentum index updated from Switchboard: {} -> {}", old_index, new_index);
+
+        emit!(MomentumUpdateEvent {
+            match_id: pool.match_id.clone(),
+            old_index,
+            new_index,
+            timestamp: now,
+            confidence: pool.oracle_confidence,
+        });
+
+        Ok(())
+    }
+
+    /// Cache the collateral's USD price from the pool's Pyth feed, for
+    /// position limits and USD PnL. Anyone may crank it, typically just
+    /// before opening a position. The price must be trading, newer than the
+    /// cached one, within `price_max_age` and inside `price_max_conf_bps`.
+    pub fn refresh_collateral_price(ctx: Context<RefreshCollateralPrice>) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+        let now = Clock::get()?.unix_timestamp;
+
+        let price = PythPrice::read(&ctx.accounts.price_feed)?;
+        require!(price.price > 0, TradingError::InvalidPriceFeed);
+        require!(price.publish_time >= pool.collateral_price_time, TradingError::StalePrice);
+        require!(
+            pool.config.price_max_age == 0 || now - price.publish_time <= pool.config.price_max_age,
+            TradingError::StalePrice
+        );
+        require!(
+            pool.config.price_max_conf_bps == 0
+                || price.conf as u128 * 10_000 <= price.price as u128 * pool.config.price_max_conf_bps as u128,
+            TradingError::PriceConfidenceTooLow
+        );
+
+        pool.collateral_price = price.price;
+        pool.collateral_price_expo = price.expo;
+        pool.collateral_price_time = price.publish_time;
+
+        msg!("Collateral price: {}e{} at {}", price.price, price.expo, price.publish_time);
+        Ok(())
+    }
+
+    /// Reopen a pool halted by the circuit breaker, returning it to its
+    /// prior status. With `resume_index` the index moves there first, e.g.
+    /// to accept the print that tripped the breaker after checking it.
+    pub fn resume_pool(ctx: Context<PoolAdmin>, resume_index: Option<u8>) -> Result<()> {
+        let pool = &mut ctx.accounts.momentum_pool;
+
+        require!(pool.status == PoolStatus::Halted, TradingError::InvalidStatusTransition);
+        if let Some(index) = resume_index {
+            require!(index as u16 <= pool.index_scale_max, TradingError::InvalidMomentumIndex);
+            let old_index = pool.apply_momentum_update(index, Clock::get()?.unix_timestamp, None);
+            msg!("Momentum index reset on resume: {} -> {}", old_index, index);
+        }
+        let status = pool.status_before_halt;
+        change_pool_status(pool, status)
+    }
+}
+
+/// Trader-chosen parameters for opening a position
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct OpenPositionArgs {
+    pub amount: u64,
+    /// In seconds (300 for 5 minutes)
+    pub window_duration: i64,
+    /// Settle at a match checkpoint instead of the window end
+    pub checkpoint: Option<MatchCheckpoint>,
+    /// Worst acceptable entry index: a ceiling for longs, a floor for shorts;
+    /// ignored for straddles. None accepts any entry.
+    pub entry_index_limit: Option<u8>,
+    /// Opaque client-side strategy identifier
+    pub client_tag: [u8; 16],
+    /// Earns a share of the profit fee at settlement
+    pub referrer: Option<Pubkey>,
+    /// Wallet paid at settlement, defaults to the trader
+    pub payout_recipient: Option<Pubkey>,
+    /// Let keepers close the position early once momentum moves against it
+    /// to this index: below the entry for longs, above it for shorts
+    pub stop_loss_index: Option<u8>,
+    /// Let keepers close the position early at this index once momentum
+    /// reaches it: above the entry for longs, below it for shorts
+    pub take_profit_index: Option<u8>,
+}
+
+impl OpenPositionArgs {
+    pub const LEN: usize = 8 + 8 + 2 + 2 + 16 + 33 + 33 + 2 + 2;
+}
+
+impl<'info> OpenPosition<'info> {
+    fn open(&mut self, position_type: PositionType, args: &OpenPositionArgs, trader_positions_bump: u8) -> Result<()> {
+        self.trading_position.collateral_mint = self.user_token_account.mint;
+        open_position(
+            &mut self.trading_position,
+            &mut self.momentum_pool,
+            &self.protocol_config,
+            Some((&mut self.trader_positions, trader_positions_bump)),
+            self.user.key(),
+            self.allowlist_entry.is_some(),
+            position_type,
+            args,
+        )?;
+
+        // Transfer tokens from user to pool
+        token::transfer(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                Transfer {
+                    from: self.user_token_account.to_account_info(),
+                    to: self.pool_token_account.to_account_info(),
+                    authority: self.user.to_account_info(),
+                },
+            ),
+            args.amount,
+        )
+    }
+}
+
+impl<'info> OpenTokenizedPosition<'info> {
+    fn open(
+        &mut self,
+        position_type: PositionType,
+        args: &OpenPositionArgs,
+        metadata_uri: String,
+        pool_bump: u8,
+    ) -> Result<()> {
+        require!(metadata_uri.len() <= MAX_METADATA_URI_LEN, TradingError::InvalidMetadataUri);
+        // Keepers close exit orders out to the payout recipient, who need not
+        // hold the token
+        require!(
+            args.stop_loss_index.is_none() && args.take_profit_index.is_none(),
+            TradingError::PositionTokenized
+        );
+
+        self.trading_position.collateral_mint = self.user_token_account.mint;
+        self.trading_position.position_mint = Some(self.position_mint.key());
+        open_position(
+            &mut self.trading_position,
+            &mut self.momentum_pool,
+            &self.protocol_config,
+            None,
+            self.user.key(),
+            self.allowlist_entry.is_some(),
+            position_type,
+            args,
+        )?;
+
+        token::transfer(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                Transfer {
+                    from: self.user_token_account.to_account_info(),
+                    to: self.pool_token_account.to_account_info(),
+                    authority: self.user.to_account_info(),
+                },
+            ),
+            args.amount,
+        )?;
+
+        let pool = &self.momentum_pool;
+        let signer: &[&[&[u8]]] = &[&[b"momentum_pool", pool.match_id.as_bytes(), &[pool_bump]]];
+        token::mint_to(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                MintTo {
+                    mint: self.position_mint.to_account_info(),
+                    to: self.position_token_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                signer,
+            ),
+            1,
+        )?;
+        self.create_metadata(position_type, metadata_uri, signer)?;
+        // A single token, for good
+        token::set_authority(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                SetAuthority {
+                    current_authority: pool.to_account_info(),
+                    account_or_mint: self.position_mint.to_account_info(),
+                },
+                signer,
+            ),
+            token::spl_token::instruction::AuthorityType::MintTokens,
+            None,
+        )?;
+
+        msg!("Position tokenized as mint {}", self.position_mint.key());
+
+        emit!(PositionTokenizedEvent {
+            position: self.trading_position.key(),
+            pool: pool.key(),
+            trader: self.user.key(),
+            mint: self.position_mint.key(),
+        });
+
+        Ok(())
+    }
+
+    /// CreateMetadataAccountV3 on the Token Metadata program, with the pool
+    /// as mint and update authority
+    fn create_metadata(&self, position_type: PositionType, uri: String, signer: &[&[&[u8]]]) -> Result<()> {
+        let name = format!("Futstar {:?}", position_type);
+        let mut data = vec![33];
+        for field in [name.as_str(), "FUTPOS", uri.as_str()] {
+            field.serialize(&mut data)?;
+        }
+        // No seller fee, creators, collection or uses; mutable; no
+        // collection details
+        data.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0]);
+
+        let pool = self.momentum_pool.to_account_info();
+        let instruction = Instruction {
+            program_id: TOKEN_METADATA_PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(self.metadata.key(), false),
+                AccountMeta::new_readonly(self.position_mint.key(), false),
+                AccountMeta::new_readonly(pool.key(), true),
+                AccountMeta::new(self.user.key(), true),
+                AccountMeta::new_readonly(pool.key(), true),
+                AccountMeta::new_readonly(self.system_program.key(), false),
+            ],
+            data,
+        };
+        invoke_signed(
+            &instruction,
+            &[
+                self.metadata.to_account_info(),
+                self.position_mint.to_account_info(),
+                pool.clone(),
+                self.user.to_account_info(),
+                pool,
+                self.system_program.to_account_info(),
+                self.token_metadata_program.to_account_info(),
+            ],
+            signer,
+        )?;
+        Ok(())
+    }
+}
+
+/// Guard against a hand-summed LEN drifting from the real layout: `value`,
+/// with every optional field set, must fit the space allocated for `account`
+/// Slice of a settlement's profit `fee` that seeds the insurance fund, out of
+/// at most `available` vault funds. Nothing without the fund, or when its
+/// vault holds another mint than the pool's `vault_mint`.
+fn insurance_fee_share(
+    pool: &MomentumPool,
+    fee: u64,
+    insurance: Option<(&Account<InsuranceFund>, &Account<TokenAccount>)>,
+    vault_mint: Pubkey,
+    available: u64,
+) -> Result<u64> {
+    match insurance {
+        Some((fund, vault)) if pool.config.insurance_fee_bps > 0 => {
+            require_keys_eq!(vault.key(), fund.vault, TradingError::InvalidInsuranceVault);
+            if vault.mint != vault_mint {
+                return Ok(0);
+            }
+            pool.insurance_fee(fee, available)
+        }
+        _ => Ok(0),
+    }
+}
+
+fn check_account_size<T: AnchorSerialize>(value: &T, account: &AccountInfo) -> Result<()> {
+    let serialized_len = 8 + value.try_to_vec()?.len();
+    require!(serialized_len <= account.data_len(), TradingError::AccountSizeMismatch);
+    Ok(())
+}
+
+/// Reject an entry at `entry_index` beyond the trader's `limit`: longs after
+/// momentum has risen past it, shorts after it has fallen below
+fn check_entry_limit(position_type: PositionType, entry_index: u8, limit: Option<u8>) -> Result<()> {
+    if let Some(limit) = limit {
+        let within_limit = match position_type {
+            PositionType::Long => entry_index <= limit,
+            PositionType::Short => entry_index >= limit,
+            // Pays on the size of the move, not its direction
+            PositionType::Straddle => true,
+        };
+        require!(within_limit, TradingError::EntrySlippageExceeded);
+    }
+    Ok(())
+}
+
+/// Whether `take_profit_index` lies on the winning side of `entry_index`
+fn is_valid_take_profit(position_type: PositionType, entry_index: u8, take_profit_index: u8) -> bool {
+    match position_type {
+        PositionType::Long => take_profit_index > entry_index,
+        PositionType::Short => take_profit_index < entry_index,
+        PositionType::Straddle => false,
+    }
+}
+
+/// Settle `position` before its window ends at `exit_index`, less the pool's
+/// early-exit fee, releasing it from the pool's counters. Returns the payout
+/// and the fee; the caller transfers the payout.
+fn close_early(
+    position: &mut Account<TradingPosition>,
+    pool: &mut Account<MomentumPool>,
+    exit_index: u8,
+    now: i64,
+) -> Result<(u64, u64)> {
+    check_open_window(position, pool, now)?;
+    require!(
+        pool.settlement_mode == SettlementMode::FixedMultiplier,
+        TradingError::NotSupportedInPariMutuel
+    );
+    require!(
+        now - position.entry_time >= pool.config.min_hold_seconds,
+        TradingError::MinHoldNotMet
+    );
+
+    let (payout, fee) = position_payout(position, exit_index, &pool.payout_config(position))?;
+    let early_exit_fee = mul_div(
+        payout,
+        pool.config.early_exit_fee_bps as u64,
+        10_000,
+        pool.config.rounding_mode,
+    )?;
+    let payout = payout - early_exit_fee;
+    release_position(position, pool, payout, exit_index, now)?;
+    pool.collect_fee(fee.checked_add(early_exit_fee).ok_or(TradingError::MathOverflow)?, 0)?;
+    pool.record_outcome(position.stake(), payout + fee + early_exit_fee)?;
+
+    Ok((payout, early_exit_fee))
+}
+
+/// Ensure `position` can still be closed at the current index: unsettled, in
+/// the current epoch, inside its window, and with a live index that isn't
+/// about to be replaced by a committed update someone could front-run
+fn check_open_window(position: &TradingPosition, pool: &MomentumPool, now: i64) -> Result<()> {
+    require!(!position.is_settled, TradingError::AlreadySettled);
+    require!(position.pool_epoch == pool.epoch, TradingError::StalePosition);
+    require!(pool.status != PoolStatus::Finalized, TradingError::WindowEnded);
+    require!(pool.status != PoolStatus::Halted, TradingError::PoolHalted);
+    require!(now < position.window_end_time, TradingError::WindowEnded);
+    require!(!pool.is_heartbeat_lost(now), TradingError::OracleHeartbeatLost);
+    require!(pool.pending_commitment.is_none(), TradingError::CommitmentPending);
+    Ok(())
+}
+
+/// Mark `position` settled at `exit_index` with `payout` owed to the trader,
+/// and release it from the pool's counters
+fn release_position(
+    position: &mut Account<TradingPosition>,
+    pool: &mut Account<MomentumPool>,
+    payout: u64,
+    exit_index: u8,
+    now: i64,
+) -> Result<()> {
+    let stake = position.stake();
+    position.is_settled = true;
+    position.record_pnl(payout, stake)?;
+    position.exit_momentum_index = exit_index;
+    position.settled_at = now;
+    pool.last_settlement_time = now;
+    pool.remove_volume(position.position_type, position.amount)?;
+    pool.replace_liability(position.max_liability, 0)?;
+    pool.open_positions = pool.open_positions.saturating_sub(1);
+    Ok(())
+}
+
+/// Move `pool` to `status`, which the caller has checked is a valid transition
+fn change_pool_status(pool: &mut Account<MomentumPool>, status: PoolStatus) -> Result<()> {
+    let old_status = pool.status;
+    pool.status = status;
+
+    msg!("Pool status changed: {:?} -> {:?}", old_status, status);
+
+    emit!(PoolStatusChangedEvent {
+        match_id: pool.match_id.clone(),
+        old_status,
+        new_status: status,
+        timestamp: Clock::get()?.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+/// End of a window of `window_duration` seconds starting at `now`. Rejects
+/// non-positive clocks and durations outside (0, MAX_WINDOW_DURATION], so a
+/// stored window end is always positive and never near i64::MAX.
+fn window_end_time(now: i64, window_duration: i64) -> Result<i64> {
+    require!(now > 0, TradingError::InvalidTimestamp);
+    require!(
+        window_duration > 0 && window_duration <= MAX_WINDOW_DURATION,
+        TradingError::InvalidTimestamp
+    );
+    now.checked_add(window_duration).ok_or(TradingError::InvalidTimestamp.into())
+}
+
+/// Checks shared by every path that puts new stake at the current index:
+/// the index must be live, the pool must have room for `added`, and the
+/// position's whole `stake` must stay within the USD limit.
+fn check_new_stake(pool: &MomentumPool, added: u64, stake: u64, now: i64) -> Result<()> {
+    require!(!pool.is_heartbeat_lost(now), TradingError::OracleHeartbeatLost);
+    require!(!pool.is_oracle_stale(now), TradingError::StaleOracle);
+    require!(pool.has_capacity_for(added), TradingError::PoolCapacityReached);
+    if pool.price_feed != Pubkey::default() && pool.config.max_position_usd > 0 {
+        let value = pool
+            .usd_value(i64::try_from(stake).map_err(|_| TradingError::MathOverflow)?, now)
+            .ok_or(TradingError::StalePrice)?;
+        require!(value <= pool.config.max_position_usd as i64, TradingError::PositionValueExceeded);
+    }
+    Ok(())
+}
+
+/// Validate a new position for `trader` against the pool and record it. The
+/// caller moves the stake into the vault afterwards, with whichever authority
+/// its entry point allows, unless it is already there (compounding).
+#[allow(clippy::too_many_arguments)]
+fn open_position(
+    position: &mut Account<TradingPosition>,
+    pool: &mut Account<MomentumPool>,
+    protocol_config: &ProtocolConfig,
+    trader_positions: Option<(&mut Account<TraderPositions>, u8)>,
+    trader: Pubkey,
+    allowlisted: bool,
+    position_type: PositionType,
+    args: &OpenPositionArgs,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let amount = args.amount;
+
+    require!(!protocol_config.trading_paused, TradingError::ProtocolPaused);
+    require!(pool.status == PoolStatus::Active, TradingError::PoolNotActive);
+    require!(amount > 0, TradingError::InvalidAmount);
+    if pool.settlement_mode == SettlementMode::PariMutuel {
+        // Every position shares the match's single outcome
+        require!(
+            position_type != PositionType::Straddle && args.checkpoint.is_none(),
+            TradingError::NotSupportedInPariMutuel
+        );
+    }
+    let window_end_time = window_end_time(clock.unix_timestamp, args.window_duration)?;
+    require!(
+        pool.protocol_limits.allows_window(args.window_duration),
+        TradingError::WindowDurationOutOfRange
+    );
+    check_new_stake(pool, amount, amount, clock.unix_timestamp)?;
+    require!(pool.has_position_slot(), TradingError::MaxPositionsReached);
+    require!(
+        !pool.config.allowlist_enabled || allowlisted,
+        TradingError::NotAllowlisted
+    );
+    let (entry_index, entry_spread) = pool.entry_index(&position_type);
+    check_entry_limit(position_type, entry_index, args.entry_index_limit)?;
+    if let Some(stop_loss_index) = args.stop_loss_index {
+        let below_entry = match position_type {
+            PositionType::Long => stop_loss_index < entry_index,
+            PositionType::Short => stop_loss_index > entry_index,
+            PositionType::Straddle => false,
+        };
+        require!(below_entry, TradingError::InvalidStopLoss);
+    }
+    if let Some(take_profit_index) = args.take_profit_index {
+        require!(
+            is_valid_take_profit(position_type, entry_index, take_profit_index),
+            TradingError::InvalidTakeProfit
+        );
+    }
+    if let Some(checkpoint) = args.checkpoint {
+        require!(
+            pool.checkpoint_snapshot(checkpoint).is_none(),
+            TradingError::CheckpointAlreadyRecorded
+        );
+    }
+    require!(args.referrer != Some(trader), TradingError::SelfReferral);
+
+    position.trader = trader;
+    position.pool = pool.key();
+    position.position_type = position_type;
+    position.amount = amount;
+    position.entry_momentum_index = entry_index;
+    position.entry_spread = entry_spread;
+    position.entry_time = clock.unix_timestamp;
+    position.window_end_time = window_end_time;
+    position.window_duration = args.window_duration;
+    position.checkpoint = args.checkpoint;
+    position.client_tag = args.client_tag;
+    position.referrer = args.referrer;
+    position.payout_recipient = args.payout_recipient.unwrap_or(trader);
+    position.stop_loss_index = args.stop_loss_index;
+    position.take_profit_index = args.take_profit_index;
+    position.is_settled = false;
+    position.pnl = 0;
+    // Flag entries made while an oracle update is committed but not yet revealed
+    position.opened_during_commit = pool.pending_commitment.is_some();
+    position.version = POSITION_VERSION;
+    position.profit_fee_bps = pool.entry_fee_bps(&position_type);
+    position.index_scale_max = pool.index_scale_max;
+    position.max_liability = pool.max_liability(position)?;
+    // Pool balance as the position found it, before its own stake is added
+    position.entry_total_long_volume = pool.total_long_volume;
+    position.entry_total_short_volume = pool.total_short_volume;
+    position.pool_epoch = pool.epoch;
+    position.entry_funding_index = pool.funding_index(&position_type);
+
+    pool.add_volume(position_type, amount)?;
+    pool.replace_liability(0, position.max_liability)?;
+    pool.open_positions += 1;
+
+    check_account_size(&position.fully_populated(), &position.to_account_info())?;
+
+    if let Some((trader_positions, bump)) = trader_positions {
+        trader_positions.record_open(pool.key(), trader, position.key(), bump);
+    }
+
+    msg!("{:?} position opened: {} at momentum index {}",
+         position_type,
+         amount,
+         position.entry_momentum_index);
+
+    emit!(PositionOpenedEvent {
+        trader,
+        pool: position.pool,
+        position_type,
+        amount,
+        entry_index: position.entry_momentum_index,
+        window_end_time: position.window_end_time,
+        client_tag: args.client_tag,
+        profit_fee_bps: position.profit_fee_bps,
+        entry_total_long_volume: position.entry_total_long_volume,
+        entry_total_short_volume: position.entry_total_short_volume,
+    });
+
+    Ok(())
+}
+
+/// Require the publishing oracle to have at least the pool's minimum stake
+/// bonded, and restart its unbonding period
+fn check_oracle_stake(
+    pool: &MomentumPool,
+    oracle_stake: Option<&mut Account<OracleStake>>,
+    now: i64,
+) -> Result<()> {
+    if pool.config.min_oracle_stake == 0 {
+        return Ok(());
+    }
+    let oracle_stake = oracle_stake.ok_or(TradingError::InsufficientOracleStake)?;
+    require!(
+        oracle_stake.amount >= pool.config.min_oracle_stake,
+        TradingError::InsufficientOracleStake
+    );
+    oracle_stake.last_published_at = now;
+    Ok(())
+}
+
+/// Median of non-empty `sorted` values, averaging the middle two of an even count
+fn median(sorted: &[u8]) -> u8 {
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 1 {
+        sorted[mid]
+    } else {
+        ((sorted[mid - 1] as u16 + sorted[mid] as u16) / 2) as u8
+    }
+}
+
+/// Toggle one of the pool's oracle entries, emitting events for monitors
+/// tracking which feeder is live
+fn set_oracle_enabled(pool: &mut Account<MomentumPool>, oracle: Pubkey, enabled: bool) -> Result<()> {
+    let previous = pool.active_oracle();
+    let entry = pool
+        .oracles
+        .iter_mut()
+        .find(|entry| entry.key == oracle && oracle != Pubkey::default())
+        .ok_or(TradingError::UnauthorizedOracle)?;
+    entry.enabled = enabled;
+
+    emit!(OracleStatusChangedEvent {
+        pool: pool.key(),
+        oracle,
+        enabled,
+    });
+
+    let active = pool.active_oracle();
+    if active != previous {
+        msg!("Oracle failover: {:?} -> {:?}", previous, active);
+        emit!(OracleFailoverEvent {
+            pool: pool.key(),
+            previous,
+            active,
+        });
+    }
+    Ok(())
+}
+
+/// Net payout and fee for a stake of `amount` entered at `entry_index` and
+/// closed at `exit_index`: the stake plus the move in the position's favor as a
+/// fraction of the index scale it was opened under (or the multiplier of the
+/// payout band the move falls in), boosted by the entry bonus, less the
+/// profit fee. Flat or adverse moves return nothing. Each
+/// division rounds according to `config.rounding_mode`. Profits below
+/// `config.min_profit_for_fee` are paid out fee-free. Moves of at most
+/// `config.deadband` points either way are treated as noise and refund the
+/// stake. Profit never exceeds `config.max_profit`.
+pub fn compute_payout(
+    position_type: &PositionType,
+    entry_index: u8,
+    exit_index: u8,
+    amount: u64,
+    config: &PayoutConfig,
+) -> Result<(u64, u64)> {
+    // Lossless widening: i32 holds the difference of any two indices with room
+    // to spare, so neither the subtraction nor the negation below can overflow
+    let momentum_change = i32::from(exit_index) - i32::from(entry_index);
+    // Long wins if momentum increased, short wins if it decreased, straddle
+    // wins on any move
+    let favorable_change = match position_type {
+        PositionType::Long => momentum_change,
+        PositionType::Short => -momentum_change,
+        PositionType::Straddle => momentum_change.abs(),
+    };
+    if config.deadband > 0 && favorable_change.unsigned_abs() as u64 <= config.deadband {
+        return Ok((amount, 0));
+    }
+    if favorable_change <= 0 {
+        return Ok((0, 0));
+    }
+
+    let rounding = config.rounding_mode;
+    let profit = match config.band_multiplier_bps(favorable_change as u64) {
+        Some(multiplier_bps) => mul_div(amount, multiplier_bps, 10_000, rounding)?,
+        None => mul_div(amount, favorable_change as u64, config.index_scale_max, rounding)?,
+    };
+    let profit = mul_div(profit, 10_000 + config.entry_bonus_bps, 10_000, rounding)?;
+    let profit = match config.max_profit {
+        Some(max_profit) => profit.min(max_profit),
+        None => profit,
+    };
+    let fee = if profit < config.min_profit_for_fee {
+        0
+    } else {
+        mul_div(profit, config.profit_fee_bps, 10_000, rounding)?
+    };
+    let payout = amount
+        .checked_add(profit)
+        .and_then(|gross| gross.checked_sub(fee))
+        .ok_or(TradingError::MathOverflow)?;
+    Ok((payout, fee))
+}
+
+/// Net payout and fee for a stake of `amount` in a pari-mutuel pool: winners
+/// get their stake back plus a pro-rata share of the losing pot, less the
+/// profit fee; losers get nothing; with no winning side everyone is refunded.
+/// Shares round down so they never add up to more than the losing pot.
+pub fn compute_pari_mutuel_payout(
+    position_type: &PositionType,
+    amount: u64,
+    pots: &PariMutuelPots,
+    config: &PayoutConfig,
+) -> Result<(u64, u64)> {
+    let Some(winning_side) = pots.winning_side else {
+        return Ok((amount, 0));
+    };
+    if *position_type != winning_side {
+        return Ok((0, 0));
+    }
+    let profit = mul_div(amount, pots.losing, pots.winning, RoundingMode::Truncate)?;
+    let fee = if profit < config.min_profit_for_fee {
+        0
+    } else {
+        mul_div(profit, config.profit_fee_bps, 10_000, RoundingMode::Truncate)?
+    };
+    let payout = amount
+        .checked_add(profit - fee)
+        .ok_or(TradingError::MathOverflow)?;
+    Ok((payout, fee))
+}
+
+/// Payout and fee for closing `position` at `exit_index`. Unleveraged
+/// positions follow compute_payout. Leveraged ones win on their full notional
+/// (`amount`) but return their margin plus that profit, and lose linearly on
+/// adverse moves, down to nothing once the loss reaches the margin.
+pub fn position_payout(position: &TradingPosition, exit_index: u8, config: &PayoutConfig) -> Result<(u64, u64)> {
+    let (payout, fee) = price_payout(position, exit_index, config)?;
+    Ok((apply_funding(payout, config.funding, position.max_liability), fee))
+}
+
+/// Payout of `position` from the index move alone, before funding
+fn price_payout(position: &TradingPosition, exit_index: u8, config: &PayoutConfig) -> Result<(u64, u64)> {
+    if let Some(pots) = &config.pari_mutuel {
+        return compute_pari_mutuel_payout(&position.position_type, position.amount, pots, config);
+    }
+    let (gross, fee) = compute_payout(
+        &position.position_type,
+        position.entry_momentum_index,
+        exit_index,
+        position.amount,
+        config,
+    )?;
+    if !position.is_leveraged() {
+        return Ok((gross, fee));
+    }
+    // A favorable or deadband move returns at least the notional
+    if gross > 0 {
+        return Ok((position.margin + (gross - position.amount), fee));
+    }
+    let adverse_change = position.entry_momentum_index.abs_diff(exit_index) as u64;
+    let loss = mul_div(position.amount, adverse_change, config.index_scale_max, config.rounding_mode)?;
+    Ok((position.margin.saturating_sub(loss), 0))
+}
+
+/// `payout` net of `funding` owed, or plus funding received when negative.
+/// Receipts never lift the payout above `cap`, the liability reserved for
+/// the position, so the vault stays able to cover it.
+pub fn apply_funding(payout: u64, funding: i64, cap: u64) -> u64 {
+    if funding >= 0 {
+        payout.saturating_sub(funding as u64)
+    } else {
+        payout.saturating_add(funding.unsigned_abs()).min(cap.max(payout))
+    }
+}
+
+/// `value * numerator / denominator` in 128-bit precision, rounded per `mode`
+pub fn mul_div(value: u64, numerator: u64, denominator: u64, mode: RoundingMode) -> Result<u64> {
+    require!(denominator > 0, TradingError::MathOverflow);
+    let product = value as u128 * numerator as u128;
+    let denominator = denominator as u128;
+    let quotient = match mode {
+        RoundingMode::Truncate => product / denominator,
+        RoundingMode::RoundHalfUp => (product + denominator / 2) / denominator,
+    };
+    u64::try_from(quotient).map_err(|_| error!(TradingError::MathOverflow))
+}
+
+/// Message an oracle signs off-chain to authorize a relayed momentum update:
+/// the match id, index, timestamp and sequence number, then the confidence.
+/// An unreported confidence is signed as 0.
+pub fn oracle_update_message(
+    match_id: &str,
+    index: u8,
+    timestamp: i64,
+    sequence: u64,
+    confidence: Option<u8>,
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(match_id.len() + 1 + 8 + 8 + 1);
+    message.extend_from_slice(match_id.as_bytes());
+    message.push(index);
+    message.extend_from_slice(&timestamp.to_le_bytes());
+    message.extend_from_slice(&sequence.to_le_bytes());
+    message.push(confidence.unwrap_or(0));
+    message
+}
+
+/// Check that the instruction preceding this one is an Ed25519 program
+/// verification of exactly one `signer` signature over `message`. The Ed25519
+/// program aborts the transaction on a bad signature, so finding it with the
+/// expected key and message proves the oracle signed.
+fn verify_ed25519_signature(
+    instructions_sysvar: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    const HEADER_LEN: usize = 2;
+    const OFFSETS_LEN: usize = 14;
+    // Offsets pointing back into the Ed25519 instruction itself
+    const CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    require!(current_index > 0, TradingError::InvalidOracleSignature);
+    let ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, TradingError::InvalidOracleSignature);
+
+    let data = &ix.data;
+    require!(
+        data.len() >= HEADER_LEN + OFFSETS_LEN && data[0] == 1,
+        TradingError::InvalidOracleSignature
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let offsets = HEADER_LEN;
+    let signature_ix = read_u16(offsets + 2);
+    let public_key_offset = read_u16(offsets + 4) as usize;
+    let public_key_ix = read_u16(offsets + 6);
+    let message_offset = read_u16(offsets + 8) as usize;
+    let message_size = read_u16(offsets + 10) as usize;
+    let message_ix = read_u16(offsets + 12);
+    require!(
+        signature_ix == CURRENT_INSTRUCTION
+            && public_key_ix == CURRENT_INSTRUCTION
+            && message_ix == CURRENT_INSTRUCTION,
+        TradingError::InvalidOracleSignature
+    );
+
+    let signed_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(TradingError::InvalidOracleSignature)?;
+    let signed_message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(TradingError::InvalidOracleSignature)?;
+    require!(
+        signed_key == signer.as_ref() && signed_message == message,
+        TradingError::InvalidOracleSignature
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(match_id: String, start_time: i64, home_team: String, away_team: String)]
+pub struct InitializePool<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + MomentumPool::space(&match_id, &home_team, &away_team),
+        seeds = [b"momentum_pool", match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub collateral_mint: Account<'info, Mint>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    /// League the new pool joins, if any
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"league", league_registry.league_id.to_le_bytes().as_ref()],
+        bump = league_registry.bump
+    )]
+    pub league_registry: Option<Account<'info, LeagueRegistry>>,
+    
+    /// Collateral vault owned by the pool, derived from the pool and mint so
+    /// later instructions can re-derive it instead of trusting the caller
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"vault", momentum_pool.key().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+        token::mint = collateral_mint,
+        token::authority = momentum_pool
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddCollateralMint<'info> {
+    #[account(has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub collateral_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"vault", momentum_pool.key().as_ref(), collateral_mint.key().as_ref()],
+        bump,
+        token::mint = collateral_mint,
+        token::authority = momentum_pool
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenPosition<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + TradingPosition::LEN,
+        seeds = [
+            b"position",
+            momentum_pool.key().as_ref(),
+            user.key().as_ref(),
+            &Clock::get()?.unix_timestamp.to_le_bytes()
+        ],
+        bump
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    /// Vault for the deposited mint. Only mints the authority has added have
+    /// one, so this also rejects unsupported collateral.
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), user_token_account.mint.as_ref()],
+        bump,
+        constraint = pool_token_account.mint == user_token_account.mint @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), user.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + TraderPositions::LEN,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub trader_positions: Account<'info, TraderPositions>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMomentum<'info> {
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub oracle: Signer<'info>,
+    
+    /// CHECK: Address-constrained to the instructions sysvar; only needed to
+    /// verify relayed, oracle-signed updates
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions_sysvar: Option<UncheckedAccount<'info>>,
+    
+    /// Appended to with each update, if the pool has one
+    #[account(mut, seeds = [b"momentum_history", momentum_pool.key().as_ref()], bump)]
+    pub momentum_history: Option<AccountLoader<'info, MomentumHistory>>,
+    
+    /// The oracle's bond, required when the pool sets a minimum oracle stake
+    #[account(
+        mut,
+        seeds = [b"oracle_stake", oracle.key().as_ref()],
+        bump = oracle_stake.bump
+    )]
+    pub oracle_stake: Option<Account<'info, OracleStake>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMomentumBatch<'info> {
+    pub oracle: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettlePosition<'info> {
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        // Tokenized positions pay the token holder, checked when settling
+        constraint = trading_position.is_tokenized()
+            || user_token_account.owner == trading_position.payout_recipient @ TradingError::InvalidPayoutRecipient
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"insurance_fund"],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Option<Account<'info, InsuranceFund>>,
+    
+    #[account(mut)]
+    pub insurance_vault: Option<Account<'info, TokenAccount>>,
+    
+    #[account(mut)]
+    pub referrer_token_account: Option<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), trading_position.trader.as_ref()],
+        bump = trader_positions.bump
+    )]
+    pub trader_positions: Option<Account<'info, TraderPositions>>,
+    
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + TraderStats::LEN,
+        seeds = [b"trader_stats", trading_position.trader.as_ref()],
+        bump
+    )]
+    pub trader_stats: Account<'info, TraderStats>,
+    
+    /// Follow-on position, only when settling with compounding
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + TradingPosition::LEN,
+        seeds = [
+            b"position",
+            momentum_pool.key().as_ref(),
+            trading_position.trader.as_ref(),
+            &Clock::get()?.unix_timestamp.to_le_bytes()
+        ],
+        bump
+    )]
+    pub compound_position: Option<Account<'info, TradingPosition>>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), trading_position.trader.as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    /// CHECK: The SPL token-swap program; only needed to settle into
+    /// stablecoin
+    #[account(executable, address = SPL_TOKEN_SWAP_PROGRAM_ID @ TradingError::InvalidSwapAccount)]
+    pub swap_program: Option<UncheckedAccount<'info>>,
+    
+    /// CHECK: The pool's configured AMM pool; validated by the swap program
+    #[account(address = momentum_pool.config.swap_pool @ TradingError::InvalidSwapAccount)]
+    pub swap_pool: Option<UncheckedAccount<'info>>,
+    
+    /// CHECK: Validated by the swap program against `swap_pool`
+    pub swap_authority: Option<UncheckedAccount<'info>>,
+    
+    /// CHECK: AMM reserve receiving the collateral; validated by the swap program
+    #[account(mut)]
+    pub swap_source: Option<UncheckedAccount<'info>>,
+    
+    /// CHECK: AMM reserve paying out stablecoin; validated by the swap program
+    #[account(mut)]
+    pub swap_destination: Option<UncheckedAccount<'info>>,
+    
+    /// CHECK: AMM LP mint; validated by the swap program
+    #[account(mut)]
+    pub swap_pool_mint: Option<UncheckedAccount<'info>>,
+    
+    /// CHECK: AMM fee account; validated by the swap program
+    #[account(mut)]
+    pub swap_fee_account: Option<UncheckedAccount<'info>>,
+    
+    /// Receives the swapped payout
+    #[account(
+        mut,
+        constraint = trading_position.is_tokenized()
+            || stable_token_account.owner == trading_position.payout_recipient @ TradingError::InvalidPayoutRecipient
+    )]
+    pub stable_token_account: Option<Account<'info, TokenAccount>>,
+    
+    /// Collateral mint, for the swap escrow
+    #[account(address = trading_position.collateral_mint @ TradingError::InvalidSwapAccount)]
+    pub collateral_mint: Option<Account<'info, Mint>>,
+    
+    /// Single-use account, its own authority, that holds just the payout
+    /// while it's swapped so the swap program never acts for the vault.
+    /// Closed to the payer once emptied.
+    #[account(
+        init,
+        payer = payer,
+        seeds = [b"swap_escrow", trading_position.key().as_ref()],
+        bump,
+        token::mint = collateral_mint,
+        token::authority = swap_escrow
+    )]
+    pub swap_escrow: Option<Account<'info, TokenAccount>>,
+    
+    /// Token of a tokenized position, burned at settlement
+    #[account(mut)]
+    pub position_mint: Option<Account<'info, Mint>>,
+    
+    /// Holds the tokenized position's token
+    #[account(mut)]
+    pub position_token_account: Option<Account<'info, TokenAccount>>,
+    
+    /// Owner of `position_token_account`, paid in place of the payout recipient
+    pub position_holder: Option<Signer<'info>>,
+    
+    /// Required to settle TWAP pools, and otherwise consulted when the
+    /// pool's sample buffer no longer holds the settlement update
+    #[account(seeds = [b"momentum_history", momentum_pool.key().as_ref()], bump)]
+    pub momentum_history: Option<AccountLoader<'info, MomentumHistory>>,
+    
+    /// Trader's stake, for a profit fee discount
+    #[account(
+        seeds = [b"stake", trading_position.trader.as_ref()],
+        bump = stake_account.bump
+    )]
+    pub stake_account: Option<Account<'info, StakeAccount>>,
+    
+    /// Funds the trader's stats account the first time one of their
+    /// positions settles; any keeper may pay
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> SettlePosition<'info> {
+    /// Settle, logging a SettlementFailedEvent on failure so keepers can
+    /// tell from the transaction logs (or a simulation) whether to retry
+    fn settle(
+        &mut self,
+        bumps: &SettlePositionBumps,
+        compound_window: Option<i64>,
+        swap_min_out: Option<u64>,
+    ) -> Result<()> {
+        let result = self.try_settle(bumps, compound_window, swap_min_out);
+        if let Err(Error::AnchorError(error)) = &result {
+            let kind = SettlementFailureKind::from_error_code(error.error_code_number);
+            msg!("Settlement failed ({:?}): {}", kind, error.error_msg);
+            emit!(SettlementFailedEvent {
+                position: self.trading_position.key(),
+                error_code: error.error_code_number,
+                kind,
+                timestamp: Clock::get()?.unix_timestamp,
+            });
+        }
+        result
+    }
+
+    /// For a tokenized position, check the signer holds its token, burn it,
+    /// and make the holder the payout recipient
+    fn redeem_position_token(&mut self) -> Result<()> {
+        let Some(position_mint) = self.trading_position.position_mint else {
+            return Ok(());
+        };
+        let (Some(mint), Some(token_account), Some(holder)) =
+            (&self.position_mint, &self.position_token_account, &self.position_holder)
+        else {
+            return err!(TradingError::MissingPositionToken);
+        };
+        require_keys_eq!(mint.key(), position_mint, TradingError::InvalidPositionToken);
+        require!(
+            token_account.mint == position_mint
+                && token_account.owner == holder.key()
+                && token_account.amount == 1,
+            TradingError::InvalidPositionToken
+        );
+        require_keys_eq!(self.user_token_account.owner, holder.key(), TradingError::InvalidPayoutRecipient);
+        if let Some(stable_token_account) = &self.stable_token_account {
+            require_keys_eq!(stable_token_account.owner, holder.key(), TradingError::InvalidPayoutRecipient);
+        }
+        // The follow-on position would belong to the original trader
+        require!(self.compound_position.is_none(), TradingError::InvalidCompound);
+
+        token::burn(
+            CpiContext::new(
+                self.token_program.to_account_info(),
+                Burn {
+                    mint: mint.to_account_info(),
+                    from: token_account.to_account_info(),
+                    authority: holder.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+        // Escrowed winnings are claimed by the payout recipient later
+        self.trading_position.payout_recipient = holder.key();
+        Ok(())
+    }
+
+    fn try_settle(
+        &mut self,
+        bumps: &SettlePositionBumps,
+        compound_window: Option<i64>,
+        swap_min_out: Option<u64>,
+    ) -> Result<()> {
+        self.redeem_position_token()?;
+        let position = &mut self.trading_position;
+        let pool = &mut self.momentum_pool;
+        let clock = Clock::get()?;
+        
+        require!(!self.protocol_config.settlement_paused, TradingError::ProtocolPaused);
+        require!(!position.is_settled, TradingError::AlreadySettled);
+        require!(position.pool_epoch == pool.epoch, TradingError::StalePosition);
+        require!(
+            clock.unix_timestamp - position.entry_time >= pool.config.min_hold_seconds,
+            TradingError::MinHoldNotMet
+        );
+        require!(!pool.is_oracle_stale(clock.unix_timestamp), TradingError::StaleOracle);
+        require!(pool.status != PoolStatus::Halted, TradingError::PoolHalted);
+        
+        let exit_index = {
+            let history = self.momentum_history.as_ref().map(|h| h.load()).transpose()?;
+            pool.exit_index(position, clock.unix_timestamp, history.as_deref())?
+        };
+        
+        // Stakers pay a reduced profit fee
+        let fee_discount_bps = self.protocol_config.fee_discount_bps(self.stake_account.as_deref());
+        if fee_discount_bps > 0 {
+            msg!("Staker fee discount: {} bps", fee_discount_bps);
+        }
+        let payout_config = pool.settlement_payout_config(position, fee_discount_bps);
+        let (payout, fee) = position_payout(position, exit_index, &payout_config)?;
+        
+        // Winnings left unclaimed past the deadline stay in the pool
+        let forfeited = if pool.is_claim_expired(position.window_end_time, clock.unix_timestamp) {
+            payout
+        } else {
+            0
+        };
+        let (payout, fee) = if forfeited > 0 { (0, 0) } else { (payout, fee) };
+        let full_payout = payout;
+        let payout = pool.solvent_payout(full_payout, self.pool_token_account.amount)?;
+        if payout < full_payout {
+            msg!("Pool insolvent: payout scaled from {} to {}", full_payout, payout);
+            emit!(PayoutScaledEvent {
+                pool: pool.key(),
+                position: position.key(),
+                full_payout,
+                payout,
+                outstanding_liability: pool.outstanding_liability,
+                vault_balance: self.pool_token_account.amount,
+            });
+        }
+        
+        // Compounding needs something to compound and the trader's consent
+        match compound_window {
+            Some(_) => {
+                require!(payout > 0, TradingError::InvalidCompound);
+                require!(self.compound_position.is_some(), TradingError::InvalidCompound);
+                require_keys_eq!(self.payer.key(), position.trader, TradingError::InvalidCompound);
+                require!(
+                    self.pool_token_account.amount >= payout,
+                    TradingError::InsufficientLiquidity
+                );
+            }
+            None => require!(self.compound_position.is_none(), TradingError::InvalidCompound),
+        }
+        if swap_min_out.is_some() {
+            require!(pool.config.swap_enabled, TradingError::SwapDisabled);
+            require!(compound_window.is_none(), TradingError::InvalidCompound);
+        }
+        
+        // Large payouts wait out a timelock and are paid by claim_payout
+        // A compounded payout never leaves the vault, so it isn't escrowed
+        let escrowed_payout = if compound_window.is_none() && pool.config.is_escrowed(payout) {
+            payout
+        } else {
+            0
+        };
+        let due_now = if compound_window.is_some() { 0 } else { payout - escrowed_payout };
+        
+        // Prefer pool funds, drawing any shortfall from the insurance fund
+        let pool_token_account = &self.pool_token_account;
+        let from_pool = due_now.min(pool_token_account.amount);
+        let from_insurance = match (&self.insurance_fund, &self.insurance_vault) {
+            (Some(fund), Some(vault)) if from_pool < due_now => {
+                require_keys_eq!(vault.key(), fund.vault, TradingError::InvalidInsuranceVault);
+                require_keys_eq!(vault.mint, pool_token_account.mint, TradingError::InvalidInsuranceVault);
+                // Only the pool's own token account may trigger a draw, so an
+                // empty decoy account can't be used to pull from the fund
+                require_keys_eq!(pool_token_account.owner, pool.key(), TradingError::InvalidInsuranceVault);
+                (due_now - from_pool)
+                    .min(vault.amount)
+                    .min(fund.max_draw_per_settlement)
+            }
+            _ => 0,
+        };
+        require!(from_pool + from_insurance == due_now, TradingError::InsufficientLiquidity);
+        
+        // Referrers earn a share of the fee, paid from whatever pool liquidity
+        // remains once the trader is covered
+        let referral_fee = match (&position.referrer, &self.referrer_token_account) {
+            (Some(referrer), Some(referrer_token_account)) => {
+                require_keys_eq!(
+                    referrer_token_account.owner,
+                    *referrer,
+                    TradingError::InvalidReferrerAccount
+                );
+                (fee * REFERRAL_SHARE_BPS / 10_000)
+                    .min(pool_token_account.amount.saturating_sub(from_pool + (payout - due_now)))
+            }
+            _ => 0,
+        };
+        
+        // A slice of the fee seeds the insurance fund, likewise from what
+        // remains after the trader and referrer
+        let insurance_fee = insurance_fee_share(
+            pool,
+            fee,
+            self.insurance_fund.as_ref().zip(self.insurance_vault.as_ref()),
+            pool_token_account.mint,
+            pool_token_account.amount.saturating_sub(from_pool + (payout - due_now) + referral_fee),
+        )?;
+        
+        // Record settlement before the transfer so the position can never be
+        // observed paid-but-unsettled. A failed transfer aborts the whole
+        // instruction, rolling these writes back and leaving it settleable.
+        position.is_settled = true;
+        let stake = position.stake();
+        position.record_pnl(payout, stake)?;
+        position.exit_momentum_index = exit_index;
+        position.settled_at = clock.unix_timestamp;
+        pool.last_settlement_time = clock.unix_timestamp;
+        // Volume counters track open interest, so release the settled stake
+        pool.remove_volume(position.position_type, position.amount)?;
+        pool.replace_liability(position.max_liability, 0)?;
+        if escrowed_payout > 0 {
+            // Still owed, so keep it out of reach of sweep_dust until claimed
+            pool.replace_liability(0, escrowed_payout)?;
+            position.escrowed_payout = escrowed_payout;
+            position.claimable_at = clock.unix_timestamp + pool.config.escrow_delay;
+            emit!(PayoutEscrowedEvent {
+                pool: pool.key(),
+                trader: position.trader,
+                amount: escrowed_payout,
+                claimable_at: position.claimable_at,
+            });
+        }
+        // Saturating: pools migrated from v0 may hold positions opened before counting began
+        pool.open_positions = pool.open_positions.saturating_sub(1);
+        if let Some(trader_positions) = self.trader_positions.as_mut() {
+            trader_positions.record_settled(&position.key());
+        }
+        self.trader_stats.record_settlement(
+            position.trader,
+            position.amount,
+            payout as i64 - position.stake() as i64,
+            bumps.trader_stats,
+        )?;
+        pool.record_insurance_draw(from_insurance)?;
+        pool.collect_fee(fee, referral_fee + insurance_fee)?;
+        pool.record_outcome(position.stake(), payout + fee)?;
+        
+        // A swapped payout leaves the vault through the AMM instead, below
+        if from_pool > 0 && swap_min_out.is_none() {
+            // Transfer winnings to user
+            token::transfer(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    Transfer {
+                        from: self.pool_token_account.to_account_info(),
+                        to: self.user_token_account.to_account_info(),
+                        authority: pool.to_account_info(),
+                    },
+                    &[&[
+                        b"momentum_pool",
+                        pool.match_id.as_bytes(),
+                        &[bumps.momentum_pool],
+                    ]],
+                ),
+                from_pool,
+            )?;
+        }
+        
+        if referral_fee > 0 {
+            if let Some(referrer_token_account) = &self.referrer_token_account {
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        self.token_program.to_account_info(),
+                        Transfer {
+                            from: self.pool_token_account.to_account_info(),
+                            to: referrer_token_account.to_account_info(),
+                            authority: pool.to_account_info(),
+                        },
+                        &[&[
+                            b"momentum_pool",
+                            pool.match_id.as_bytes(),
+                            &[bumps.momentum_pool],
+                        ]],
+                    ),
+                    referral_fee,
+                )?;
+            }
+        }
+        
+        if insurance_fee > 0 {
+            if let (Some(fund), Some(vault)) = (self.insurance_fund.as_mut(), &self.insurance_vault) {
+                fund.record_deposit(insurance_fee)?;
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        self.token_program.to_account_info(),
+                        Transfer {
+                            from: self.pool_token_account.to_account_info(),
+                            to: vault.to_account_info(),
+                            authority: pool.to_account_info(),
+                        },
+                        &[&[
+                            b"momentum_pool",
+                            pool.match_id.as_bytes(),
+                            &[bumps.momentum_pool],
+                        ]],
+                    ),
+                    insurance_fee,
+                )?;
+                emit!(InsuranceFeeEvent {
+                    pool: pool.key(),
+                    amount: insurance_fee,
+                    fund_balance: vault.amount + insurance_fee,
+                });
+            }
+        }
+        
+        if from_insurance > 0 {
+            let (Some(fund), Some(vault)) = (
+                self.insurance_fund.as_mut(),
+                self.insurance_vault.as_ref(),
+            ) else {
+                return err!(TradingError::InvalidInsuranceVault);
+            };
+            fund.record_draw(from_insurance)?;
+            
+            token::transfer(
+                CpiContext::new_with_signer(
+                    self.token_program.to_account_info(),
+                    Transfer {
+                        from: vault.to_account_info(),
+                        to: self.user_token_account.to_account_info(),
+                        authority: fund.to_account_info(),
+                    },
+                    &[&[b"insurance_fund", &[fund.bump]]],
+                ),
+                from_insurance,
+            )?;
+            
+            emit!(InsuranceDrawEvent {
+                pool: pool.key(),
+                trader: position.trader,
+                amount: from_insurance,
+                fund_balance: vault.amount - from_insurance,
+            });
+        }
+        
+        // Warn monitors while there's still time to top up or stop new entries
+        let remaining_liquidity = self.pool_token_account.amount - from_pool - referral_fee - insurance_fee;
+        if pool.is_liquidity_low(remaining_liquidity) {
+            emit!(LowLiquidityWarningEvent {
+                pool: pool.key(),
+                liquidity: remaining_liquidity,
+                open_interest: pool.open_interest(),
+                threshold_bps: pool.config.low_liquidity_threshold_bps,
+            });
+        }
+        
+        if forfeited > 0 {
+            emit!(ForfeitEvent {
+                pool: pool.key(),
+                trader: position.trader,
+                forfeited,
+                window_end_time: position.window_end_time,
+            });
+        }
+        
+        let stable_received = match swap_min_out {
+            Some(min_out) if from_pool > 0 => {
+                self.swap_payout(from_pool, min_out, bumps)?
+            }
+            _ => 0,
+        };
+        let position = &self.trading_position;
+        
+        msg!("Position settled. PnL: {}", position.pnl);
+        
+        emit!(PositionSettledEvent {
+            trader: position.trader,
+            position_type: position.position_type,
+            pnl: position.pnl,
+            entry_index: position.entry_momentum_index,
+            exit_index: position.exit_momentum_index,
+            amount: position.amount,
+            gross_payout: payout + fee,
+            fee_paid: fee,
+            window_duration: position.window_duration,
+            client_tag: position.client_tag,
+            referrer: position.referrer,
+            referral_fee,
+            stable_received,
+            pnl_usd: self.momentum_pool.usd_value(position.pnl, clock.unix_timestamp),
+        });
+        
+        if let Some(window_duration) = compound_window {
+            self.compound(window_duration, payout)?;
+        }
+        
+        Ok(())
+    }
+
+    /// Swap `amount` of collateral out of the vault through an SPL
+    /// token-swap pool, delivering the other side to `stable_token_account`.
+    /// The amount moves to the swap escrow first, which signs the swap, so
+    /// the vault can lose no more than `amount`. Returns the amount received.
+    fn swap_payout(&mut self, amount: u64, min_out: u64, bumps: &SettlePositionBumps) -> Result<u64> {
+        let (
+            Some(swap_program),
+            Some(swap_pool),
+            Some(swap_authority),
+            Some(swap_source),
+            Some(swap_destination),
+            Some(swap_pool_mint),
+            Some(swap_fee_account),
+            Some(stable_token_account),
+            Some(swap_escrow),
+        ) = (
+            &self.swap_program,
+            &self.swap_pool,
+            &self.swap_authority,
+            &self.swap_source,
+            &self.swap_destination,
+            &self.swap_pool_mint,
+            &self.swap_fee_account,
+            self.stable_token_account.as_mut(),
+            self.swap_escrow.as_mut(),
+        ) else {
+            return err!(TradingError::MissingSwapAccounts);
+        };
+        let pool = &self.momentum_pool;
+        let position_key = self.trading_position.key();
+        let escrow_seeds: &[&[u8]] = &[b"swap_escrow", position_key.as_ref(), &[bumps.swap_escrow]];
+        self.pool_token_account.reload()?;
+        let vault_before = self.pool_token_account.amount;
+        let balance_before = stable_token_account.amount;
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                Transfer {
+                    from: self.pool_token_account.to_account_info(),
+                    to: swap_escrow.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&[b"momentum_pool", pool.match_id.as_bytes(), &[bumps.momentum_pool]]],
+            ),
+            amount,
+        )?;
+
+        // SPL token-swap `Swap { amount_in, minimum_amount_out }`
+        let mut data = Vec::with_capacity(17);
+        data.push(1);
+        data.extend_from_slice(&amount.to_le_bytes());
+        data.extend_from_slice(&min_out.to_le_bytes());
+        let ix = Instruction {
+            program_id: swap_program.key(),
+            accounts: vec![
+                AccountMeta::new_readonly(swap_pool.key(), false),
+                AccountMeta::new_readonly(swap_authority.key(), false),
+                AccountMeta::new_readonly(swap_escrow.key(), true),
+                AccountMeta::new(swap_escrow.key(), false),
+                AccountMeta::new(swap_source.key(), false),
+                AccountMeta::new(swap_destination.key(), false),
+                AccountMeta::new(stable_token_account.key(), false),
+                AccountMeta::new(swap_pool_mint.key(), false),
+                AccountMeta::new(swap_fee_account.key(), false),
+                AccountMeta::new_readonly(self.token_program.key(), false),
+            ],
+            data,
+        };
+        invoke_signed(
+            &ix,
+            &[
+                swap_pool.to_account_info(),
+                swap_authority.to_account_info(),
+                swap_escrow.to_account_info(),
+                swap_source.to_account_info(),
+                swap_destination.to_account_info(),
+                stable_token_account.to_account_info(),
+                swap_pool_mint.to_account_info(),
+                swap_fee_account.to_account_info(),
+                self.token_program.to_account_info(),
+                swap_program.to_account_info(),
+            ],
+            &[escrow_seeds],
+        )?;
+
+        // The swap must take the whole escrow and nothing else from the vault
+        swap_escrow.reload()?;
+        require!(swap_escrow.amount == 0, TradingError::InvalidSwapAccount);
+        self.pool_token_account.reload()?;
+        require!(
+            vault_before.checked_sub(amount) == Some(self.pool_token_account.amount),
+            TradingError::InvalidSwapAccount
+        );
+        token::close_account(CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            CloseAccount {
+                account: swap_escrow.to_account_info(),
+                destination: self.payer.to_account_info(),
+                authority: swap_escrow.to_account_info(),
+            },
+            &[escrow_seeds],
+        ))?;
+
+        // Don't trust the AMM's own guard alone
+        stable_token_account.reload()?;
+        let received = stable_token_account.amount.saturating_sub(balance_before);
+        require!(received >= min_out, TradingError::SlippageExceeded);
+        Ok(received)
+    }
+
+    /// Open the follow-on position for a compounded settlement, staked with
+    /// `amount` that is already in the vault
+    fn compound(&mut self, window_duration: i64, amount: u64) -> Result<()> {
+        let settled = &self.trading_position;
+        let position_type = settled.position_type;
+        let trader = settled.trader;
+        let collateral_mint = settled.collateral_mint;
+        let args = OpenPositionArgs {
+            amount,
+            window_duration,
+            checkpoint: None,
+            // Letting it ride means taking whatever the current index is
+            entry_index_limit: None,
+            client_tag: settled.client_tag,
+            referrer: settled.referrer,
+            payout_recipient: Some(settled.payout_recipient),
+            stop_loss_index: None,
+            take_profit_index: None,
+        };
+
+        let position = self
+            .compound_position
+            .as_mut()
+            .ok_or(TradingError::InvalidCompound)?;
+        position.collateral_mint = collateral_mint;
+        let trader_positions = self.trader_positions.as_mut().map(|trader_positions| {
+            let bump = trader_positions.bump;
+            (trader_positions, bump)
+        });
+        open_position(
+            position,
+            &mut self.momentum_pool,
+            &self.protocol_config,
+            trader_positions,
+            trader,
+            self.allowlist_entry.is_some(),
+            position_type,
+            &args,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct ForceSettleExpired<'info> {
+    pub settle: SettlePosition<'info>,
+    
+    /// CHECK: Receives the position's rent if it is closed; constrained to
+    /// the position's trader
+    #[account(mut, address = settle.trading_position.trader)]
+    pub trader: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CrankSettle<'info> {
+    pub settle: SettlePosition<'info>,
+    
+    /// The keeper's (`settle.payer`) account for the reward, in the position's collateral
+    #[account(
+        mut,
+        constraint = keeper_token_account.mint == settle.trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = keeper_token_account.owner == settle.payer.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub keeper_token_account: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct PartialClose<'info> {
+    #[account(
+        mut,
+        has_one = trader,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub trader: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = user_token_account.owner == trading_position.payout_recipient @ TradingError::InvalidPayoutRecipient
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeInsuranceFund<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + InsuranceFund::LEN,
+        seeds = [b"insurance_fund"],
+        bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+    
+    #[account(constraint = insurance_vault.owner == insurance_fund.key() @ TradingError::InvalidInsuranceVault)]
+    pub insurance_vault: Account<'info, TokenAccount>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    /// The protocol admin, who becomes the fund's authority
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ManageInsurance<'info> {
+    #[account(
+        mut,
+        seeds = [b"insurance_fund"],
+        bump = insurance_fund.bump,
+        has_one = authority,
+        constraint = insurance_fund.vault == insurance_vault.key() @ TradingError::InvalidInsuranceVault
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+    
+    #[account(mut)]
+    pub insurance_vault: Account<'info, TokenAccount>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(mut)]
+    pub authority_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DrawInsurance<'info> {
+    #[account(
+        mut,
+        seeds = [b"insurance_fund"],
+        bump = insurance_fund.bump,
+        has_one = authority,
+        constraint = insurance_fund.vault == insurance_vault.key() @ TradingError::InvalidInsuranceVault
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+    
+    #[account(mut)]
+    pub insurance_vault: Account<'info, TokenAccount>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), insurance_vault.mint.as_ref()],
+        bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    /// CHECK: Older layouts don't deserialize as MomentumPool, so the
+    /// discriminator and authority are checked manually in the handler
+    #[account(mut, owner = crate::ID)]
+    pub momentum_pool: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RollPosition<'info> {
+    #[account(
+        mut,
+        has_one = trader,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    pub trader: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = trader_positions.bump
+    )]
+    pub trader_positions: Option<Account<'info, TraderPositions>>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct MarkPosition<'info> {
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    pub momentum_pool: Account<'info, MomentumPool>,
+}
+
+#[derive(Accounts)]
+pub struct SnapshotExitIndex<'info> {
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"momentum_history", momentum_pool.key().as_ref()], bump)]
+    pub momentum_history: Option<AccountLoader<'info, MomentumHistory>>,
+}
+
+#[derive(Accounts)]
+#[instruction(trader: Pubkey)]
+pub struct AddToAllowlist<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + AllowlistEntry::LEN,
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), trader.as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+    
+    #[account(has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFromAllowlist<'info> {
+    #[account(
+        mut,
+        close = authority,
+        constraint = allowlist_entry.pool == momentum_pool.key() @ TradingError::NotAllowlisted
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+    
+    #[account(has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PoolAdmin<'info> {
+    #[account(mut, has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SweepDust<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), momentum_pool.collateral_mint.as_ref()],
+        bump = momentum_pool.vault_bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = treasury_token_account.mint == momentum_pool.collateral_mint @ TradingError::InvalidTokenAccount
+    )]
+    pub treasury_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct OpenDelegatedPosition<'info> {
+    #[account(
+        init,
+        payer = delegate,
+        space = 8 + TradingPosition::LEN,
+        seeds = [
+            b"position",
+            momentum_pool.key().as_ref(),
+            trader.key().as_ref(),
+            &Clock::get()?.unix_timestamp.to_le_bytes()
+        ],
+        bump
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    /// CHECK: The trader the position is opened for; only its key is used,
+    /// and the delegate approval below is derived from it
+    pub trader: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub delegate: Signer<'info>,
+    
+    #[account(
+        seeds = [
+            b"delegate",
+            momentum_pool.key().as_ref(),
+            trader.key().as_ref(),
+            delegate.key().as_ref()
+        ],
+        bump = delegate_approval.bump
+    )]
+    pub delegate_approval: Account<'info, DelegateApproval>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.owner == trader.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    /// Vault for the deposited mint. Only mints the authority has added have
+    /// one, so this also rejects unsupported collateral.
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), user_token_account.mint.as_ref()],
+        bump,
+        constraint = pool_token_account.mint == user_token_account.mint @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        init_if_needed,
+        payer = delegate,
+        space = 8 + TraderPositions::LEN,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump
+    )]
+    pub trader_positions: Account<'info, TraderPositions>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct ApproveDelegate<'info> {
+    #[account(
+        init,
+        payer = trader,
+        space = 8 + DelegateApproval::LEN,
+        seeds = [
+            b"delegate",
+            momentum_pool.key().as_ref(),
+            trader.key().as_ref(),
+            delegate.as_ref()
+        ],
+        bump
+    )]
+    pub delegate_approval: Account<'info, DelegateApproval>,
+    
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeDelegate<'info> {
+    #[account(mut, close = trader, has_one = trader)]
+    pub delegate_approval: Account<'info, DelegateApproval>,
+    
+    #[account(mut)]
+    pub trader: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RecoverTokens<'info> {
+    #[account(
+        has_one = authority,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = source_token_account.owner == momentum_pool.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub source_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = recipient_token_account.mint == source_token_account.mint @ TradingError::InvalidTokenAccount
+    )]
+    pub recipient_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundPool<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = authority_token_account.mint == momentum_pool.collateral_mint @ TradingError::InvalidTokenAccount
+    )]
+    pub authority_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), momentum_pool.collateral_mint.as_ref()],
+        bump = momentum_pool.vault_bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProtocol<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + ProtocolConfig::LEN,
+        seeds = [b"protocol_config"],
+        bump
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProtocolAdmin<'info> {
+    #[account(mut, seeds = [b"protocol_config"], bump = protocol_config.bump, has_one = admin)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct StakeTokens<'info> {
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + StakeAccount::LEN,
+        seeds = [b"stake", owner.key().as_ref()],
+        bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(
+        address = protocol_config.stake_mint @ TradingError::InvalidStakeMint,
+        constraint = protocol_config.stake_mint != Pubkey::default() @ TradingError::StakingDisabled
+    )]
+    pub stake_mint: Account<'info, Mint>,
+    
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [b"stake_vault", stake_mint.key().as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = protocol_config
+    )]
+    pub stake_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == stake_mint.key() @ TradingError::InvalidTokenAccount,
+        constraint = owner_token_account.owner == owner.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeTokens<'info> {
+    #[account(
+        mut,
+        seeds = [b"stake", owner.key().as_ref()],
+        bump = stake_account.bump,
+        has_one = owner
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    /// Vault of the mint the tokens were staked in, which may since have
+    /// been replaced as the stake mint
+    #[account(
+        mut,
+        seeds = [b"stake_vault", stake_account.mint.as_ref()],
+        bump
+    )]
+    pub stake_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == stake_account.mint @ TradingError::InvalidTokenAccount,
+        constraint = owner_token_account.owner == owner.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+    
+    pub owner: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct StakeOracle<'info> {
+    #[account(
+        init_if_needed,
+        payer = oracle,
+        space = 8 + OracleStake::LEN,
+        seeds = [b"oracle_stake", oracle.key().as_ref()],
+        bump
+    )]
+    pub oracle_stake: Account<'info, OracleStake>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(
+        address = protocol_config.stake_mint @ TradingError::InvalidStakeMint,
+        constraint = protocol_config.stake_mint != Pubkey::default() @ TradingError::StakingDisabled
+    )]
+    pub stake_mint: Account<'info, Mint>,
+    
+    #[account(
+        init_if_needed,
+        payer = oracle,
+        seeds = [b"oracle_stake_vault", stake_mint.key().as_ref()],
+        bump,
+        token::mint = stake_mint,
+        token::authority = protocol_config
+    )]
+    pub oracle_stake_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = oracle_token_account.mint == stake_mint.key() @ TradingError::InvalidTokenAccount,
+        constraint = oracle_token_account.owner == oracle.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub oracle_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub oracle: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UnstakeOracle<'info> {
+    #[account(
+        mut,
+        seeds = [b"oracle_stake", oracle.key().as_ref()],
+        bump = oracle_stake.bump,
+        has_one = oracle
+    )]
+    pub oracle_stake: Account<'info, OracleStake>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(
+        mut,
+        seeds = [b"oracle_stake_vault", oracle_stake.mint.as_ref()],
+        bump
+    )]
+    pub oracle_stake_vault: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = oracle_token_account.mint == oracle_stake.mint @ TradingError::InvalidTokenAccount,
+        constraint = oracle_token_account.owner == oracle.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub oracle_token_account: Account<'info, TokenAccount>,
+    
+    pub oracle: Signer<'info>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SlashOracle<'info> {
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"oracle_stake", oracle_stake.oracle.as_ref()],
+        bump = oracle_stake.bump
+    )]
+    pub oracle_stake: Account<'info, OracleStake>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(mut, address = oracle_stake.mint @ TradingError::InvalidStakeMint)]
+    pub stake_mint: Account<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"oracle_stake_vault", oracle_stake.mint.as_ref()],
+        bump
+    )]
+    pub oracle_stake_vault: Account<'info, TokenAccount>,
+    
+    pub disputer: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = disputer_token_account.mint == oracle_stake.mint @ TradingError::InvalidTokenAccount,
+        constraint = disputer_token_account.owner == disputer.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub disputer_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut, seeds = [b"insurance_fund"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+    
+    #[account(mut, address = insurance_fund.vault @ TradingError::InvalidInsuranceVault)]
+    pub insurance_vault: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimPayout<'info> {
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = user_token_account.owner == trading_position.payout_recipient @ TradingError::InvalidPayoutRecipient
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PayoutHold<'info> {
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReportSolvency<'info> {
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        seeds = [b"vault", momentum_pool.key().as_ref(), pool_token_account.mint.as_ref()],
+        bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ShortenWindow<'info> {
+    #[account(
+        mut,
+        has_one = trader,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub trader: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolConfig<'info> {
+    #[account(mut, has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub authority: Signer<'info>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolStatus<'info> {
+    #[account(mut, has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    /// Required to close a league pool, so it leaves the member list
+    #[account(
+        mut,
+        seeds = [b"league", momentum_pool.league_id.to_le_bytes().as_ref()],
+        bump = league_registry.bump
+    )]
+    pub league_registry: Option<Account<'info, LeagueRegistry>>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(league_id: u64)]
+pub struct InitializeLeague<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + LeagueRegistry::LEN,
+        seeds = [b"league", league_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub league_registry: Account<'info, LeagueRegistry>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LeagueAdmin<'info> {
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"league", league_registry.league_id.to_le_bytes().as_ref()],
+        bump = league_registry.bump
+    )]
+    pub league_registry: Account<'info, LeagueRegistry>,
+    
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReportLeague<'info> {
+    #[account(
+        seeds = [b"league", league_registry.league_id.to_le_bytes().as_ref()],
+        bump = league_registry.bump
+    )]
+    pub league_registry: Account<'info, LeagueRegistry>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePositionEarly<'info> {
+    #[account(
+        mut,
+        has_one = trader,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub trader: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = user_token_account.owner == trading_position.payout_recipient @ TradingError::InvalidPayoutRecipient
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = trader_positions.bump
+    )]
+    pub trader_positions: Option<Account<'info, TraderPositions>>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_stats", trader.key().as_ref()],
+        bump = trader_stats.bump
+    )]
+    pub trader_stats: Option<Account<'info, TraderStats>>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SettlePositionsBatch<'info> {
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), pool_token_account.mint.as_ref()],
+        bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    /// Receives the batch's slice of the fees, as in settle_position
+    #[account(
+        mut,
+        seeds = [b"insurance_fund"],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Option<Account<'info, InsuranceFund>>,
+    
+    #[account(mut)]
+    pub insurance_vault: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteExitOrder<'info> {
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = user_token_account.owner == trading_position.payout_recipient @ TradingError::InvalidPayoutRecipient
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SetTakeProfit<'info> {
+    #[account(mut, has_one = trader)]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    pub trader: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: u64)]
+pub struct PlaceLimitOrder<'info> {
+    #[account(
+        init,
+        payer = trader,
+        space = 8 + PendingOrder::LEN,
+        seeds = [
+            b"order",
+            momentum_pool.key().as_ref(),
+            trader.key().as_ref(),
+            &order_id.to_le_bytes()
+        ],
+        bump
+    )]
+    pub pending_order: Account<'info, PendingOrder>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), user_token_account.mint.as_ref()],
+        bump,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelLimitOrder<'info> {
+    #[account(
+        mut,
+        close = trader,
+        has_one = trader,
+        constraint = pending_order.pool == momentum_pool.key() @ TradingError::PositionPoolMismatch
+    )]
+    pub pending_order: Account<'info, PendingOrder>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == pending_order.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = user_token_account.owner == trader.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), pending_order.collateral_mint.as_ref()],
+        bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TriggerOrder<'info> {
+    #[account(
+        mut,
+        close = keeper,
+        constraint = pending_order.pool == momentum_pool.key() @ TradingError::PositionPoolMismatch
+    )]
+    pub pending_order: Account<'info, PendingOrder>,
+    
+    #[account(
+        init,
+        payer = keeper,
+        space = 8 + TradingPosition::LEN,
+        seeds = [
+            b"position",
+            momentum_pool.key().as_ref(),
+            pending_order.trader.as_ref(),
+            &Clock::get()?.unix_timestamp.to_le_bytes()
+        ],
+        bump
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), pending_order.trader.as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), pending_order.trader.as_ref()],
+        bump = trader_positions.bump
+    )]
+    pub trader_positions: Option<Account<'info, TraderPositions>>,
+    
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ManageMargin<'info> {
+    #[account(
+        init_if_needed,
+        payer = trader,
+        space = 8 + MarginAccount::LEN,
+        seeds = [b"margin", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == momentum_pool.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = user_token_account.owner == trader.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), momentum_pool.collateral_mint.as_ref()],
+        bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenLeveragedPosition<'info> {
+    #[account(
+        init,
+        payer = trader,
+        space = 8 + TradingPosition::LEN,
+        seeds = [
+            b"position",
+            momentum_pool.key().as_ref(),
+            trader.key().as_ref(),
+            &Clock::get()?.unix_timestamp.to_le_bytes()
+        ],
+        bump
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(
+        mut,
+        has_one = trader,
+        seeds = [b"margin", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = margin_account.bump
+    )]
+    pub margin_account: Account<'info, MarginAccount>,
+    
+    #[account(mut)]
+    pub trader: Signer<'info>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = trader_positions.bump
+    )]
+    pub trader_positions: Option<Account<'info, TraderPositions>>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct LiquidatePosition<'info> {
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    pub liquidator: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = liquidator_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = liquidator_token_account.owner == liquidator.key() @ TradingError::InvalidTokenAccount
+    )]
+    pub liquidator_token_account: Account<'info, TokenAccount>,
+    
+    /// Receives the remainder when the fund's vault holds the position's
+    /// collateral; otherwise omitted, and the pool keeps it
+    #[account(
+        mut,
+        seeds = [b"insurance_fund"],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Option<Account<'info, InsuranceFund>>,
+    
+    /// Checked against `insurance_fund` and the position's collateral when
+    /// liquidating
+    #[account(mut)]
+    pub insurance_vault: Option<Account<'info, TokenAccount>>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct AccrueFunding<'info> {
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+}
+
+#[derive(Accounts)]
+pub struct IncreasePosition<'info> {
+    #[account(
+        mut,
+        has_one = trader,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    pub trader: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = user_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount
+    )]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), trading_position.collateral_mint.as_ref()],
+        bump,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TransferPosition<'info> {
+    #[account(
+        mut,
+        has_one = trader,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    pub trader: Signer<'info>,
+    
+    /// Accepts the position by signing
+    #[account(mut)]
+    pub new_trader: Signer<'info>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), new_trader.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), trader.key().as_ref()],
+        bump = from_positions.bump
+    )]
+    pub from_positions: Option<Account<'info, TraderPositions>>,
+    
+    #[account(
+        init_if_needed,
+        payer = new_trader,
+        space = 8 + TraderPositions::LEN,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), new_trader.key().as_ref()],
+        bump
+    )]
+    pub to_positions: Account<'info, TraderPositions>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenTokenizedPosition<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + TradingPosition::LEN,
+        seeds = [
+            b"position",
+            momentum_pool.key().as_ref(),
+            user.key().as_ref(),
+            &Clock::get()?.unix_timestamp.to_le_bytes()
+        ],
+        bump
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(
+        mut,
+        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
+        bump
+    )]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(seeds = [b"protocol_config"], bump = protocol_config.bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"vault", momentum_pool.key().as_ref(), user_token_account.mint.as_ref()],
+        bump,
+        constraint = pool_token_account.mint == user_token_account.mint @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.key() != user_token_account.key() @ TradingError::InvalidTokenAccount,
+        constraint = pool_token_account.owner != user_token_account.owner @ TradingError::InvalidTokenAccount
+    )]
+    pub pool_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), user.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"position_mint", trading_position.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = momentum_pool
+    )]
+    pub position_mint: Account<'info, Mint>,
+    
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = position_mint,
+        associated_token::authority = user
+    )]
+    pub position_token_account: Account<'info, TokenAccount>,
+    
+    /// CHECK: The position mint's metadata PDA, created by the Token
+    /// Metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", TOKEN_METADATA_PROGRAM_ID.as_ref(), position_mint.key().as_ref()],
+        bump,
+        seeds::program = TOKEN_METADATA_PROGRAM_ID
+    )]
+    pub metadata: UncheckedAccount<'info>,
+    
+    /// CHECK: Address-constrained to the Token Metadata program
+    #[account(executable, address = TOKEN_METADATA_PROGRAM_ID)]
+    pub token_metadata_program: UncheckedAccount<'info>,
+    
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ListPositionForSale<'info> {
+    #[account(
+        mut,
+        has_one = trader,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        init,
+        payer = trader,
+        space = 8 + Listing::LEN,
+        seeds = [b"listing", trading_position.key().as_ref()],
+        bump
+    )]
+    pub listing: Account<'info, Listing>,
+    
     #[account(mut)]
+    pub trader: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelListing<'info> {
+    #[account(
+        mut,
+        close = seller,
+        has_one = seller,
+        seeds = [b"listing", trading_position.key().as_ref()],
+        bump = listing.bump
+    )]
+    pub listing: Account<'info, Listing>,
+    
+    #[account(mut)]
+    pub trading_position: Account<'info, TradingPosition>,
+    
+    #[account(mut)]
+    pub seller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct BuyPosition<'info> {
+    #[account(
+        mut,
+        close = seller,
+        seeds = [b"listing", trading_position.key().as_ref()],
+        bump = listing.bump
+    )]
+    pub listing: Account<'info, Listing>,
+    
+    #[account(
+        mut,
+        constraint = trading_position.pool == momentum_pool.key()
+    )]
     pub trading_position: Account<'info, TradingPosition>,
     
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    /// CHECK: Receives the listing's rent; address-constrained to the seller
+    #[account(mut, address = listing.seller)]
+    pub seller: UncheckedAccount<'info>,
+    
+    #[account(
+        mut,
+        constraint = seller_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount,
+        constraint = seller_token_account.owner == listing.seller @ TradingError::InvalidTokenAccount
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+    
+    #[account(
+        mut,
+        constraint = buyer_token_account.mint == trading_position.collateral_mint @ TradingError::InvalidTokenAccount
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"allowlist", momentum_pool.key().as_ref(), buyer.key().as_ref()],
+        bump = allowlist_entry.bump
+    )]
+    pub allowlist_entry: Option<Account<'info, AllowlistEntry>>,
+    
+    #[account(
+        mut,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), listing.seller.as_ref()],
+        bump = seller_positions.bump
+    )]
+    pub seller_positions: Option<Account<'info, TraderPositions>>,
+    
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + TraderPositions::LEN,
+        seeds = [b"trader_positions", momentum_pool.key().as_ref(), buyer.key().as_ref()],
+        bump
+    )]
+    pub buyer_positions: Account<'info, TraderPositions>,
+    
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeMomentumHistory<'info> {
+    #[account(has_one = authority)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + MomentumHistory::LEN,
+        seeds = [b"momentum_history", momentum_pool.key().as_ref()],
+        bump
+    )]
+    pub momentum_history: AccountLoader<'info, MomentumHistory>,
+    
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMomentumFromSwitchboard<'info> {
+    #[account(mut)]
+    pub momentum_pool: Account<'info, MomentumPool>,
+    
+    /// CHECK: The pool's Switchboard aggregator; owner-checked here and
+    /// parsed by SwitchboardRound::read
     #[account(
-        seeds = [b"momentum_pool", momentum_pool.match_id.as_bytes()],
-        bump
+        owner = SWITCHBOARD_PROGRAM_ID @ TradingError::InvalidSwitchboardFeed,
+        address = momentum_pool.switchboard_feed @ TradingError::InvalidSwitchboardFeed
     )]
-    pub momentum_pool: Account<'info, MomentumPool>,
-    
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
+    pub switchboard_feed: UncheckedAccount<'info>,
     
+    #[account(mut, seeds = [b"momentum_history", momentum_pool.key().as_ref()], bump)]
+    pub momentum_history: Option<AccountLoader<'info, MomentumHistory>>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshCollateralPrice<'info> {
     #[account(mut)]
-    pub pool_token_account: Account<'info, TokenAccount>,
+    pub momentum_pool: Account<'info, MomentumPool>,
     
-    pub token_program: Program<'info, Token>,
+    /// CHECK: The pool's Pyth price account; owner-checked here and parsed
+    /// by PythPrice::read
+    #[account(
+        owner = PYTH_PROGRAM_ID @ TradingError::InvalidPriceFeed,
+        address = momentum_pool.price_feed @ TradingError::InvalidPriceFeed
+    )]
+    pub price_feed: UncheckedAccount<'info>,
+}
+
+#[account]
+#[derive(Default)]
+pub struct MomentumPool {
+    pub authority: Pubkey,
+    pub match_id: String,
+    pub start_time: i64,
+    pub home_team: String,
+    pub away_team: String,
+    pub total_long_volume: u64,
+    pub total_short_volume: u64,
+    pub current_momentum_index: u8,
+    pub status: PoolStatus,
+    pub created_at: i64,
+    pub last_update: i64,
+    pub pending_commitment: Option<[u8; 32]>,
+    pub commitment_time: i64,
+    pub insurance_drawn: u64,
+    pub config: PoolConfig,
+    pub version: u8,
+    pub previous_momentum_index: u8,
+    pub previous_update: i64,
+    pub checkpoints: [Option<CheckpointSnapshot>; MatchCheckpoint::COUNT],
+    pub collateral_mint: Pubkey,
+    pub vault_bump: u8,
+    pub last_settlement_time: i64,
+    pub final_momentum_index: u8,
+    pub finalized_at: i64,
+    pub index_scale_max: u16,
+    pub outstanding_liability: u64,
+    pub oracle_pubkey: Pubkey,
+    pub open_positions: u32,
+    pub oracle_confidence: u8,
+    pub momentum_samples: [u8; MOMENTUM_SAMPLE_COUNT],
+    pub sample_times: [i64; MOMENTUM_SAMPLE_COUNT],
+    pub sample_cursor: u8,
+    pub sample_count: u8,
+    pub seeded_liquidity: u64,
+    pub oracles: [OracleEntry; MAX_ORACLES],
+    pub kind: PoolKind,
+    pub epoch: u32,
+    pub final_submissions: [Option<FinalSubmission>; MAX_ORACLES],
+    /// League registry this pool belongs to (0 for none)
+    pub league_id: u64,
+    pub total_straddle_volume: u64,
+    /// Cumulative funding paid per unit of long stake, scaled by
+    /// FUNDING_INDEX_SCALE; negative once longs have received more than paid
+    pub long_funding_index: i64,
+    /// Cumulative funding paid per unit of short stake, likewise
+    pub short_funding_index: i64,
+    /// Start of the next funding interval to accrue
+    pub last_funding_time: i64,
+    pub settlement_mode: SettlementMode,
+    /// Long and short pots when the pool was finalized, which a pari-mutuel
+    /// pool's winners share
+    pub final_long_volume: u64,
+    pub final_short_volume: u64,
+    /// Protocol revenue retained in the vault: profit and early-exit fees net
+    /// of referral and insurance shares
+    pub fees_collected: u64,
+    pub fees_withdrawn: u64,
+    /// Protocol limits as of the pool's last config update
+    pub protocol_limits: ProtocolLimits,
+    /// What the vault kept of losing stakes, less the winnings (and the fees
+    /// on them) paid out of it since
+    pub winners_pot: u64,
+    /// Every stake lost to the pool, for reconciling the vault
+    pub total_losses: u64,
+    /// Each oracle slot's reading towards the next aggregated update
+    pub oracle_readings: [Option<OracleReading>; MAX_ORACLES],
+    /// Where index updates come from, fixed at creation
+    pub oracle_source: OracleSource,
+    /// Aggregator read by Switchboard-fed pools
+    pub switchboard_feed: Pubkey,
+    /// Sequence number of the last relayed, oracle-signed update applied
+    pub oracle_sequence: u64,
+    /// Status to return to when a halted pool resumes
+    pub status_before_halt: PoolStatus,
+    /// Pyth price account for the collateral in USD; default when the
+    /// collateral is itself a stablecoin
+    pub price_feed: Pubkey,
+    /// Last accepted collateral price: `collateral_price` * 10^`collateral_price_expo`
+    /// USD, published at `collateral_price_time`
+    pub collateral_price: i64,
+    pub collateral_price_expo: i32,
+    pub collateral_price_time: i64,
+}
+
+impl MomentumPool {
+    /// Size of every field except the variable-length metadata strings
+    pub const FIXED_LEN: usize = 32 + 8 + 8 + 8 + 1 + 1 + 8 + 8 + 33 + 8 + 8 + PoolConfig::LEN + 1 + 1 + 8
+        + MatchCheckpoint::COUNT * (1 + CheckpointSnapshot::LEN) + 32 + 1 + 8 + 1 + 8 + 2 + 8 + 32 + 4
+        + 1 + MOMENTUM_SAMPLE_COUNT * (1 + 8) + 1 + 1 + 8 + MAX_ORACLES * OracleEntry::LEN
+        + 1 + 4 + MAX_ORACLES * (1 + FinalSubmission::LEN) + 8 + 8 + 8 + 8 + 8 + 1 + 8 + 8 + 8 + 8 + ProtocolLimits::LEN
+        + 8 + 8 + MAX_ORACLES * (1 + OracleReading::LEN) + 1 + 32
+        + 8 + 1 + 32 + 8 + 4 + 8;
+
+    /// Exact account size, excluding the discriminator, for the given metadata.
+    /// Any instruction that grows a string field must realloc to this size.
+    pub fn space(match_id: &str, home_team: &str, away_team: &str) -> usize {
+        Self::FIXED_LEN
+            + 4 + match_id.len()
+            + 4 + home_team.len()
+            + 4 + away_team.len()
+    }
+
+    /// Copy of the pool with every optional field set, i.e. at the largest
+    /// serialized size its metadata allows
+    pub fn fully_populated(&self) -> Self {
+        let mut pool = self.clone();
+        pool.pending_commitment = Some([0; 32]);
+        pool.checkpoints = std::array::from_fn(|_| Some(CheckpointSnapshot::default()));
+        pool.final_submissions = [Some(FinalSubmission::default()); MAX_ORACLES];
+        pool.oracle_readings = [Some(OracleReading::default()); MAX_ORACLES];
+        pool
+    }
+
+    /// Whether an operator may move the pool to `status`: Active <->
+    /// ClosingToNewPositions -> Closed, or Finalized -> Closed. Finalization
+    /// snapshots the final index, so it has its own instruction; halting and
+    /// resuming belong to the circuit breaker.
+    pub fn can_transition_to(&self, status: PoolStatus) -> bool {
+        self.status != PoolStatus::Closed
+            && self.status != status
+            && status != PoolStatus::Finalized
+            && self.status != PoolStatus::Halted
+            && status != PoolStatus::Halted
+            && (self.status != PoolStatus::Finalized || status == PoolStatus::Closed)
+    }
+
+    /// Run an update to `new_index` at `now` past the circuit breaker. Updates
+    /// inside the cooldown are rejected. One moving the index by more than
+    /// `max_index_delta` is rejected too, or, with `halt_on_breach`, halts
+    /// the pool instead; then this returns false and the caller must drop
+    /// the update but let the instruction succeed, so the halt sticks.
+    pub fn check_circuit_breaker(&mut self, new_index: u8, now: i64) -> Result<bool> {
+        require!(self.status != PoolStatus::Halted, TradingError::PoolHalted);
+        require!(
+            self.config.update_cooldown == 0 || now - self.last_update >= self.config.update_cooldown,
+            TradingError::UpdateCooldown
+        );
+        let delta = new_index.abs_diff(self.current_momentum_index);
+        if self.config.max_index_delta == 0 || delta <= self.config.max_index_delta {
+            return Ok(true);
+        }
+        require!(self.config.halt_on_breach, TradingError::IndexDeltaExceeded);
+
+        self.status_before_halt = self.status;
+        self.status = PoolStatus::Halted;
+        msg!(
+            "Circuit breaker tripped: {} -> {} exceeds {} points",
+            self.current_momentum_index,
+            new_index,
+            self.config.max_index_delta
+        );
+        emit!(CircuitBreakerTrippedEvent {
+            match_id: self.match_id.clone(),
+            current_index: self.current_momentum_index,
+            rejected_index: new_index,
+            timestamp: now,
+        });
+        Ok(false)
+    }
+
+    /// Whether the oracle has been silent for longer than the heartbeat timeout
+    pub fn is_heartbeat_lost(&self, now: i64) -> bool {
+        self.config.heartbeat_timeout > 0
+            && now - self.last_update > self.config.heartbeat_timeout
+    }
+
+    /// Whether the index is older than `max_oracle_staleness`, so trading or
+    /// settling against it is refused. A finalized pool's index is final,
+    /// never stale.
+    pub fn is_oracle_stale(&self, now: i64) -> bool {
+        self.config.max_oracle_staleness > 0
+            && self.status != PoolStatus::Finalized
+            && now - self.last_update > self.config.max_oracle_staleness
+    }
+
+    /// Earliest time a position whose window ends at `window_end_time` may
+    /// settle, leaving room for the oracle's final update to land
+    pub fn settlement_time(&self, window_end_time: i64) -> i64 {
+        window_end_time + self.config.settlement_grace_period
+    }
+
+    /// Whether a window shortened at `now` to end at `window_end_time` would
+    /// still settle against an index nobody knows yet: the end must be past
+    /// the grace period, and at least a second past `now` without one
+    pub fn is_unknown_exit(&self, window_end_time: i64, now: i64) -> bool {
+        window_end_time > now.saturating_add(self.config.settlement_grace_period.max(1))
+    }
+
+    /// Whether a position whose window ends at `window_end_time` has missed
+    /// its claim deadline and forfeits any winnings
+    pub fn is_claim_expired(&self, window_end_time: i64, now: i64) -> bool {
+        // Pari-mutuel positions can't settle before finalization, however
+        // early their windows end
+        let claimable_from = match self.settlement_mode {
+            SettlementMode::FixedMultiplier => window_end_time,
+            SettlementMode::PariMutuel => window_end_time.max(self.finalized_at),
+        };
+        self.config.claim_period > 0 && now > claimable_from + self.config.claim_period
+    }
+
+    /// Momentum index in effect at `timestamp`: that of the last update
+    /// stamped at or before it, if still held in the sample buffer. An update
+    /// stamped exactly at `timestamp` counts.
+    pub fn index_at(&self, timestamp: i64) -> Option<u8> {
+        if self.last_update <= timestamp {
+            return Some(self.current_momentum_index);
+        }
+        let found = (1..=self.sample_count as usize)
+            .map(|back| (self.sample_cursor as usize + MOMENTUM_SAMPLE_COUNT - back) % MOMENTUM_SAMPLE_COUNT)
+            .find(|&slot| self.sample_times[slot] <= timestamp)
+            .map(|slot| self.momentum_samples[slot]);
+        // Pools migrated without samples still know the previous update
+        found.or_else(|| (self.previous_update <= timestamp).then_some(self.previous_momentum_index))
+    }
+
+    /// Index a window settling at `settlement_time` exits at: the average
+    /// over the `twap_seconds` before it when the pool settles on a TWAP,
+    /// otherwise the last update at or before it. `history` supplies TWAPs
+    /// and updates the sample buffer no longer holds.
+    pub fn settlement_index_at(&self, settlement_time: i64, history: Option<&MomentumHistory>) -> Option<u8> {
+        if self.config.twap_seconds > 0 {
+            return history?.twap(settlement_time - self.config.twap_seconds, settlement_time);
+        }
+        self.index_at(settlement_time)
+            .or_else(|| history?.index_at(settlement_time))
+    }
+
+    /// Index `position` settles against at `now`, or why it can't settle yet.
+    /// `history` is the pool's momentum history account, if passed.
+    pub fn exit_index(
+        &self,
+        position: &TradingPosition,
+        now: i64,
+        history: Option<&MomentumHistory>,
+    ) -> Result<u8> {
+        let finalized = self.status == PoolStatus::Finalized;
+        // Pari-mutuel pots are only known once the match is over
+        if self.settlement_mode == SettlementMode::PariMutuel {
+            require!(finalized, TradingError::WindowNotEnded);
+            return Ok(self.final_momentum_index);
+        }
+        let exit_index = match position.checkpoint {
+            Some(checkpoint) => match self.checkpoint_snapshot(checkpoint) {
+                Some(snapshot) => snapshot.momentum_index,
+                // The match was finalized without reaching this checkpoint
+                None if finalized => self.final_momentum_index,
+                None => return err!(TradingError::CheckpointNotReached),
+            },
+            // Once finalized, windows that ended before the final whistle
+            // still settle against the index in effect when they ended, if
+            // the buffer holds it; later windows, and those whose update has
+            // been overwritten, settle against the final index. No update
+            // lands after finalization, so neither depends on when
+            // settlement does.
+            None if finalized => {
+                let settlement_time = self.settlement_time(position.window_end_time);
+                position
+                    .exit_snapshot_index
+                    .or_else(|| {
+                        (settlement_time < self.finalized_at)
+                            .then(|| self.settlement_index_at(settlement_time, history))
+                            .flatten()
+                    })
+                    .unwrap_or(self.final_momentum_index)
+            }
+            None => {
+                require!(now >= position.window_end_time, TradingError::WindowNotEnded);
+                
+                // Strictly after: an update stamped in the settlement second
+                // could otherwise land after this settle, making the exit
+                // index depend on transaction ordering
+                let settlement_time = self.settlement_time(position.window_end_time);
+                require!(now > settlement_time, TradingError::InGracePeriod);
+                
+                // Settle against the last update stamped at or before the end of
+                // the grace period, so momentum drifting afterwards can't be
+                // cherry-picked by settling late. If that update has left the
+                // sample buffer the position waits for finalization instead.
+                position
+                    .exit_snapshot_index
+                    .or_else(|| self.settlement_index_at(settlement_time, history))
+                    .ok_or(TradingError::SnapshotUnavailable)?
+            }
+        };
+        Ok(exit_index)
+    }
+
+    /// Index a new position on `position_type`'s side enters at: the current
+    /// index moved against it by `spread_points`, clamped to the index range.
+    /// Also returns the spread actually applied after clamping.
+    pub fn entry_index(&self, position_type: &PositionType) -> (u8, u8) {
+        let current = self.current_momentum_index;
+        let spread = self.config.spread_points;
+        let entry = match position_type {
+            PositionType::Long => {
+                let max = self.index_scale_max.min(u8::MAX as u16) as u8;
+                current.saturating_add(spread).min(max).max(current)
+            }
+            PositionType::Short => current.saturating_sub(spread),
+            PositionType::Straddle => current,
+        };
+        (entry, entry.abs_diff(current))
+    }
+
+    /// Profit fee for a new position on `position_type`'s side: the base fee
+    /// shifted by up to `skew_fee_bps`, proportional to how lopsided the pool
+    /// already is. Joining the crowded side costs more, balancing it costs less.
+    /// Straddles pay the base fee plus `straddle_fee_bps` instead.
+    pub fn entry_fee_bps(&self, position_type: &PositionType) -> u16 {
+        if *position_type == PositionType::Straddle {
+            return self.protocol_limits.profit_fee_bps + self.config.straddle_fee_bps;
+        }
+        let long = self.total_long_volume as i128;
+        let short = self.total_short_volume as i128;
+        let total = long + short;
+        if total == 0 {
+            return self.protocol_limits.profit_fee_bps;
+        }
+
+        // Skew toward this position's side, in bps of total volume
+        let side_skew = match position_type {
+            PositionType::Long => (long - short) * 10_000 / total,
+            PositionType::Short => (short - long) * 10_000 / total,
+            PositionType::Straddle => 0,
+        };
+        let adjustment = side_skew * self.config.skew_fee_bps as i128 / 10_000;
+        (self.protocol_limits.profit_fee_bps as i128 + adjustment).clamp(0, 10_000) as u16
+    }
+
+    /// Stake currently open on both sides of the pool and in straddles
+    pub fn open_interest(&self) -> u64 {
+        self.total_long_volume + self.total_short_volume + self.total_straddle_volume
+    }
+
+    /// Whether `liquidity` has fallen below the configured share of open interest
+    pub fn is_liquidity_low(&self, liquidity: u64) -> bool {
+        let threshold_bps = self.config.low_liquidity_threshold_bps as u128;
+        threshold_bps > 0
+            && (liquidity as u128) * 10_000 < (self.open_interest() as u128) * threshold_bps
+    }
+
+    /// `amount` of collateral in USD at the cached collateral price, in the
+    /// collateral's base units, or None if the pool has no price no older
+    /// than `price_max_age`
+    pub fn usd_value(&self, amount: i64, now: i64) -> Option<i64> {
+        if self.collateral_price <= 0
+            || (self.config.price_max_age > 0 && now - self.collateral_price_time > self.config.price_max_age)
+        {
+            return None;
+        }
+        let value = amount as i128 * self.collateral_price as i128;
+        let scale = 10i128.checked_pow(self.collateral_price_expo.unsigned_abs())?;
+        let value = if self.collateral_price_expo < 0 {
+            value / scale
+        } else {
+            value.checked_mul(scale)?
+        };
+        i64::try_from(value).ok()
+    }
+
+    /// Whether a new stake of `amount` fits under the open interest cap
+    pub fn has_capacity_for(&self, amount: u64) -> bool {
+        self.config.max_open_interest == 0
+            || self.open_interest() + amount <= self.config.max_open_interest
+    }
+
+    /// Whether another position may be opened under `config.max_positions`
+    pub fn has_position_slot(&self) -> bool {
+        self.config.max_positions == 0 || self.open_positions < self.config.max_positions
+    }
+
+    /// Largest payout `position` could receive, i.e. with momentum moving all
+    /// the way to the end of the scale in its favor
+    pub fn max_liability(&self, position: &TradingPosition) -> Result<u64> {
+        // Winners are paid from losing stakes already in the vault
+        if self.settlement_mode == SettlementMode::PariMutuel {
+            return Ok(position.stake());
+        }
+        let top = position.index_scale_max().min(u8::MAX as u16) as u8;
+        let best_exit = match position.position_type {
+            PositionType::Long => top,
+            PositionType::Short => 0,
+            // Whichever end of the scale is farther from the entry
+            PositionType::Straddle if position.entry_momentum_index <= top / 2 => top,
+            PositionType::Straddle => 0,
+        };
+        let config = PayoutConfig {
+            max_profit: self.payout_multiplier_cap(position),
+            ..self.config.payout_config(position)
+        };
+        let (payout, _) = position_payout(position, best_exit, &config)?;
+        Ok(payout.max(position.stake()))
+    }
+
+    /// PnL `position` would realize if it settled at the current index
+    pub fn unrealized_pnl(&self, position: &TradingPosition) -> Result<i64> {
+        let (payout, _) = position_payout(
+            position,
+            self.current_momentum_index,
+            &self.payout_config(position),
+        )?;
+        Ok(payout as i64 - position.stake() as i64)
+    }
+
+    /// Equity of a leveraged `position` at the current index, its margin plus
+    /// unrealized PnL, as bps of its notional
+    pub fn margin_ratio_bps(&self, position: &TradingPosition) -> Result<u64> {
+        let equity = (position.stake() as i64 + self.unrealized_pnl(position)?).max(0) as u64;
+        mul_div(equity, 10_000, position.amount, RoundingMode::Truncate)
+    }
+
+    /// Add `amount` of newly opened `position_type` stake to the open volume
+    pub fn add_volume(&mut self, position_type: PositionType, amount: u64) -> Result<()> {
+        let volume = match position_type {
+            PositionType::Long => &mut self.total_long_volume,
+            PositionType::Short => &mut self.total_short_volume,
+            PositionType::Straddle => &mut self.total_straddle_volume,
+        };
+        *volume = volume.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Take `amount` of closed `position_type` stake out of the open volume
+    pub fn remove_volume(&mut self, position_type: PositionType, amount: u64) -> Result<()> {
+        let volume = match position_type {
+            PositionType::Long => &mut self.total_long_volume,
+            PositionType::Short => &mut self.total_short_volume,
+            PositionType::Straddle => &mut self.total_straddle_volume,
+        };
+        *volume = volume.checked_sub(amount).ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Swap a position's `old` maximum liability for `new` in the pool's
+    /// outstanding total
+    pub fn replace_liability(&mut self, old: u64, new: u64) -> Result<()> {
+        self.outstanding_liability = self
+            .outstanding_liability
+            .checked_add(new)
+            .and_then(|liability| liability.checked_sub(old))
+            .ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Whether a leveraged `position` has fallen below its maintenance margin
+    pub fn is_undercollateralized(&self, position: &TradingPosition) -> Result<bool> {
+        Ok(position.is_leveraged()
+            && self.margin_ratio_bps(position)? < position.maintenance_margin_bps as u64)
+    }
+
+    pub fn checkpoint_snapshot(&self, checkpoint: MatchCheckpoint) -> Option<&CheckpointSnapshot> {
+        self.checkpoints[checkpoint as usize].as_ref()
+    }
+
+    /// Move the index to `new_index` as of `now`, keeping the previous value
+    /// for grace-period lookups and corrections. Returns the old index.
+    pub fn apply_momentum_update(&mut self, new_index: u8, now: i64, confidence: Option<u8>) -> u8 {
+        let old_index = self.current_momentum_index;
+        self.previous_momentum_index = old_index;
+        self.previous_update = self.last_update;
+        self.current_momentum_index = new_index;
+        self.last_update = now;
+        self.oracle_confidence = confidence.unwrap_or(FULL_CONFIDENCE);
+        self.record_sample(new_index, now);
+        old_index
+    }
+
+    /// Append an update to the momentum sample ring buffer
+    pub fn record_sample(&mut self, index: u8, timestamp: i64) {
+        self.momentum_samples[self.sample_cursor as usize] = index;
+        self.sample_times[self.sample_cursor as usize] = timestamp;
+        self.sample_cursor = ((self.sample_cursor as usize + 1) % MOMENTUM_SAMPLE_COUNT) as u8;
+        self.sample_count = (self.sample_count + 1).min(MOMENTUM_SAMPLE_COUNT as u8);
+    }
+
+    /// Replace the latest sample after a correction, or drop it if the
+    /// update was voided outright
+    pub fn amend_latest_sample(&mut self, corrected_index: Option<u8>) {
+        if self.sample_count == 0 {
+            return;
+        }
+        let latest = (self.sample_cursor as usize + MOMENTUM_SAMPLE_COUNT - 1) % MOMENTUM_SAMPLE_COUNT;
+        match corrected_index {
+            Some(index) => self.momentum_samples[latest] = index,
+            None => {
+                self.sample_cursor = latest as u8;
+                self.sample_count -= 1;
+            }
+        }
+    }
+
+    /// Sum of absolute steps between consecutive samples, and the number of
+    /// steps; their ratio is the mean per-update move in index points
+    fn volatility(&self) -> (u64, u64) {
+        let count = self.sample_count as usize;
+        let oldest = (self.sample_cursor as usize + MOMENTUM_SAMPLE_COUNT - count) % MOMENTUM_SAMPLE_COUNT;
+        let total = (1..count)
+            .map(|i| {
+                let prev = self.momentum_samples[(oldest + i - 1) % MOMENTUM_SAMPLE_COUNT];
+                let next = self.momentum_samples[(oldest + i) % MOMENTUM_SAMPLE_COUNT];
+                prev.abs_diff(next) as u64
+            })
+            .sum();
+        (total, count.saturating_sub(1) as u64)
+    }
+
+    /// Neutral band, in index points, for settlements right now: the mean
+    /// recent step scaled by `volatility_deadband_bps`, divided by the oracle's
+    /// confidence so noisier periods demand a larger move to win
+    pub fn deadband(&self) -> u64 {
+        let (total_steps, steps) = self.volatility();
+        if steps == 0 || self.config.volatility_deadband_bps == 0 {
+            return 0;
+        }
+        let confidence = match self.oracle_confidence {
+            0 => FULL_CONFIDENCE,
+            c => c,
+        } as u64;
+        total_steps * self.config.volatility_deadband_bps as u64 * FULL_CONFIDENCE as u64
+            / (steps * 10_000 * confidence)
+    }
+
+    /// Oracle currently feeding the index: the first enabled entry, so the
+    /// backup only takes over while the primary is disabled
+    pub fn active_oracle(&self) -> Option<Pubkey> {
+        self.oracles
+            .iter()
+            .find(|oracle| oracle.enabled && oracle.key != Pubkey::default())
+            .map(|oracle| oracle.key)
+    }
+
+    /// Require `key` to be the active oracle
+    pub fn check_oracle(&self, key: &Pubkey) -> Result<()> {
+        let entry = self
+            .oracles
+            .iter()
+            .find(|oracle| oracle.key == *key)
+            .ok_or(TradingError::UnauthorizedOracle)?;
+        require!(entry.enabled, TradingError::OracleDisabled);
+        require!(self.active_oracle() == Some(*key), TradingError::UnauthorizedOracle);
+        Ok(())
+    }
+
+    /// Slot of an enabled oracle, whether or not it is the active one
+    pub fn oracle_slot(&self, key: &Pubkey) -> Result<usize> {
+        let slot = self
+            .oracles
+            .iter()
+            .position(|oracle| oracle.key == *key && *key != Pubkey::default())
+            .ok_or(TradingError::UnauthorizedOracle)?;
+        require!(self.oracles[slot].enabled, TradingError::OracleDisabled);
+        Ok(slot)
+    }
+
+    /// Median of the largest group of enabled oracles' final submissions that
+    /// lie within `quorum_tolerance` points of each other, provided the group
+    /// reaches `finalize_quorum`
+    pub fn quorum_final_index(&self) -> Result<u8> {
+        let mut submissions: Vec<u8> = self
+            .oracles
+            .iter()
+            .zip(self.final_submissions.iter())
+            .filter_map(|(oracle, submission)| submission.filter(|_| oracle.enabled).map(|s| s.index))
+            .collect();
+        submissions.sort_unstable();
+
+        // Sorted, so any agreeing group is a contiguous run; find the longest
+        let mut best = &submissions[..0];
+        for start in 0..submissions.len() {
+            let len = submissions[start..]
+                .iter()
+                .take_while(|&&value| value - submissions[start] <= self.config.quorum_tolerance)
+                .count();
+            if len > best.len() {
+                best = &submissions[start..start + len];
+            }
+        }
+        require!(
+            !best.is_empty() && best.len() >= self.config.finalize_quorum as usize,
+            TradingError::QuorumNotMet
+        );
+
+        Ok(median(best))
+    }
+
+    /// Median of the enabled oracles' readings taken within MAX_READING_AGE
+    /// of `now`, once there are at least `update_quorum` of them
+    pub fn quorum_reading(&self, now: i64) -> Option<u8> {
+        let mut readings: Vec<u8> = self
+            .oracles
+            .iter()
+            .zip(self.oracle_readings.iter())
+            .filter_map(|(oracle, reading)| reading.filter(|_| oracle.enabled))
+            .filter(|reading| now - reading.timestamp <= MAX_READING_AGE)
+            .map(|reading| reading.index)
+            .collect();
+        if readings.len() < (self.config.update_quorum as usize).max(1) {
+            return None;
+        }
+        readings.sort_unstable();
+        Some(median(&readings))
+    }
+
+    /// Payout parameters for settling `position` against the pool's current
+    /// deadband and, in counterparty-funded pools, opposing volume
+    pub fn payout_config(&self, position: &TradingPosition) -> PayoutConfig {
+        PayoutConfig {
+            deadband: self.deadband(),
+            max_profit: match (
+                self.config.counterparty_funded.then(|| self.counterparty_profit_cap(position)),
+                self.payout_multiplier_cap(position),
+            ) {
+                (Some(cap), Some(limit)) => Some(cap.min(limit)),
+                (cap, limit) => cap.or(limit),
+            },
+            funding: self.funding_owed(position, position.amount),
+            pari_mutuel: (self.settlement_mode == SettlementMode::PariMutuel)
+                .then(|| self.pari_mutuel_pots()),
+            ..self.config.payout_config(position)
+        }
+    }
+
+    /// Payout parameters for settling `position`, its profit fee reduced by
+    /// the trader's `fee_discount_bps` staking discount
+    pub fn settlement_payout_config(&self, position: &TradingPosition, fee_discount_bps: u16) -> PayoutConfig {
+        let mut config = self.payout_config(position);
+        config.profit_fee_bps = config.profit_fee_bps.saturating_sub(fee_discount_bps as u64);
+        config
+    }
+
+    /// Current funding index of `position_type`'s side; straddles take no
+    /// side and neither pay nor receive funding
+    pub fn funding_index(&self, position_type: &PositionType) -> i64 {
+        match position_type {
+            PositionType::Long => self.long_funding_index,
+            PositionType::Short => self.short_funding_index,
+            PositionType::Straddle => 0,
+        }
+    }
+
+    /// Funding `amount` of `position`'s stake has accrued since it opened;
+    /// negative if it is owed funding
+    pub fn funding_owed(&self, position: &TradingPosition, amount: u64) -> i64 {
+        let accrued = self.funding_index(&position.position_type) as i128
+            - position.entry_funding_index as i128;
+        let owed = amount as i128 * accrued / FUNDING_INDEX_SCALE as i128;
+        owed.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    /// Fees collected but not yet withdrawn
+    pub fn pending_fees(&self) -> u64 {
+        self.fees_collected - self.fees_withdrawn
+    }
+
+    /// Slice of a settlement's profit `fee` owed to the insurance fund, out
+    /// of at most `available` vault funds
+    pub fn insurance_fee(&self, fee: u64, available: u64) -> Result<u64> {
+        Ok(mul_div(fee, self.config.insurance_fee_bps as u64, 10_000, RoundingMode::Truncate)?.min(available))
+    }
+
+    /// Count `amount` the insurance fund paid into this pool
+    pub fn record_insurance_draw(&mut self, amount: u64) -> Result<()> {
+        self.insurance_drawn = self.insurance_drawn.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Book a settlement's profit `fee` for the treasury, less the `shares`
+    /// of it paid straight out to the referrer and insurance fund
+    pub fn collect_fee(&mut self, fee: u64, shares: u64) -> Result<()> {
+        self.fees_collected = fee
+            .checked_sub(shares)
+            .and_then(|kept| self.fees_collected.checked_add(kept))
+            .ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Part of a `vault_balance` not owed to positions, the treasury's
+    /// pending fees, the winners' pot or the authority's seeded liquidity
+    pub fn unreserved_balance(&self, vault_balance: u64) -> u64 {
+        vault_balance
+            .saturating_sub(self.outstanding_liability)
+            .saturating_sub(self.seeded_liquidity)
+            .saturating_sub(self.pending_fees())
+            .saturating_sub(self.winners_pot)
+    }
+
+    /// Account for a closed `stake` of which `paid_out` (payout and fees)
+    /// left the trader's side of the vault. A loss is credited to the
+    /// winners' pot; a win is drawn from it as far as it goes, the rest
+    /// coming from seeded liquidity.
+    pub fn record_outcome(&mut self, stake: u64, paid_out: u64) -> Result<()> {
+        if paid_out < stake {
+            let loss = stake - paid_out;
+            self.winners_pot = self.winners_pot.checked_add(loss).ok_or(TradingError::MathOverflow)?;
+            self.total_losses = self.total_losses.checked_add(loss).ok_or(TradingError::MathOverflow)?;
+        } else {
+            self.winners_pot -= (paid_out - stake).min(self.winners_pot);
+        }
+        Ok(())
+    }
+
+    /// `payout` cut to its pro-rata share of `vault_balance` when the pool
+    /// scales insolvent payouts and its outstanding liabilities exceed the
+    /// vault. Each scaled settlement releases at least as much liability as
+    /// it pays out proportionally, so later settlements are never scaled harder.
+    pub fn solvent_payout(&self, payout: u64, vault_balance: u64) -> Result<u64> {
+        if !self.config.scale_insolvent_payouts || self.outstanding_liability <= vault_balance {
+            return Ok(payout);
+        }
+        mul_div(payout, vault_balance, self.outstanding_liability, RoundingMode::Truncate)
+    }
+
+    /// Winning side and pots of a pari-mutuel pool: as finalized, or as they
+    /// would stand if the match ended now. Longs win if the index closes
+    /// above the neutral midpoint the pool opened at, shorts if below.
+    pub fn pari_mutuel_pots(&self) -> PariMutuelPots {
+        let (index, long, short) = if self.status == PoolStatus::Finalized {
+            (self.final_momentum_index, self.final_long_volume, self.final_short_volume)
+        } else {
+            (self.current_momentum_index, self.total_long_volume, self.total_short_volume)
+        };
+        let neutral = (self.index_scale_max / 2) as u8;
+        let (winning_side, winning, losing) = match index.cmp(&neutral) {
+            std::cmp::Ordering::Greater => (Some(PositionType::Long), long, short),
+            std::cmp::Ordering::Less => (Some(PositionType::Short), short, long),
+            std::cmp::Ordering::Equal => (None, 0, 0),
+        };
+        PariMutuelPots {
+            // With no one on the winning side there is no one to pay, so
+            // everyone is refunded as on a tie
+            winning_side: winning_side.filter(|_| winning > 0),
+            winning,
+            losing,
+        }
+    }
+
+    /// Profit ceiling implied by the protocol's maximum payout multiplier,
+    /// measured on the position's notional
+    pub fn payout_multiplier_cap(&self, position: &TradingPosition) -> Option<u64> {
+        let multiplier_bps = self.protocol_limits.max_payout_multiplier_bps as u64;
+        if multiplier_bps == 0 {
+            return None;
+        }
+        Some(
+            mul_div(position.amount, multiplier_bps - 10_000, 10_000, RoundingMode::Truncate)
+                .unwrap_or(u64::MAX),
+        )
+    }
+
+    /// Profit the opposing side's open volume can fund for `position`, pro
+    /// rata to its share of its own side. Nothing if no one took the other
+    /// side, so the stake is simply refunded. Straddles are funded by the
+    /// directional traders on both sides.
+    pub fn counterparty_profit_cap(&self, position: &TradingPosition) -> u64 {
+        let (own_side, opposing_side) = match position.position_type {
+            PositionType::Long => (self.total_long_volume, self.total_short_volume),
+            PositionType::Short => (self.total_short_volume, self.total_long_volume),
+            PositionType::Straddle => (
+                self.total_straddle_volume,
+                self.total_long_volume + self.total_short_volume,
+            ),
+        };
+        if own_side == 0 {
+            return 0;
+        }
+        mul_div(opposing_side, position.amount, own_side, RoundingMode::Truncate)
+            .map_or(opposing_side, |share| share.min(opposing_side))
+    }
+}
+
+/// MomentumPool layout before account versioning was introduced
+#[derive(AnchorDeserialize)]
+pub struct MomentumPoolV0 {
+    pub authority: Pubkey,
+    pub match_id: String,
+    pub start_time: i64,
+    pub home_team: String,
+    pub away_team: String,
+    pub total_long_volume: u64,
+    pub total_short_volume: u64,
+    pub current_momentum_index: u8,
+    pub is_active: bool,
+    pub created_at: i64,
+    pub last_update: i64,
+}
+
+impl From<MomentumPoolV0> for MomentumPool {
+    fn from(legacy: MomentumPoolV0) -> Self {
+        MomentumPool {
+            authority: legacy.authority,
+            match_id: legacy.match_id,
+            start_time: legacy.start_time,
+            home_team: legacy.home_team,
+            away_team: legacy.away_team,
+            total_long_volume: legacy.total_long_volume,
+            total_short_volume: legacy.total_short_volume,
+            current_momentum_index: legacy.current_momentum_index,
+            status: if legacy.is_active {
+                PoolStatus::Active
+            } else {
+                PoolStatus::Closed
+            },
+            created_at: legacy.created_at,
+            last_update: legacy.last_update,
+            index_scale_max: DEFAULT_INDEX_SCALE_MAX,
+            version: POOL_VERSION,
+            ..Default::default()
+        }
+    }
+}
+
+/// Trading parameters chosen by the pool authority
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct PoolConfig {
+    /// Profit bonus, in bps, for a position spanning the full bonus window
+    pub max_entry_bonus_bps: u16,
+    /// Window length in seconds that earns the full early-entry bonus
+    pub bonus_window: i64,
+    /// Seconds without an oracle update before new positions are refused (0 disables)
+    pub heartbeat_timeout: i64,
+    /// Seconds after a window ends before it may settle
+    pub settlement_grace_period: i64,
+    /// Largest move, in index points, a single update may make (0 disables)
+    pub max_index_delta: u8,
+    /// Halt the pool on an update exceeding `max_index_delta` instead of
+    /// just rejecting it
+    pub halt_on_breach: bool,
+    /// Minimum seconds between index updates (0 disables)
+    pub update_cooldown: i64,
+    /// Seconds without an oracle update after which positions may neither
+    /// open nor settle until the feed resumes or the pool finalizes
+    /// (0 disables)
+    pub max_oracle_staleness: i64,
+    /// Restrict trading to wallets holding an AllowlistEntry for the pool
+    pub allowlist_enabled: bool,
+    /// Seconds a position must be held after entry before it may settle
+    pub min_hold_seconds: i64,
+    /// Maximum fee adjustment, in bps, applied at full long/short skew
+    pub skew_fee_bps: u16,
+    /// Cap on total open long plus short stake (0 disables)
+    pub max_open_interest: u64,
+    /// Vault balance, in bps of open interest, below which settlement emits
+    /// a LowLiquidityWarningEvent (0 disables)
+    pub low_liquidity_threshold_bps: u16,
+    /// How payout divisions round; truncation favors the pool
+    pub rounding_mode: RoundingMode,
+    /// Cap on concurrently open positions, bounding settlement work per
+    /// match (0 disables)
+    pub max_positions: u32,
+    /// Gross profit, in collateral base units, below which no fee is
+    /// charged; set relative to the mint's decimals
+    pub min_profit_for_fee: u64,
+    /// Seconds after a window ends before anyone may force-settle and close
+    /// the position (0 disables)
+    pub force_settle_delay: i64,
+    /// Settlement deadband, in bps of the pool's recent volatility; widened
+    /// further as oracle confidence drops (0 disables)
+    pub volatility_deadband_bps: u16,
+    /// Tiered payout curve replacing the linear one when the first band is
+    /// set; unused trailing bands have a zero threshold
+    pub payout_bands: [PayoutBand; MAX_PAYOUT_BANDS],
+    /// Seconds after a window ends that winners have to settle before
+    /// forfeiting their payout to the pool (0 disables)
+    pub claim_period: i64,
+    /// Payouts above this are escrowed for `escrow_delay` seconds before
+    /// they can be claimed (0 disables)
+    pub escrow_threshold: u64,
+    pub escrow_delay: i64,
+    /// Oracles whose readings are aggregated into each index update (0 or 1
+    /// lets the active oracle update alone)
+    pub update_quorum: u8,
+    /// Stake, in the protocol's stake mint, an oracle must have bonded to
+    /// publish to the pool (0 disables)
+    pub min_oracle_stake: u64,
+    /// Oldest Switchboard round, in seconds, a feed-driven pool accepts
+    /// (0 disables)
+    pub feed_max_age: i64,
+    /// Widest standard deviation, in index points, between a Switchboard
+    /// round's oracle responses (0 disables)
+    pub feed_max_std_dev: u8,
+    /// Largest stake a position may open with, in USD at the collateral
+    /// price, in the collateral's base units; needs a price feed (0 disables)
+    pub max_position_usd: u64,
+    /// Oldest collateral price, in seconds, used for USD values (0 disables)
+    pub price_max_age: i64,
+    /// Widest Pyth confidence interval accepted, in bps of the price (0 disables)
+    pub price_max_conf_bps: u16,
+    /// Oracles that must agree on the final index before the pool can
+    /// finalize (0 or 1 lets the active oracle finalize alone)
+    pub finalize_quorum: u8,
+    /// Widest spread, in index points, between agreeing final submissions
+    pub quorum_tolerance: u8,
+    /// House edge, in index points, that new longs enter above and new
+    /// shorts below the current index
+    pub spread_points: u8,
+    /// Let winners swap their payout through `swap_pool`, an SPL token-swap
+    /// pool owned by `swap_program`
+    pub swap_enabled: bool,
+    pub swap_program: Pubkey,
+    pub swap_pool: Pubkey,
+    /// Cap each winner's profit at its pro-rata share of the opposing side's
+    /// open volume at settlement, so wins are funded by actual counterparties
+    pub counterparty_funded: bool,
+    /// Added to the base profit fee for straddle positions, in bps
+    pub straddle_fee_bps: u16,
+    /// Charged on the payout of positions closed before their window ends, in bps
+    pub early_exit_fee_bps: u16,
+    /// Paid by the pool to whoever cranks a settlement, in bps of the stake
+    pub keeper_reward_bps: u16,
+    /// Highest leverage open_leveraged_position accepts (0 or 1 disables)
+    pub max_leverage: u8,
+    /// Equity, in bps of notional, below which a leveraged position may be
+    /// liquidated
+    pub maintenance_margin_bps: u16,
+    /// Share of a liquidated position's remaining equity paid to the
+    /// liquidator, in bps; the rest goes to the insurance fund
+    pub liquidation_bonus_bps: u16,
+    /// Funding charged per interval to each unit of the crowded side's stake
+    /// when the pool is entirely one-sided, in bps; scales with the imbalance.
+    /// 0 disables funding.
+    pub funding_rate_bps: u16,
+    /// Seconds between funding accruals
+    pub funding_interval: i64,
+    /// When liabilities exceed the vault, pay each settlement its pro-rata
+    /// share of the vault instead of drawing on the insurance fund
+    pub scale_insolvent_payouts: bool,
+    /// Share of each settlement's profit fee paid into the insurance fund,
+    /// in bps, when the fund is passed and holds the pool's collateral mint
+    pub insurance_fee_bps: u16,
+    /// Settle windows against the time-weighted average index over this many
+    /// seconds up to their settlement time, read from the pool's momentum
+    /// history, instead of a single print (0 disables)
+    pub twap_seconds: i64,
+}
+
+impl PoolConfig {
+    pub const LEN: usize = 2 + 8 + 8 + 8 + 1 + 8 + 2 + 8 + 2 + 1 + 4 + 8 + 8 + 2
+        + MAX_PAYOUT_BANDS * PayoutBand::LEN + 8 + 8 + 8 + 1 + 1 + 1 + 1 + 32 + 32 + 1 + 2 + 2 + 2 + 1 + 2 + 2 + 2 + 8 + 1 + 2 + 8 + 1 + 8 + 8 + 1 + 8 + 8 + 2 + 8 + 1 + 1 + 8;
+
+    pub fn validate(&self, limits: &ProtocolLimits) -> Result<()> {
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.bonus_window), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.heartbeat_timeout), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.twap_seconds), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.feed_max_age), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.price_max_age), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.max_oracle_staleness), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.update_cooldown), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.settlement_grace_period), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.min_hold_seconds), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.force_settle_delay), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.claim_period), TradingError::InvalidPoolConfig);
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.escrow_delay), TradingError::InvalidPoolConfig);
+        require!(self.finalize_quorum as usize <= MAX_ORACLES, TradingError::InvalidPoolConfig);
+        require!(self.update_quorum as usize <= MAX_ORACLES, TradingError::InvalidPoolConfig);
+        if self.swap_enabled {
+            require!(
+                self.swap_program == SPL_TOKEN_SWAP_PROGRAM_ID && self.swap_pool != Pubkey::default(),
+                TradingError::InvalidPoolConfig
+            );
+        }
+        // Set bands must come first, with strictly rising thresholds and
+        // multipliers that never fall
+        let set_bands = self.payout_bands.iter().take_while(|band| band.threshold > 0).count();
+        require!(
+            self.payout_bands[set_bands..].iter().all(|band| band.threshold == 0),
+            TradingError::InvalidPoolConfig
+        );
+        require!(
+            self.payout_bands[..set_bands].windows(2).all(|pair| {
+                pair[0].threshold < pair[1].threshold
+                    && pair[0].multiplier_bps <= pair[1].multiplier_bps
+            }),
+            TradingError::InvalidPoolConfig
+        );
+        require!(
+            self.skew_fee_bps as u64 <= 10_000 - limits.profit_fee_bps as u64,
+            TradingError::InvalidPoolConfig
+        );
+        require!(
+            self.straddle_fee_bps as u64 <= 10_000 - limits.profit_fee_bps as u64,
+            TradingError::InvalidPoolConfig
+        );
+        require!(self.early_exit_fee_bps <= 10_000, TradingError::InvalidPoolConfig);
+        require!(self.keeper_reward_bps <= 10_000, TradingError::InvalidPoolConfig);
+        require!(self.maintenance_margin_bps <= 10_000, TradingError::InvalidPoolConfig);
+        require!(self.liquidation_bonus_bps <= 10_000, TradingError::InvalidPoolConfig);
+        require!(self.funding_rate_bps <= 10_000, TradingError::InvalidPoolConfig);
+        require!(
+            self.insurance_fee_bps as u64 + REFERRAL_SHARE_BPS <= 10_000,
+            TradingError::InvalidPoolConfig
+        );
+        require!((0..=MAX_WINDOW_DURATION).contains(&self.funding_interval), TradingError::InvalidPoolConfig);
+        if self.funding_rate_bps > 0 {
+            require!(self.funding_interval > 0, TradingError::InvalidPoolConfig);
+        }
+        Ok(())
+    }
+
+    /// Whether a settlement paying `payout` must go through escrow
+    pub fn is_escrowed(&self, payout: u64) -> bool {
+        self.escrow_threshold > 0 && payout > self.escrow_threshold
+    }
+
+    /// Highest profit fee a position can be charged: the base fee plus the
+    /// full skew adjustment or the straddle fee, whichever is larger
+    pub fn max_profit_fee_bps(&self, limits: &ProtocolLimits) -> u64 {
+        limits.profit_fee_bps as u64 + self.skew_fee_bps.max(self.straddle_fee_bps) as u64
+    }
+
+    /// Early-entry bonus for a position committed for `window_duration`
+    /// seconds, scaled linearly so later (shorter) entries earn less
+    pub fn entry_bonus_bps(&self, window_duration: i64) -> u64 {
+        if self.bonus_window <= 0 {
+            return 0;
+        }
+        let committed = window_duration.clamp(0, self.bonus_window);
+        self.max_entry_bonus_bps as u64 * committed as u64 / self.bonus_window as u64
+    }
+
+    /// Payout parameters for `position`, using the fee rate and index scale
+    /// recorded when it was opened
+    pub fn payout_config(&self, position: &TradingPosition) -> PayoutConfig {
+        PayoutConfig {
+            entry_bonus_bps: self.entry_bonus_bps(position.window_duration),
+            profit_fee_bps: position.profit_fee_bps as u64,
+            index_scale_max: position.index_scale_max() as u64,
+            rounding_mode: self.rounding_mode,
+            min_profit_for_fee: self.min_profit_for_fee,
+            deadband: 0,
+            payout_bands: self.payout_bands,
+            max_profit: None,
+            funding: 0,
+            pari_mutuel: None,
+        }
+    }
+}
+
+/// Per-position inputs to compute_payout
+#[derive(Clone, Debug, Default)]
+pub struct PayoutConfig {
+    /// Bonus applied to profit, in bps
+    pub entry_bonus_bps: u64,
+    /// Fee charged on the (bonused) profit, in bps
+    pub profit_fee_bps: u64,
+    /// Index range the entry and exit indices are measured against
+    pub index_scale_max: u64,
+    pub rounding_mode: RoundingMode,
+    /// Profits below this pay no fee
+    pub min_profit_for_fee: u64,
+    /// Index points either side of the entry within which the stake is refunded
+    pub deadband: u64,
+    pub payout_bands: [PayoutBand; MAX_PAYOUT_BANDS],
+    /// Ceiling on the (bonused) profit, before the fee
+    pub max_profit: Option<u64>,
+    /// Funding the position owes, deducted from its payout; negative if it is
+    /// owed funding
+    pub funding: i64,
+    /// Pots to settle against instead of the index move, in pari-mutuel pools
+    pub pari_mutuel: Option<PariMutuelPots>,
+}
+
+/// Outcome of a pari-mutuel pool: the side that won, if any, and the stakes
+/// on the winning and losing sides
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PariMutuelPots {
+    pub winning_side: Option<PositionType>,
+    pub winning: u64,
+    pub losing: u64,
+}
+
+impl PayoutConfig {
+    /// Profit multiplier, in bps of the stake, for a favorable move of
+    /// `change` points under a tiered curve, or None if the curve is linear
+    pub fn band_multiplier_bps(&self, change: u64) -> Option<u64> {
+        if self.payout_bands[0].threshold == 0 {
+            return None;
+        }
+        let multiplier = self
+            .payout_bands
+            .iter()
+            .take_while(|band| band.threshold > 0 && band.threshold as u64 <= change)
+            .last()
+            .map_or(0, |band| band.multiplier_bps as u64);
+        Some(multiplier)
+    }
+}
+
+/// An oracle allowed to feed the pool's index
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct OracleEntry {
+    pub key: Pubkey,
+    pub enabled: bool,
+}
+
+impl OracleEntry {
+    pub const LEN: usize = 32 + 1;
+}
+
+/// An oracle's reading towards an aggregated index update
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct OracleReading {
+    pub index: u8,
+    pub timestamp: i64,
+}
+
+impl OracleReading {
+    pub const LEN: usize = 1 + 8;
+}
+
+/// An oracle's final index submission, kept with the key that signed it so
+/// a later change of slot holder can't redirect a dispute
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct FinalSubmission {
+    pub oracle: Pubkey,
+    pub index: u8,
+}
+
+impl FinalSubmission {
+    pub const LEN: usize = 32 + 1;
+}
+
+/// Payout tier: moves of at least `threshold` points earn `multiplier_bps`
+/// of the stake as profit, up to the next band's threshold
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+pub struct PayoutBand {
+    pub threshold: u8,
+    pub multiplier_bps: u16,
+}
+
+impl PayoutBand {
+    pub const LEN: usize = 1 + 2;
+}
+
+/// Program-wide settings that bound what any pool may be configured with
+#[account]
+pub struct ProtocolConfig {
+    pub admin: Pubkey,
+    /// Ceiling on a pool's maximum profit fee, in bps
+    pub max_profit_fee_bps: u16,
+    pub bump: u8,
+    /// Copied to each pool when its config is set
+    pub limits: ProtocolLimits,
+    /// Refuse new positions in every pool
+    pub trading_paused: bool,
+    /// Refuse settlements in every pool
+    pub settlement_paused: bool,
+    /// Token staked for fee discounts; default while discounts are off
+    pub stake_mint: Pubkey,
+    pub fee_tiers: [FeeTier; MAX_FEE_TIERS],
+    pub oracle_slashing: OracleSlashing,
+}
+
+impl ProtocolConfig {
+    pub const LEN: usize = 32 + 2 + 1 + ProtocolLimits::LEN + 1 + 1 + 32 + FeeTier::LEN * MAX_FEE_TIERS
+        + OracleSlashing::LEN;
+
+    /// Profit fee discount earned by `stake`: that of the best tier its
+    /// balance reaches, or none if it is missing or in another mint
+    pub fn fee_discount_bps(&self, stake: Option<&StakeAccount>) -> u16 {
+        match stake {
+            Some(stake) if self.stake_mint != Pubkey::default() && stake.mint == self.stake_mint => self
+                .fee_tiers
+                .iter()
+                .filter(|tier| stake.amount >= tier.min_stake)
+                .map(|tier| tier.discount_bps)
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+/// Profit fee discount for traders staking at least `min_stake`. Against the
+/// default 2% fee, discounts of 50, 100 and 150 bps give 1.5%, 1% and 0.5%.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FeeTier {
+    pub min_stake: u64,
+    pub discount_bps: u16,
+}
+
+impl FeeTier {
+    pub const LEN: usize = 8 + 2;
+}
+
+/// Penalty for an oracle whose final index submission strayed from the
+/// finalized value by more than the pool's quorum tolerance
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OracleSlashing {
+    /// Share of the oracle's stake slashed per dispute, in bps (0 disables)
+    pub slash_bps: u16,
+    /// Shares of the slashed amount paid to the disputer and the insurance
+    /// fund, in bps; the rest is burned, as is the fund's share when its
+    /// vault isn't in the stake mint
+    pub disputer_share_bps: u16,
+    pub insurance_share_bps: u16,
+}
+
+impl OracleSlashing {
+    pub const LEN: usize = 2 + 2 + 2;
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.slash_bps <= 10_000
+                && self.disputer_share_bps as u32 + self.insurance_share_bps as u32 <= 10_000,
+            TradingError::InvalidPoolConfig
+        );
+        Ok(())
+    }
+}
+
+/// Stake bonded by an oracle operator to publish to pools requiring one
+#[account]
+pub struct OracleStake {
+    pub oracle: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// Last publication; the stake stays bonded for ORACLE_UNBONDING_SECONDS after
+    pub last_published_at: i64,
+    pub total_slashed: u64,
+    pub bump: u8,
+}
+
+impl OracleStake {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 1;
+}
+
+#[account]
+pub struct StakeAccount {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    /// When the stake was last added to; its lockup runs from here
+    pub staked_at: i64,
+    pub bump: u8,
+}
+
+impl StakeAccount {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 1;
+}
+
+/// Protocol-wide trading limits. Pools take a copy when created or
+/// reconfigured, so a change applies to a pool from its next config update.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProtocolLimits {
+    /// Base profit fee, in bps, before skew and straddle adjustments
+    pub profit_fee_bps: u16,
+    /// Shortest and longest window a position may be opened for, in seconds
+    pub min_window_duration: i64,
+    pub max_window_duration: i64,
+    /// Ceiling on a position's payout as a multiple of its stake, in bps
+    /// (0 disables)
+    pub max_payout_multiplier_bps: u32,
+}
+
+/// The limits in force before they were configurable
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self {
+            profit_fee_bps: PROFIT_FEE_BPS as u16,
+            min_window_duration: 0,
+            max_window_duration: MAX_WINDOW_DURATION,
+            max_payout_multiplier_bps: 0,
+        }
+    }
+}
+
+impl ProtocolLimits {
+    pub const LEN: usize = 2 + 8 + 8 + 4;
+
+    pub fn validate(&self, max_profit_fee_bps: u16) -> Result<()> {
+        require!(self.profit_fee_bps <= max_profit_fee_bps, TradingError::FeeExceedsCeiling);
+        require!(
+            self.min_window_duration >= 0
+                && self.min_window_duration <= self.max_window_duration
+                && self.max_window_duration > 0
+                && self.max_window_duration <= MAX_WINDOW_DURATION,
+            TradingError::InvalidPoolConfig
+        );
+        require!(
+            self.max_payout_multiplier_bps == 0 || self.max_payout_multiplier_bps >= 10_000,
+            TradingError::InvalidPoolConfig
+        );
+        Ok(())
+    }
+
+    pub fn allows_window(&self, window_duration: i64) -> bool {
+        (self.min_window_duration..=self.max_window_duration).contains(&window_duration)
+    }
+}
+
+#[account]
+pub struct InsuranceFund {
+    pub authority: Pubkey,
+    pub vault: Pubkey,
+    pub max_draw_per_settlement: u64,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub total_drawn: u64,
+    pub bump: u8,
+}
+
+impl InsuranceFund {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 1;
+
+    /// Count `amount` paid into the fund's vault
+    pub fn record_deposit(&mut self, amount: u64) -> Result<()> {
+        self.total_deposited = self.total_deposited.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Count `amount` withdrawn by the fund's authority
+    pub fn record_withdrawal(&mut self, amount: u64) -> Result<()> {
+        self.total_withdrawn = self.total_withdrawn.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Count `amount` drawn into a pool's vault or a trader's payout
+    pub fn record_draw(&mut self, amount: u64) -> Result<()> {
+        self.total_drawn = self.total_drawn.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+pub struct TradingPosition {
+    pub trader: Pubkey,
+    pub pool: Pubkey,
+    pub position_type: PositionType,
+    pub amount: u64,
+    pub entry_momentum_index: u8,
+    pub exit_momentum_index: u8,
+    pub entry_time: i64,
+    pub window_end_time: i64,
+    pub window_duration: i64,
+    pub is_settled: bool,
+    pub pnl: i64,
+    pub settled_at: i64,
+    pub opened_during_commit: bool,
+    pub version: u8,
+    pub exit_snapshot_index: Option<u8>,
+    pub checkpoint: Option<MatchCheckpoint>,
+    pub client_tag: [u8; 16],
+    pub referrer: Option<Pubkey>,
+    pub payout_recipient: Pubkey,
+    pub profit_fee_bps: u16,
+    pub index_scale_max: u16,
+    pub max_liability: u64,
+    pub collateral_mint: Pubkey,
+    pub entry_total_long_volume: u64,
+    pub entry_total_short_volume: u64,
+    pub mark_pnl: i64,
+    pub mark_index: u8,
+    pub marked_at: i64,
+    pub pool_epoch: u32,
+    pub escrowed_payout: u64,
+    pub claimable_at: i64,
+    pub payout_held: bool,
+    pub entry_spread: u8,
+    pub stop_loss_index: Option<u8>,
+    pub take_profit_index: Option<u8>,
+    /// Notional multiple of the margin; 0 for unleveraged positions, whose
+    /// stake is `amount`
+    pub leverage: u8,
+    /// Collateral backing a leveraged position; `amount` is its notional
+    pub margin: u64,
+    pub maintenance_margin_bps: u16,
+    /// Funding index of the position's side when it was opened
+    pub entry_funding_index: i64,
+    /// Mint of the token representing a tokenized position; whoever holds it
+    /// is paid at settlement
+    pub position_mint: Option<Pubkey>,
+}
+
+impl TradingPosition {
+    pub const LEN: usize = 32 + 32 + 1 + 8 + 1 + 1 + 8 + 8 + 8 + 1 + 8 + 8 + 1 + 1 + 2 + 2 + 16 + 33 + 32 + 2 + 2 + 8 + 32
+        + 8 + 8 + 8 + 1 + 8 + 4 + 8 + 8 + 1 + 1 + 2 + 2 + 1 + 8 + 2 + 8 + 33;
+
+    pub fn is_leveraged(&self) -> bool {
+        self.leverage > 1
+    }
+
+    pub fn is_tokenized(&self) -> bool {
+        self.position_mint.is_some()
+    }
+
+    /// What the trader put at risk: the margin for leveraged positions,
+    /// otherwise the amount
+    pub fn stake(&self) -> u64 {
+        if self.is_leveraged() {
+            self.margin
+        } else {
+            self.amount
+        }
+    }
+
+    /// Add the gain or loss of `paid` back against `staked` to the realized PnL
+    pub fn record_pnl(&mut self, paid: u64, staked: u64) -> Result<()> {
+        let change = i64::try_from(paid)
+            .ok()
+            .zip(i64::try_from(staked).ok())
+            .and_then(|(paid, staked)| paid.checked_sub(staked));
+        self.pnl = change
+            .and_then(|change| self.pnl.checked_add(change))
+            .ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Copy of the position with every optional field set, i.e. at its
+    /// largest serialized size
+    pub fn fully_populated(&self) -> Self {
+        let mut position = self.clone();
+        position.exit_snapshot_index = Some(0);
+        position.checkpoint = Some(MatchCheckpoint::Fulltime);
+        position.referrer = Some(Pubkey::default());
+        position.stop_loss_index = Some(0);
+        position.take_profit_index = Some(0);
+        position.position_mint = Some(Pubkey::default());
+        position
+    }
+
+    /// Index scale the position was opened under; positions predating the
+    /// field were all opened on the default scale
+    pub fn index_scale_max(&self) -> u16 {
+        if self.index_scale_max == 0 {
+            DEFAULT_INDEX_SCALE_MAX
+        } else {
+            self.index_scale_max
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PositionType {
+    Long,
+    Short,
+    /// Wins on the absolute momentum change, whichever way it moves
+    Straddle,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoolStatus {
+    /// Accepting new positions and settling existing ones
+    #[default]
+    Active,
+    /// Winding down: existing positions settle, new ones are refused
+    ClosingToNewPositions,
+    /// No further trading
+    Closed,
+    /// Final index recorded; open positions settle against it
+    Finalized,
+    /// Circuit breaker tripped by an outsized update: no trading, settling
+    /// or further updates until the authority resumes the pool
+    Halted,
+}
+
+/// How a pool pays winners. FixedMultiplier pays each position on its own
+/// index move, out of the vault. PariMutuel splits the losing side's stakes
+/// among the winning side pro rata once the match is finalized, so it can
+/// never owe more than it holds.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SettlementMode {
+    #[default]
+    FixedMultiplier,
+    PariMutuel,
+}
+
+/// Where a pool's index updates come from: its registered oracles signing
+/// them, or a Switchboard aggregator anyone can relay
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OracleSource {
+    #[default]
+    Signer,
+    Switchboard,
+}
+
+/// Switchboard V2 decimal: `mantissa` scaled down by 10^`scale`
+#[derive(Clone, Copy, Debug)]
+pub struct SwitchboardDecimal {
+    pub mantissa: i128,
+    pub scale: u32,
+}
+
+impl SwitchboardDecimal {
+    /// Round to the nearest whole index point
+    pub fn to_index(&self) -> Result<u8> {
+        require!(self.scale <= 38, TradingError::InvalidSwitchboardFeed);
+        let divisor = 10i128.pow(self.scale);
+        let rounded = self
+            .mantissa
+            .checked_add(divisor / 2)
+            .ok_or(TradingError::InvalidSwitchboardFeed)?
+            .div_euclid(divisor);
+        u8::try_from(rounded).map_err(|_| error!(TradingError::InvalidMomentumIndex))
+    }
+}
+
+/// The fields settlement needs from a Switchboard V2 aggregator's latest
+/// confirmed round
+#[derive(Clone, Copy, Debug)]
+pub struct SwitchboardRound {
+    pub num_success: u32,
+    pub open_timestamp: i64,
+    pub result: SwitchboardDecimal,
+    pub std_deviation: SwitchboardDecimal,
+}
+
+impl SwitchboardRound {
+    /// Offset of `latest_confirmed_round` in the packed AggregatorAccountData
+    /// layout, discriminator included
+    const OFFSET: usize = 341;
+    const LEN: usize = 4 + 4 + 1 + 8 + 8 + 2 * (16 + 4);
+
+    pub fn read(feed: &AccountInfo) -> Result<Self> {
+        let data = feed.try_borrow_data()?;
+        let discriminator = hashv(&[b"account:AggregatorAccountData"]).to_bytes();
+        require!(
+            data.len() >= Self::OFFSET + Self::LEN && data[..8] == discriminator[..8],
+            TradingError::InvalidSwitchboardFeed
+        );
+        let round = &data[Self::OFFSET..Self::OFFSET + Self::LEN];
+        let read_u32 = |at: usize| u32::from_le_bytes([round[at], round[at + 1], round[at + 2], round[at + 3]]);
+        let read_i64 = |at: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&round[at..at + 8]);
+            i64::from_le_bytes(bytes)
+        };
+        let decimal = |at: usize| {
+            let mut bytes = [0u8; 16];
+            bytes.copy_from_slice(&round[at..at + 16]);
+            SwitchboardDecimal {
+                mantissa: i128::from_le_bytes(bytes),
+                scale: read_u32(at + 16),
+            }
+        };
+        Ok(Self {
+            num_success: read_u32(0),
+            // Skips num_error, is_closed and round_open_slot
+            open_timestamp: read_i64(17),
+            result: decimal(25),
+            std_deviation: decimal(45),
+        })
+    }
+}
+
+/// Aggregate price from a Pyth price account: `price` * 10^`expo`, give or
+/// take `conf`
+#[derive(Clone, Copy, Debug)]
+pub struct PythPrice {
+    pub price: i64,
+    pub conf: u64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+impl PythPrice {
+    const MAGIC: u32 = 0xa1b2c3d4;
+    const VERSION: u32 = 2;
+    const PRICE_ACCOUNT: u32 = 3;
+    const STATUS_TRADING: u32 = 1;
+    /// End of the aggregate price info in the price account layout
+    const MIN_LEN: usize = 240;
+
+    pub fn read(feed: &AccountInfo) -> Result<Self> {
+        let data = feed.try_borrow_data()?;
+        require!(data.len() >= Self::MIN_LEN, TradingError::InvalidPriceFeed);
+        let read_u32 = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        let read_u64 = |at: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[at..at + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        require!(
+            read_u32(0) == Self::MAGIC && read_u32(4) == Self::VERSION && read_u32(8) == Self::PRICE_ACCOUNT,
+            TradingError::InvalidPriceFeed
+        );
+        // Aggregate status; a halted or unknown price isn't usable
+        require!(read_u32(224) == Self::STATUS_TRADING, TradingError::InvalidPriceFeed);
+        Ok(Self {
+            price: read_u64(208) as i64,
+            conf: read_u64(216),
+            expo: read_u32(20) as i32,
+            publish_time: read_u64(96) as i64,
+        })
+    }
+}
+
+/// Live pools trade real collateral; Paper pools use a no-value test token
+/// and may be reset for repeatable demos
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoolKind {
+    #[default]
+    Live,
+    Paper,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Round down, always in the pool's favor
+    #[default]
+    Truncate,
+    /// Round to nearest, with halves rounding up
+    RoundHalfUp,
+}
+
+/// Natural settlement points in a match
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchCheckpoint {
+    Halftime,
+    Fulltime,
+}
+
+impl MatchCheckpoint {
+    pub const COUNT: usize = 2;
+}
+
+/// Momentum index recorded when the oracle tagged an update as a checkpoint
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct CheckpointSnapshot {
+    pub momentum_index: u8,
+    pub timestamp: i64,
+}
+
+impl CheckpointSnapshot {
+    pub const LEN: usize = 1 + 8;
+}
+
+#[account]
+pub struct AllowlistEntry {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub added_at: i64,
+    pub bump: u8,
+}
+
+impl AllowlistEntry {
+    pub const LEN: usize = 32 + 32 + 8 + 1;
+}
+
+/// A trader's consent for `delegate` to open positions on their behalf
+#[account]
+pub struct DelegateApproval {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub delegate: Pubkey,
+    pub approved_at: i64,
+    pub bump: u8,
+}
+
+impl DelegateApproval {
+    pub const LEN: usize = 32 + 32 + 32 + 8 + 1;
+}
+
+/// Per-pool index of a trader's open positions, so wallets can list them
+/// without a getProgramAccounts scan. Settled positions are removed; if more
+/// than MAX_INDEXED_POSITIONS are open at once the oldest entry is evicted
+/// and counted in `evicted`, signalling that the list is incomplete.
+#[account]
+pub struct TraderPositions {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub positions: Vec<Pubkey>,
+    pub total_opened: u64,
+    pub evicted: u64,
+    pub bump: u8,
+}
+
+impl TraderPositions {
+    pub const LEN: usize = 32 + 32 + 4 + 32 * MAX_INDEXED_POSITIONS + 8 + 8 + 1;
+
+    pub fn record_open(&mut self, pool: Pubkey, trader: Pubkey, position: Pubkey, bump: u8) {
+        // Freshly created by init_if_needed
+        if self.trader == Pubkey::default() {
+            self.pool = pool;
+            self.trader = trader;
+            self.bump = bump;
+        }
+        if self.positions.contains(&position) {
+            return;
+        }
+        if self.positions.len() >= MAX_INDEXED_POSITIONS {
+            self.positions.remove(0);
+            self.evicted += 1;
+        }
+        self.positions.push(position);
+        self.total_opened += 1;
+    }
+
+    pub fn record_settled(&mut self, position: &Pubkey) {
+        self.positions.retain(|p| p != position);
+    }
+}
+
+/// A trader's lifetime results across every pool, for leaderboards and
+/// reward tiers. Updated on each settlement.
+#[account]
+pub struct TraderStats {
+    pub trader: Pubkey,
+    pub total_positions: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub net_pnl: i64,
+    pub total_volume: u64,
+    pub bump: u8,
+}
+
+impl TraderStats {
+    pub const LEN: usize = 32 + 8 + 8 + 8 + 8 + 8 + 1;
+
+    pub fn record_settlement(&mut self, trader: Pubkey, amount: u64, pnl: i64, bump: u8) -> Result<()> {
+        // Freshly created by init_if_needed
+        if self.trader == Pubkey::default() {
+            self.trader = trader;
+            self.bump = bump;
+        }
+        self.total_positions += 1;
+        match pnl.signum() {
+            1 => self.wins += 1,
+            -1 => self.losses += 1,
+            _ => {}
+        }
+        self.net_pnl = self.net_pnl.checked_add(pnl).ok_or(TradingError::MathOverflow)?;
+        self.total_volume = self.total_volume.checked_add(amount).ok_or(TradingError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+/// Pools an operator runs under one league or tournament, so they can be
+/// paused or reported on together. Closed pools leave `pools`.
+#[account]
+pub struct LeagueRegistry {
+    pub authority: Pubkey,
+    pub league_id: u64,
+    pub pools: Vec<Pubkey>,
+    pub total_pools: u64,
+    pub closed_pools: u64,
+    pub bump: u8,
+}
+
+impl LeagueRegistry {
+    pub const LEN: usize = 32 + 8 + 4 + 32 * MAX_LEAGUE_POOLS + 8 + 8 + 1;
+
+    pub fn record_created(&mut self, pool: Pubkey) -> Result<()> {
+        require!(self.pools.len() < MAX_LEAGUE_POOLS, TradingError::LeagueFull);
+        self.pools.push(pool);
+        self.total_pools += 1;
+        Ok(())
+    }
+
+    pub fn record_closed(&mut self, pool: &Pubkey) {
+        let members = self.pools.len();
+        self.pools.retain(|p| p != pool);
+        if self.pools.len() < members {
+            self.closed_pools += 1;
+        }
+    }
+}
+
+/// How a keeper should treat a failed settlement
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettlementFailureKind {
+    /// Not settleable yet (window open, grace period, missing exit index,
+    /// short liquidity); retry later unchanged
+    Transient,
+    /// The accounts or options passed were wrong; fix them and resubmit
+    InvalidRequest,
+    /// Can never settle as submitted (already settled, stale epoch, math
+    /// failure); skip it
+    Permanent,
+}
+
+impl SettlementFailureKind {
+    const TRANSIENT: [TradingError; 9] = [
+        TradingError::WindowNotEnded,
+        TradingError::InGracePeriod,
+        TradingError::MinHoldNotMet,
+        TradingError::CheckpointNotReached,
+        TradingError::SnapshotUnavailable,
+        TradingError::InsufficientLiquidity,
+        TradingError::SlippageExceeded,
+        TradingError::ProtocolPaused,
+        TradingError::StaleOracle,
+    ];
+    const INVALID_REQUEST: [TradingError; 9] = [
+        TradingError::InvalidInsuranceVault,
+        TradingError::InvalidReferrerAccount,
+        TradingError::InvalidCompound,
+        TradingError::SwapDisabled,
+        TradingError::MissingSwapAccounts,
+        TradingError::InvalidSwapAccount,
+        TradingError::InvalidTimestamp,
+        TradingError::MissingPositionToken,
+        TradingError::InvalidPositionToken,
+    ];
+
+    /// Category of the error with code `code` raised while settling
+    pub fn from_error_code(code: u32) -> Self {
+        let is_any = |errors: &[TradingError]| errors.iter().any(|&error| u32::from(error) == code);
+        if is_any(&Self::TRANSIENT) {
+            Self::Transient
+        } else if is_any(&Self::INVALID_REQUEST) {
+            Self::InvalidRequest
+        } else {
+            Self::Permanent
+        }
+    }
+}
+
+/// A position waiting for the momentum index to reach its trigger. Its stake
+/// is already in the pool's vault.
+#[account]
+pub struct PendingOrder {
+    pub trader: Pubkey,
+    pub pool: Pubkey,
+    pub order_id: u64,
+    pub position_type: PositionType,
+    pub trigger_index: u8,
+    pub collateral_mint: Pubkey,
+    pub args: OpenPositionArgs,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl PendingOrder {
+    pub const LEN: usize = 32 + 32 + 8 + 1 + 1 + 32 + OpenPositionArgs::LEN + 8 + 1;
+}
+
+/// An open position offered for sale. While it exists, the position's
+/// `trader` is the listing itself, so the seller can't act on it.
+#[account]
+pub struct Listing {
+    pub seller: Pubkey,
+    pub position: Pubkey,
+    pub pool: Pubkey,
+    /// In the position's collateral mint
+    pub price: u64,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl Listing {
+    pub const LEN: usize = 32 + 32 + 32 + 8 + 8 + 1;
+}
+
+/// A trader's free collateral in one pool, available to back leveraged
+/// positions. The tokens sit in the pool's vault.
+#[account]
+pub struct MarginAccount {
+    pub trader: Pubkey,
+    pub pool: Pubkey,
+    pub balance: u64,
+    pub bump: u8,
+}
+
+impl MarginAccount {
+    pub const LEN: usize = 32 + 32 + 8 + 1;
+}
+
+/// Ring buffer of a pool's last MOMENTUM_HISTORY_LEN index updates
+#[account(zero_copy)]
+pub struct MomentumHistory {
+    pub pool: Pubkey,
+    pub timestamps: [i64; MOMENTUM_HISTORY_LEN],
+    /// Slot the next update is written to
+    pub head: u32,
+    /// Updates held, up to MOMENTUM_HISTORY_LEN
+    pub count: u32,
+    pub indices: [u8; MOMENTUM_HISTORY_LEN],
+}
+
+impl MomentumHistory {
+    pub const LEN: usize = 32 + 8 * MOMENTUM_HISTORY_LEN + 4 + 4 + MOMENTUM_HISTORY_LEN;
+
+    pub fn push(&mut self, index: u8, timestamp: i64) {
+        let head = self.head as usize;
+        self.indices[head] = index;
+        self.timestamps[head] = timestamp;
+        self.head = ((head + 1) % MOMENTUM_HISTORY_LEN) as u32;
+        self.count = (self.count + 1).min(MOMENTUM_HISTORY_LEN as u32);
+    }
+
+    /// Follow a correction of the update stamped `timestamp`: replace its
+    /// index, or drop it if the update was voided outright
+    pub fn amend_latest(&mut self, timestamp: i64, corrected_index: Option<u8>) {
+        let latest = (self.head as usize + MOMENTUM_HISTORY_LEN - 1) % MOMENTUM_HISTORY_LEN;
+        if self.count == 0 || self.timestamps[latest] != timestamp {
+            return;
+        }
+        match corrected_index {
+            Some(index) => self.indices[latest] = index,
+            None => {
+                self.head = latest as u32;
+                self.count -= 1;
+            }
+        }
+    }
+
+    /// Updates held, newest first, as (timestamp, index)
+    pub fn iter_newest(&self) -> impl Iterator<Item = (i64, u8)> + '_ {
+        (1..=self.count as usize)
+            .map(|back| (self.head as usize + MOMENTUM_HISTORY_LEN - back) % MOMENTUM_HISTORY_LEN)
+            .map(|slot| (self.timestamps[slot], self.indices[slot]))
+    }
+
+    /// Index in effect at `timestamp`: that of the last update stamped at or
+    /// before it, if still held
+    pub fn index_at(&self, timestamp: i64) -> Option<u8> {
+        self.iter_newest()
+            .find(|&(time, _)| time <= timestamp)
+            .map(|(_, index)| index)
+    }
+
+    /// Time-weighted average index from `start` to `end`, rounded to the
+    /// nearest point, or None unless the update in effect at `start` is
+    /// still held
+    pub fn twap(&self, start: i64, end: i64) -> Option<u8> {
+        if end <= start {
+            return self.index_at(end);
+        }
+        let mut weighted: i128 = 0;
+        let mut segment_end = end;
+        for (time, index) in self.iter_newest().filter(|&(time, _)| time <= end) {
+            let segment_start = time.max(start);
+            weighted += index as i128 * (segment_end - segment_start) as i128;
+            segment_end = segment_start;
+            if time <= start {
+                let span = (end - start) as i128;
+                return Some(((weighted + span / 2) / span) as u8);
+            }
+        }
+        None
+    }
+}
+
+#[event]
+pub struct MomentumUpdateEvent {
+    pub match_id: String,
+    pub old_index: u8,
+    pub new_index: u8,
+    pub timestamp: i64,
+    pub confidence: u8,
+}
+
+#[event]
+pub struct PositionOpenedEvent {
+    pub trader: Pubkey,
+    pub pool: Pubkey,
+    pub position_type: PositionType,
+    pub amount: u64,
+    pub entry_index: u8,
+    pub window_end_time: i64,
+    pub client_tag: [u8; 16],
+    pub profit_fee_bps: u16,
+    pub entry_total_long_volume: u64,
+    pub entry_total_short_volume: u64,
+}
+
+#[event]
+pub struct PositionSettledEvent {
+    pub trader: Pubkey,
+    pub position_type: PositionType,
+    pub pnl: i64,
+    pub entry_index: u8,
+    pub exit_index: u8,
+    pub amount: u64,
+    pub gross_payout: u64,
+    pub fee_paid: u64,
+    pub window_duration: i64,
+    pub client_tag: [u8; 16],
+    pub referrer: Option<Pubkey>,
+    pub referral_fee: u64,
+    /// Stablecoin delivered when the payout was swapped, otherwise 0
+    pub stable_received: u64,
+    /// `pnl` in USD, in the collateral's base units, when the pool has a
+    /// fresh collateral price
+    pub pnl_usd: Option<i64>,
+}
+
+#[event]
+pub struct PositionPartiallyClosedEvent {
+    pub trader: Pubkey,
+    pub position_type: PositionType,
+    pub close_amount: u64,
+    pub payout: u64,
+    pub remaining_amount: u64,
+    pub exit_index: u8,
+    pub early_exit_fee: u64,
+}
+
+#[event]
+pub struct InsuranceFeeEvent {
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub fund_balance: u64,
 }
 
-#[account]
-pub struct MomentumPool {
-    pub authority: Pubkey,
-    pub match_id: String,
-    pub start_time: i64,
-    pub home_team: String,
-    pub away_team: String,
-    pub total_long_volume: u64,
-    pub total_short_volume: u64,
-    pub current_momentum_index: u8,
-    pub is_active: bool,
-    pub created_at: i64,
-    pub last_update: i64,
-}
+#[event]
+pub struct InsurancePoolDrawEvent {
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub fund_balance: u64,
+}
+
+#[event]
+pub struct InsuranceDrawEvent {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub amount: u64,
+    pub fund_balance: u64,
+}
+
+#[event]
+pub struct PositionRolledEvent {
+    pub trader: Pubkey,
+    pub position_type: PositionType,
+    pub amount: u64,
+    pub entry_index: u8,
+    pub window_end_time: i64,
+}
+
+#[event]
+pub struct PoolStatusChangedEvent {
+    pub match_id: String,
+    pub old_status: PoolStatus,
+    pub new_status: PoolStatus,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MomentumCorrectedEvent {
+    pub match_id: String,
+    pub voided_index: u8,
+    pub corrected_index: u8,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct DustSweptEvent {
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub outstanding_liability: u64,
+}
+
+#[event]
+pub struct LowLiquidityWarningEvent {
+    pub pool: Pubkey,
+    pub liquidity: u64,
+    pub open_interest: u64,
+    pub threshold_bps: u16,
+}
+
+#[event]
+pub struct OracleStatusChangedEvent {
+    pub pool: Pubkey,
+    pub oracle: Pubkey,
+    pub enabled: bool,
+}
+
+#[event]
+pub struct OracleFailoverEvent {
+    pub pool: Pubkey,
+    pub previous: Option<Pubkey>,
+    pub active: Option<Pubkey>,
+}
+
+#[event]
+pub struct ForfeitEvent {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub forfeited: u64,
+    pub window_end_time: i64,
+}
+
+#[event]
+pub struct PayoutEscrowedEvent {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub amount: u64,
+    pub claimable_at: i64,
+}
+
+#[event]
+pub struct SolvencyReportEvent {
+    pub pool: Pubkey,
+    pub positions: u32,
+    pub vault_balance: u64,
+    /// Owed if every counted position settled at the current index
+    pub current_liability: u64,
+    /// Owed if every counted position settled at its best possible exit
+    pub max_liability: u64,
+    /// Vault balance as a share of max_liability (u64::MAX when nothing is owed)
+    pub coverage_bps: u64,
+    /// Lost stakes held for winners, on top of the liabilities
+    pub winners_pot: u64,
+}
+
+#[event]
+pub struct WindowShortenedEvent {
+    pub trader: Pubkey,
+    pub position: Pubkey,
+    pub old_window_end_time: i64,
+    pub new_window_end_time: i64,
+}
+
+#[event]
+pub struct ConfigUpdatedEvent {
+    pub pool: Pubkey,
+    pub old_config: PoolConfig,
+    pub new_config: PoolConfig,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LeagueReportEvent {
+    pub league_id: u64,
+    pub pools: u32,
+    pub total_long_volume: u64,
+    pub total_short_volume: u64,
+    pub open_positions: u64,
+}
+
+#[event]
+pub struct SettlementFailedEvent {
+    pub position: Pubkey,
+    pub error_code: u32,
+    pub kind: SettlementFailureKind,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionClosedEarlyEvent {
+    pub trader: Pubkey,
+    pub position_type: PositionType,
+    pub entry_index: u8,
+    pub exit_index: u8,
+    pub amount: u64,
+    pub payout: u64,
+    pub early_exit_fee: u64,
+    pub pnl: i64,
+}
+
+#[event]
+pub struct KeeperRewardEvent {
+    pub pool: Pubkey,
+    pub position: Pubkey,
+    pub keeper: Pubkey,
+    pub reward: u64,
+}
+
+#[event]
+pub struct StopLossExecutedEvent {
+    pub trader: Pubkey,
+    pub position: Pubkey,
+    pub stop_loss_index: u8,
+    pub exit_index: u8,
+    pub payout: u64,
+    pub early_exit_fee: u64,
+}
+
+#[event]
+pub struct TakeProfitExecutedEvent {
+    pub trader: Pubkey,
+    pub position: Pubkey,
+    pub take_profit_index: u8,
+    pub current_index: u8,
+    pub payout: u64,
+    pub early_exit_fee: u64,
+}
+
+#[event]
+pub struct LimitOrderPlacedEvent {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub order_id: u64,
+    pub position_type: PositionType,
+    pub trigger_index: u8,
+    pub amount: u64,
+}
+
+#[event]
+pub struct LimitOrderTriggeredEvent {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub order_id: u64,
+    pub position: Pubkey,
+    pub entry_index: u8,
+}
+
+#[event]
+pub struct LiquidationEvent {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub liquidator: Pubkey,
+    pub exit_index: u8,
+    pub margin: u64,
+    pub liquidator_bonus: u64,
+    pub insurance_amount: u64,
+}
+
+#[event]
+pub struct FundingAccruedEvent {
+    pub pool: Pubkey,
+    pub intervals: i64,
+    /// Funding per unit of the crowded side's stake over this accrual,
+    /// scaled by FUNDING_INDEX_SCALE
+    pub crowded_rate: i64,
+    pub long_funding_index: i64,
+    pub short_funding_index: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionIncreasedEvent {
+    pub trader: Pubkey,
+    pub pool: Pubkey,
+    pub position_type: PositionType,
+    pub added_amount: u64,
+    pub added_entry_index: u8,
+    pub amount: u64,
+    pub average_entry_index: u8,
+}
+
+#[event]
+pub struct PositionTransferredEvent {
+    pub position: Pubkey,
+    pub pool: Pubkey,
+    pub from: Pubkey,
+    pub to: Pubkey,
+}
+
+#[event]
+pub struct PositionTokenizedEvent {
+    pub position: Pubkey,
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub mint: Pubkey,
+}
+
+#[event]
+pub struct PositionListedEvent {
+    pub position: Pubkey,
+    pub pool: Pubkey,
+    pub seller: Pubkey,
+    pub price: u64,
+}
+
+#[event]
+pub struct ListingCancelledEvent {
+    pub position: Pubkey,
+    pub seller: Pubkey,
+}
+
+#[event]
+pub struct PositionSoldEvent {
+    pub position: Pubkey,
+    pub pool: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub price: u64,
+}
+
+#[event]
+pub struct PayoutScaledEvent {
+    pub pool: Pubkey,
+    pub position: Pubkey,
+    pub full_payout: u64,
+    pub payout: u64,
+    pub outstanding_liability: u64,
+    pub vault_balance: u64,
+}
+
+#[event]
+pub struct FeesWithdrawnEvent {
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub fees_collected: u64,
+    pub fees_withdrawn: u64,
+}
+
+#[event]
+pub struct ProtocolPauseEvent {
+    pub trading_paused: bool,
+    pub settlement_paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StakeUpdatedEvent {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    /// Positive when staking, negative when unstaking
+    pub delta: i64,
+    pub balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OracleSlashedEvent {
+    pub pool: Pubkey,
+    pub oracle: Pubkey,
+    pub disputer: Pubkey,
+    pub submitted_index: u8,
+    pub final_index: u8,
+    pub slashed: u64,
+    pub disputer_reward: u64,
+    pub insurance_amount: u64,
+    pub burned: u64,
+}
+
+#[event]
+pub struct CircuitBreakerTrippedEvent {
+    pub match_id: String,
+    pub current_index: u8,
+    pub rejected_index: u8,
+    pub timestamp: i64,
+}
+
+#[error_code]
+pub enum TradingError {
+    #[msg("Pool is not active")]
+    PoolNotActive,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Unauthorized oracle")]
+    UnauthorizedOracle,
+    #[msg("Invalid momentum index")]
+    InvalidMomentumIndex,
+    #[msg("Position already settled")]
+    AlreadySettled,
+    #[msg("Trading window has not ended")]
+    WindowNotEnded,
+    #[msg("Revealed momentum does not match commitment")]
+    CommitmentMismatch,
+    #[msg("Momentum commitment cannot be revealed yet")]
+    RevealTooEarly,
+    #[msg("Invalid insurance vault")]
+    InvalidInsuranceVault,
+    #[msg("Insufficient liquidity to cover payout")]
+    InsufficientLiquidity,
+    #[msg("Invalid pool configuration")]
+    InvalidPoolConfig,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Unsupported account version")]
+    UnsupportedAccountVersion,
+    #[msg("Oracle heartbeat lost")]
+    OracleHeartbeatLost,
+    #[msg("Position has not been settled")]
+    PositionNotSettled,
+    #[msg("Settlement grace period has not elapsed")]
+    InGracePeriod,
+    #[msg("Exit index snapshot already taken")]
+    SnapshotAlreadyTaken,
+    #[msg("Exit index is no longer available for snapshot")]
+    SnapshotUnavailable,
+    #[msg("Checkpoint already recorded")]
+    CheckpointAlreadyRecorded,
+    #[msg("Checkpoint has not been reached")]
+    CheckpointNotReached,
+    #[msg("Momentum index moved beyond acceptable entry")]
+    EntrySlippageExceeded,
+    #[msg("Trader is not allowlisted for this pool")]
+    NotAllowlisted,
+    #[msg("Invalid pool status transition")]
+    InvalidStatusTransition,
+    #[msg("Traders cannot refer themselves")]
+    SelfReferral,
+    #[msg("Referrer token account does not belong to the referrer")]
+    InvalidReferrerAccount,
+    #[msg("Minimum hold time has not elapsed")]
+    MinHoldNotMet,
+    #[msg("Invalid token account")]
+    InvalidTokenAccount,
+    #[msg("Token account is not owned by the payout recipient")]
+    InvalidPayoutRecipient,
+    #[msg("Momentum update can no longer be corrected")]
+    CorrectionNotAllowed,
+    #[msg("Pool open interest capacity reached")]
+    PoolCapacityReached,
+    #[msg("Pool has been finalized")]
+    PoolFinalized,
+    #[msg("No excess collateral to sweep")]
+    NothingToSweep,
+    #[msg("Invalid oracle signature")]
+    InvalidOracleSignature,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Token account is not delegated to the signer for this amount")]
+    InvalidDelegation,
+    #[msg("Pool has reached its maximum number of open positions")]
+    MaxPositionsReached,
+    #[msg("Position has not been expired long enough to force-settle")]
+    PositionNotExpired,
+    #[msg("Oracle confidence must be between 1 and 100")]
+    InvalidConfidence,
+    #[msg("Collateral tokens cannot be recovered")]
+    CollateralMintProtected,
+    #[msg("Pool still has open positions")]
+    PositionsStillOpen,
+    #[msg("Match id and team names must be non-empty and within length limits")]
+    InvalidPoolMetadata,
+    #[msg("Oracle has been disabled for this pool")]
+    OracleDisabled,
+    #[msg("Claim deadline has passed")]
+    ClaimDeadlinePassed,
+    #[msg("Number of pools and momentum indices must match")]
+    BatchLengthMismatch,
+    #[msg("Pool fees exceed the protocol fee ceiling")]
+    FeeExceedsCeiling,
+    #[msg("Only Paper pools can be reset")]
+    LivePoolNotResettable,
+    #[msg("Position belongs to a pool epoch that has been reset")]
+    StalePosition,
+    #[msg("Position has no escrowed payout")]
+    NothingToClaim,
+    #[msg("Escrowed payout is still timelocked")]
+    PayoutLocked,
+    #[msg("Escrowed payout is on hold")]
+    PayoutHeld,
+    #[msg("Position does not belong to this pool")]
+    PositionPoolMismatch,
+    #[msg("Window can only be shortened to a future time before its current end")]
+    InvalidWindow,
+    #[msg("Too few oracles agree on the final momentum index")]
+    QuorumNotMet,
+    #[msg("Compounding requires a non-zero payout, a new position account and the trader as signer")]
+    InvalidCompound,
+    #[msg("Timestamp out of range")]
+    InvalidTimestamp,
+    #[msg("Payout swaps are not enabled for this pool")]
+    SwapDisabled,
+    #[msg("Swap accounts are required to settle into stablecoin")]
+    MissingSwapAccounts,
+    #[msg("Swap account does not match the pool configuration")]
+    InvalidSwapAccount,
+    #[msg("Received less than the minimum output")]
+    SlippageExceeded,
+    #[msg("Pool is not a member of this league")]
+    InvalidLeague,
+    #[msg("League registry is full")]
+    LeagueFull,
+    #[msg("Account layout exceeds its allocated size")]
+    AccountSizeMismatch,
+    #[msg("Window has already ended; settle instead")]
+    WindowEnded,
+    #[msg("A momentum commitment is awaiting reveal")]
+    CommitmentPending,
+    #[msg("Stop-loss must be on the losing side of the entry index")]
+    InvalidStopLoss,
+    #[msg("Stop-loss has not been triggered")]
+    StopLossNotTriggered,
+    #[msg("Take-profit must be on the winning side of the entry index")]
+    InvalidTakeProfit,
+    #[msg("Take-profit has not been reached")]
+    TakeProfitNotReached,
+    #[msg("Invalid limit order")]
+    InvalidLimitOrder,
+    #[msg("Limit order trigger index has not been reached")]
+    OrderNotTriggered,
+    #[msg("Leverage outside the allowed range")]
+    InvalidLeverage,
+    #[msg("Insufficient margin balance")]
+    InsufficientMargin,
+    #[msg("Not supported for leveraged positions")]
+    LeverageNotSupported,
+    #[msg("Position is above its maintenance margin")]
+    NotLiquidatable,
+    #[msg("Funding is not enabled for this pool")]
+    FundingDisabled,
+    #[msg("No funding interval has elapsed since the last accrual")]
+    FundingNotDue,
+    #[msg("Position cannot be transferred to its current owner")]
+    InvalidTransfer,
+    #[msg("Not available for tokenized positions; the token holder settles them")]
+    PositionTokenized,
+    #[msg("Metadata URI is too long")]
+    InvalidMetadataUri,
+    #[msg("Tokenized position requires its token and holder to settle")]
+    MissingPositionToken,
+    #[msg("Token account does not hold this position's token")]
+    InvalidPositionToken,
+    #[msg("Not available in pari-mutuel pools")]
+    NotSupportedInPariMutuel,
+    #[msg("Window duration is outside the protocol limits")]
+    WindowDurationOutOfRange,
+    #[msg("Paused protocol-wide")]
+    ProtocolPaused,
+    #[msg("Fee discount staking is not enabled")]
+    StakingDisabled,
+    #[msg("Stake is held in a different mint")]
+    InvalidStakeMint,
+    #[msg("Insufficient staked balance")]
+    InsufficientStake,
+    #[msg("Stake is still locked")]
+    StakeLocked,
+    #[msg("This pool aggregates oracle readings; submit a reading instead")]
+    OracleQuorumRequired,
+    #[msg("This pool does not aggregate oracle readings")]
+    OracleQuorumNotConfigured,
+    #[msg("Every oracle slot is taken")]
+    OracleSlotsFull,
+    #[msg("Oracle has not bonded the stake this pool requires")]
+    InsufficientOracleStake,
+    #[msg("Oracle slashing is not enabled")]
+    SlashingDisabled,
+    #[msg("Submission is within tolerance of the final index, already slashed, or not on a protocol pool")]
+    NotSlashable,
+    #[msg("The pool takes index updates from a different source")]
+    WrongOracleSource,
+    #[msg("Invalid Switchboard aggregator account")]
+    InvalidSwitchboardFeed,
+    #[msg("Feed round is stale or already applied")]
+    StaleFeed,
+    #[msg("Feed responses disagree by more than the pool allows")]
+    FeedConfidenceTooLow,
+    #[msg("Invalid Pyth price account")]
+    InvalidPriceFeed,
+    #[msg("Collateral price is missing or stale")]
+    StalePrice,
+    #[msg("Collateral price confidence interval is too wide")]
+    PriceConfidenceTooLow,
+    #[msg("Position exceeds the pool's USD size limit")]
+    PositionValueExceeded,
+    #[msg("Signed update sequence number has already been used")]
+    OracleSequenceReplayed,
+    #[msg("Momentum index is stale; the oracle feed has stopped updating")]
+    StaleOracle,
+    #[msg("Pool is halted by its circuit breaker")]
+    PoolHalted,
+    #[msg("Momentum update arrived inside the cooldown")]
+    UpdateCooldown,
+    #[msg("Momentum update moves the index further than the pool allows")]
+    IndexDeltaExceeded,
+    #[msg("Stake account is not the trader's stake address")]
+    InvalidStakeAccount,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::solana_program::entrypoint::ProgramResult;
+    use anchor_lang::solana_program::program_pack::Pack;
+    use anchor_lang::InstructionData;
+    use anchor_spl::token::spl_token;
+    use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+    use solana_sdk::account::Account as SolanaAccount;
+    use solana_sdk::signature::{Keypair, Signer as _};
+    use solana_sdk::transaction::Transaction;
+
+    const MATCH_ID: &str = "match-1";
+    const STAKE: u64 = 1_000;
+    const VAULT_BALANCE: u64 = 1_000_000;
+
+    fn position(position_type: PositionType, amount: u64, entry_index: u8) -> TradingPosition {
+        TradingPosition {
+            trader: Pubkey::default(),
+            pool: Pubkey::default(),
+            position_type,
+            amount,
+            entry_momentum_index: entry_index,
+            exit_momentum_index: 0,
+            entry_time: 0,
+            window_end_time: 0,
+            window_duration: 0,
+            is_settled: false,
+            pnl: 0,
+            settled_at: 0,
+            opened_during_commit: false,
+            version: POSITION_VERSION,
+            exit_snapshot_index: None,
+            checkpoint: None,
+            client_tag: [0; 16],
+            referrer: None,
+            payout_recipient: Pubkey::default(),
+            profit_fee_bps: PROFIT_FEE_BPS as u16,
+            index_scale_max: DEFAULT_INDEX_SCALE_MAX,
+            max_liability: 0,
+            collateral_mint: Pubkey::default(),
+            entry_total_long_volume: 0,
+            entry_total_short_volume: 0,
+            mark_pnl: 0,
+            mark_index: 0,
+            marked_at: 0,
+            pool_epoch: 0,
+            escrowed_payout: 0,
+            claimable_at: 0,
+            payout_held: false,
+            entry_spread: 0,
+            stop_loss_index: None,
+            take_profit_index: None,
+            leverage: 0,
+            margin: 0,
+            maintenance_margin_bps: 0,
+            entry_funding_index: 0,
+            position_mint: None,
+        }
+    }
+
+    fn pool() -> MomentumPool {
+        MomentumPool {
+            match_id: MATCH_ID.to_string(),
+            home_team: "Home".to_string(),
+            away_team: "Away".to_string(),
+            index_scale_max: DEFAULT_INDEX_SCALE_MAX,
+            current_momentum_index: 50,
+            protocol_limits: ProtocolLimits::default(),
+            ..Default::default()
+        }
+    }
+
+    /// A winning long in `pool`, whose window ended `ended_ago` seconds
+    /// before `now` after running for `window_duration`
+    fn settleable_position(pool: &mut MomentumPool, now: i64, ended_ago: i64) -> TradingPosition {
+        let mut position = position(PositionType::Long, STAKE, 50);
+        position.window_duration = 60;
+        position.window_end_time = now - ended_ago;
+        position.entry_time = position.window_end_time - position.window_duration;
+        position.max_liability = pool.max_liability(&position).unwrap();
+        pool.total_long_volume += position.amount;
+        pool.outstanding_liability += position.max_liability;
+        pool.open_positions += 1;
+        pool.current_momentum_index = 60;
+        pool.last_update = position.window_end_time - 10;
+        position
+    }
+
+    fn process_instruction(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+        // The entrypoint ties the slice to its accounts' lifetime
+        let accounts = Box::leak(Box::new(accounts.to_vec()));
+        entry(program_id, accounts, data)
+    }
+
+    fn program_account<T: AccountSerialize>(value: &T, space: usize) -> SolanaAccount {
+        let mut data = Vec::with_capacity(space);
+        value.try_serialize(&mut data).unwrap();
+        data.resize(space, 0);
+        SolanaAccount {
+            lamports: 1_000_000_000,
+            data,
+            owner: crate::ID,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn token_account(mint: Pubkey, owner: Pubkey, amount: u64, state: spl_token::state::AccountState) -> SolanaAccount {
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        spl_token::state::Account {
+            mint,
+            owner,
+            amount,
+            state,
+            ..Default::default()
+        }
+        .pack_into_slice(&mut data);
+        SolanaAccount {
+            lamports: 1_000_000_000,
+            data,
+            owner: spl_token::ID,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    /// A pool, its vault and a position ready to settle, loaded into a
+    /// running test validator
+    struct SettleFixture {
+        context: ProgramTestContext,
+        trader: Keypair,
+        mint: Pubkey,
+        pool: Pubkey,
+        position: Pubkey,
+        vault: Pubkey,
+        user_token_account: Pubkey,
+        protocol_config: Pubkey,
+    }
+
+    impl SettleFixture {
+        /// `ended_ago` seconds after the position's window ended; `configure`
+        /// may adjust the pool and position before they are written
+        async fn new(ended_ago: i64, configure: impl FnOnce(&mut MomentumPool, &mut TradingPosition)) -> Self {
+            let program_test = ProgramTest::new("futstar_momentum_trading", crate::ID, processor!(process_instruction));
+            let mut context = program_test.start_with_context().await;
+            let now = context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp;
+
+            let trader = Keypair::new();
+            let mint = Pubkey::new_unique();
+            let (pool_key, _) = Pubkey::find_program_address(&[b"momentum_pool", MATCH_ID.as_bytes()], &crate::ID);
+            let (vault, _) = Pubkey::find_program_address(&[b"vault", pool_key.as_ref(), mint.as_ref()], &crate::ID);
+            let (protocol_config, protocol_bump) = Pubkey::find_program_address(&[b"protocol_config"], &crate::ID);
+            let position_key = Pubkey::new_unique();
+            let user_token_account = Pubkey::new_unique();
+
+            let mut pool = pool();
+            pool.collateral_mint = mint;
+            let mut position = settleable_position(&mut pool, now, ended_ago);
+            position.trader = trader.pubkey();
+            position.payout_recipient = trader.pubkey();
+            position.pool = pool_key;
+            position.collateral_mint = mint;
+            configure(&mut pool, &mut position);
+
+            let config = ProtocolConfig {
+                admin: Pubkey::new_unique(),
+                max_profit_fee_bps: 10_000,
+                bump: protocol_bump,
+                limits: ProtocolLimits::default(),
+                trading_paused: false,
+                settlement_paused: false,
+                stake_mint: Pubkey::default(),
+                fee_tiers: Default::default(),
+                oracle_slashing: OracleSlashing::default(),
+            };
+            let space = 8 + MomentumPool::space(&pool.match_id, &pool.home_team, &pool.away_team);
+            context.set_account(&pool_key, &program_account(&pool, space).into());
+            context.set_account(&position_key, &program_account(&position, 8 + TradingPosition::LEN).into());
+            context.set_account(&protocol_config, &program_account(&config, 8 + ProtocolConfig::LEN).into());
+            let initialized = spl_token::state::AccountState::Initialized;
+            context.set_account(&vault, &token_account(mint, pool_key, VAULT_BALANCE, initialized).into());
+            context.set_account(
+                &user_token_account,
+                &token_account(mint, trader.pubkey(), 0, initialized).into(),
+            );
+
+            Self {
+                context,
+                trader,
+                mint,
+                pool: pool_key,
+                position: position_key,
+                vault,
+                user_token_account,
+                protocol_config,
+            }
+        }
+
+        fn settle_accounts(&self) -> crate::accounts::SettlePosition {
+            let (trader_stats, _) =
+                Pubkey::find_program_address(&[b"trader_stats", self.trader.pubkey().as_ref()], &crate::ID);
+            crate::accounts::SettlePosition {
+                trading_position: self.position,
+                momentum_pool: self.pool,
+                protocol_config: self.protocol_config,
+                user_token_account: self.user_token_account,
+                pool_token_account: self.vault,
+                insurance_fund: None,
+                insurance_vault: None,
+                referrer_token_account: None,
+                trader_positions: None,
+                trader_stats,
+                compound_position: None,
+                allowlist_entry: None,
+                swap_program: None,
+                swap_pool: None,
+                swap_authority: None,
+                swap_source: None,
+                swap_destination: None,
+                swap_pool_mint: None,
+                swap_fee_account: None,
+                stable_token_account: None,
+                collateral_mint: None,
+                swap_escrow: None,
+                position_mint: None,
+                position_token_account: None,
+                position_holder: None,
+                momentum_history: None,
+                stake_account: None,
+                payer: self.context.payer.pubkey(),
+                token_program: spl_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            }
+        }
+
+        async fn send(&mut self, accounts: crate::accounts::SettlePosition) -> std::result::Result<(), u32> {
+            let data = crate::instruction::SettlePosition {
+                compound_window: None,
+                swap_min_out: None,
+            };
+            self.send_instruction(accounts, data).await
+        }
+
+        async fn send_instruction(
+            &mut self,
+            accounts: impl ToAccountMetas,
+            data: impl InstructionData,
+        ) -> std::result::Result<(), u32> {
+            let instruction = Instruction {
+                program_id: crate::ID,
+                accounts: accounts.to_account_metas(None),
+                data: data.data(),
+            };
+            let mut signers = vec![&self.context.payer];
+            let trader = self.trader.pubkey();
+            if instruction.accounts.iter().any(|meta| meta.is_signer && meta.pubkey == trader) {
+                signers.push(&self.trader);
+            }
+            let blockhash = self.context.banks_client.get_latest_blockhash().await.unwrap();
+            let transaction = Transaction::new_signed_with_payer(
+                &[instruction],
+                Some(&self.context.payer.pubkey()),
+                &signers,
+                blockhash,
+            );
+            self.context
+                .banks_client
+                .process_transaction(transaction)
+                .await
+                .map_err(|error| match error.unwrap() {
+                    solana_sdk::transaction::TransactionError::InstructionError(
+                        _,
+                        solana_sdk::instruction::InstructionError::Custom(code),
+                    ) => code,
+                    other => panic!("unexpected error: {other:?}"),
+                })
+        }
+
+        async fn settle(&mut self) -> std::result::Result<(), u32> {
+            self.send(self.settle_accounts()).await
+        }
+
+        async fn fetch<T: AccountDeserialize>(&mut self, key: Pubkey) -> T {
+            let account = self.context.banks_client.get_account(key).await.unwrap().unwrap();
+            T::try_deserialize(&mut account.data.as_slice()).unwrap()
+        }
+
+        async fn token_balance(&mut self, key: Pubkey) -> u64 {
+            let account = self.context.banks_client.get_account(key).await.unwrap().unwrap();
+            spl_token::state::Account::unpack(&account.data).unwrap().amount
+        }
+
+        fn set_vault_state(&mut self, state: spl_token::state::AccountState) {
+            let vault = token_account(self.mint, self.pool, VAULT_BALANCE, state);
+            self.context.set_account(&self.vault, &vault.into());
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_payout_transfer_leaves_position_unsettled() {
+        let mut fixture = SettleFixture::new(10, |_, _| {}).await;
+        let liability_before = fixture.fetch::<MomentumPool>(fixture.pool).await.outstanding_liability;
+
+        // A frozen vault makes the payout transfer fail after settlement
+        // has been recorded in memory
+        fixture.set_vault_state(spl_token::state::AccountState::Frozen);
+        assert_eq!(
+            fixture.settle().await,
+            Err(spl_token::error::TokenError::AccountFrozen as u32)
+        );
+
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(!position.is_settled);
+        assert_eq!(position.pnl, 0);
+        let pool: MomentumPool = fixture.fetch(fixture.pool).await;
+        assert_eq!(pool.outstanding_liability, liability_before);
+        assert_eq!(pool.total_long_volume, STAKE);
+        assert_eq!(fixture.token_balance(fixture.user_token_account).await, 0);
+
+        // Once the transfer can go through, the same position settles normally
+        fixture.set_vault_state(spl_token::state::AccountState::Initialized);
+        fixture.settle().await.unwrap();
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(position.is_settled);
+        assert!(fixture.token_balance(fixture.user_token_account).await > STAKE);
+    }
+
+    fn bonus_config() -> PoolConfig {
+        PoolConfig {
+            bonus_window: 600,
+            max_entry_bonus_bps: 2_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn entry_bonus_is_full_for_an_entry_at_the_window_start() {
+        let config = bonus_config();
+        assert_eq!(config.entry_bonus_bps(600), 2_000);
+        // Committing beyond the bonus window earns no more
+        assert_eq!(config.entry_bonus_bps(3_600), 2_000);
+    }
+
+    #[test]
+    fn entry_bonus_scales_linearly_for_an_entry_mid_window() {
+        let config = bonus_config();
+        assert_eq!(config.entry_bonus_bps(300), 1_000);
+        assert_eq!(config.entry_bonus_bps(150), 500);
+    }
+
+    #[test]
+    fn entry_bonus_vanishes_for_an_entry_at_the_window_end() {
+        let config = bonus_config();
+        assert_eq!(config.entry_bonus_bps(1), 3);
+        assert_eq!(config.entry_bonus_bps(0), 0);
+        assert_eq!(config.entry_bonus_bps(-60), 0);
+    }
+
+    #[test]
+    fn entry_bonus_is_off_without_a_bonus_window() {
+        let config = PoolConfig {
+            max_entry_bonus_bps: 2_000,
+            ..Default::default()
+        };
+        assert_eq!(config.entry_bonus_bps(600), 0);
+    }
 
-impl MomentumPool {
-    pub const LEN: usize = 32 + 64 + 8 + 32 + 32 + 8 + 8 + 1 + 1 + 8 + 8 + 128; // Buffer for strings
-}
+    #[test]
+    fn early_entry_earns_a_larger_payout_than_a_late_one() {
+        let config = bonus_config();
+        let payout_for = |window_duration| {
+            let mut position = position(PositionType::Long, 10_000, 50);
+            position.window_duration = window_duration;
+            let (payout, _) = position_payout(&position, 60, &config.payout_config(&position)).unwrap();
+            payout
+        };
+        // 10 points on a 100 scale is 1_000 profit before the bonus and fee
+        assert_eq!(payout_for(600), 10_000 + 1_200 - 24);
+        assert_eq!(payout_for(300), 10_000 + 1_100 - 22);
+        assert_eq!(payout_for(0), 10_000 + 1_000 - 20);
+    }
 
-#[account]
-pub struct TradingPosition {
-    pub trader: Pubkey,
-    pub pool: Pubkey,
-    pub position_type: PositionType,
-    pub amount: u64,
-    pub entry_momentum_index: u8,
-    pub exit_momentum_index: u8,
-    pub entry_time: i64,
-    pub window_end_time: i64,
-    pub is_settled: bool,
-    pub pnl: i64,
-    pub settled_at: i64,
-}
+    #[test]
+    fn pool_with_longest_names_serializes_into_its_space() {
+        let mut pool = pool();
+        pool.match_id = "m".repeat(MAX_MATCH_ID_LEN);
+        // Multi-byte names count by their encoded length
+        pool.home_team = "é".repeat(MAX_TEAM_NAME_LEN / 2);
+        pool.away_team = "a".repeat(MAX_TEAM_NAME_LEN);
+        let pool = pool.fully_populated();
 
-impl TradingPosition {
-    pub const LEN: usize = 32 + 32 + 1 + 8 + 1 + 1 + 8 + 8 + 1 + 8 + 8;
-}
+        let space = 8 + MomentumPool::space(&pool.match_id, &pool.home_team, &pool.away_team);
+        let mut data = vec![0u8; space];
+        pool.try_serialize(&mut data.as_mut_slice()).unwrap();
+        assert_eq!(pool.try_to_vec().unwrap().len() + 8, space);
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
-pub enum PositionType {
-    Long,
-    Short,
-}
+        let restored = MomentumPool::try_deserialize(&mut data.as_slice()).unwrap();
+        assert_eq!(restored.home_team, pool.home_team);
+        assert_eq!(restored.away_team, pool.away_team);
+    }
 
-#[event]
-pub struct MomentumUpdateEvent {
-    pub match_id: String,
-    pub old_index: u8,
-    pub new_index: u8,
-    pub timestamp: i64,
-}
+    #[test]
+    fn pool_space_grows_with_its_names() {
+        let short = MomentumPool::space("m", "A", "B");
+        let long = MomentumPool::space(
+            &"m".repeat(MAX_MATCH_ID_LEN),
+            &"a".repeat(MAX_TEAM_NAME_LEN),
+            &"b".repeat(MAX_TEAM_NAME_LEN),
+        );
+        assert_eq!(long - short, MAX_MATCH_ID_LEN - 1 + 2 * (MAX_TEAM_NAME_LEN - 1));
+    }
 
-#[event]
-pub struct PositionSettledEvent {
-    pub trader: Pubkey,
-    pub position_type: PositionType,
-    pub pnl: i64,
-    pub entry_index: u8,
-    pub exit_index: u8,
-}
+    fn payout_config() -> PayoutConfig {
+        PayoutConfig {
+            profit_fee_bps: PROFIT_FEE_BPS,
+            index_scale_max: DEFAULT_INDEX_SCALE_MAX as u64,
+            ..Default::default()
+        }
+    }
 
-#[error_code]
-pub enum TradingError {
-    #[msg("Pool is not active")]
-    PoolNotActive,
-    #[msg("Invalid amount")]
-    InvalidAmount,
-    #[msg("Unauthorized oracle")]
-    UnauthorizedOracle,
-    #[msg("Invalid momentum index")]
-    InvalidMomentumIndex,
-    #[msg("Position already settled")]
-    AlreadySettled,
-    #[msg("Trading window has not ended")]
-    WindowNotEnded,
+    #[test]
+    fn compute_payout_table() {
+        use PositionType::{Long, Short};
+        // (side, entry, exit, expected payout, expected fee) on a 10_000 stake
+        let cases = [
+            // Wins: 1% of the stake per point, less the 2% profit fee
+            (Long, 50, 60, 10_980, 20),
+            (Short, 50, 40, 10_980, 20),
+            (Long, 50, 51, 10_098, 2),
+            (Short, 50, 49, 10_098, 2),
+            (Long, 0, 100, 19_800, 200),
+            (Short, 100, 0, 19_800, 200),
+            // Losses forfeit the stake
+            (Long, 50, 40, 0, 0),
+            (Short, 50, 60, 0, 0),
+            (Long, 100, 0, 0, 0),
+            (Short, 0, 100, 0, 0),
+            // Neutral: no move is not a win
+            (Long, 50, 50, 0, 0),
+            (Short, 50, 50, 0, 0),
+            (Long, 0, 0, 0, 0),
+            (Short, 100, 100, 0, 0),
+        ];
+        for (side, entry, exit, payout, fee) in cases {
+            assert_eq!(
+                compute_payout(&side, entry, exit, 10_000, &payout_config()).unwrap(),
+                (payout, fee),
+                "{side:?} {entry} -> {exit}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn settle_before_min_hold_is_rejected_even_after_the_window_ends() {
+        // Window ended 10s ago after 60s, so the position is 70s old
+        let mut fixture = SettleFixture::new(10, |pool, _| pool.config.min_hold_seconds = 120).await;
+        assert_eq!(fixture.settle().await, Err(TradingError::MinHoldNotMet.into()));
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(!position.is_settled);
+    }
+
+    #[tokio::test]
+    async fn settle_after_min_hold_succeeds() {
+        // Held for 130s
+        let mut fixture = SettleFixture::new(70, |pool, _| pool.config.min_hold_seconds = 120).await;
+        fixture.settle().await.unwrap();
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(position.is_settled);
+    }
+
+    fn skewed_pool(long: u64, short: u64) -> MomentumPool {
+        let mut pool = pool();
+        pool.config.skew_fee_bps = 100;
+        pool.total_long_volume = long;
+        pool.total_short_volume = short;
+        pool
+    }
+
+    #[test]
+    fn entry_fee_is_the_base_fee_in_a_balanced_pool() {
+        for pool in [skewed_pool(0, 0), skewed_pool(5_000, 5_000)] {
+            assert_eq!(pool.entry_fee_bps(&PositionType::Long), 200);
+            assert_eq!(pool.entry_fee_bps(&PositionType::Short), 200);
+        }
+    }
+
+    #[test]
+    fn entry_fee_at_extreme_skew_charges_the_crowded_side_the_full_adjustment() {
+        let all_long = skewed_pool(5_000, 0);
+        assert_eq!(all_long.entry_fee_bps(&PositionType::Long), 300);
+        assert_eq!(all_long.entry_fee_bps(&PositionType::Short), 100);
+
+        let all_short = skewed_pool(0, 5_000);
+        assert_eq!(all_short.entry_fee_bps(&PositionType::Long), 100);
+        assert_eq!(all_short.entry_fee_bps(&PositionType::Short), 300);
+    }
+
+    #[test]
+    fn entry_fee_scales_with_partial_skew() {
+        // Longs are 75% of volume: a 50% skew toward them
+        let pool = skewed_pool(7_500, 2_500);
+        assert_eq!(pool.entry_fee_bps(&PositionType::Long), 250);
+        assert_eq!(pool.entry_fee_bps(&PositionType::Short), 150);
+    }
+
+    #[test]
+    fn entry_fee_never_goes_negative() {
+        let mut pool = skewed_pool(5_000, 0);
+        pool.config.skew_fee_bps = 500;
+        assert_eq!(pool.entry_fee_bps(&PositionType::Short), 0);
+    }
+
+    #[test]
+    fn open_interest_cap_admits_stakes_up_to_the_boundary() {
+        let mut pool = pool();
+        pool.config.max_open_interest = 10_000;
+        pool.total_long_volume = 4_000;
+        pool.total_short_volume = 3_000;
+        pool.total_straddle_volume = 1_000;
+        assert!(pool.has_capacity_for(1_999));
+        assert!(pool.has_capacity_for(2_000));
+        assert!(!pool.has_capacity_for(2_001));
+    }
+
+    #[test]
+    fn open_interest_is_uncapped_by_default() {
+        let mut pool = pool();
+        pool.total_long_volume = u64::MAX / 2;
+        assert!(pool.has_capacity_for(u64::MAX / 4));
+    }
+
+    #[test]
+    fn positions_settle_on_the_scale_they_were_opened_under() {
+        // The pool has since been rescaled to 0-200
+        let mut pool = pool();
+        pool.index_scale_max = 200;
+        let payout_on = |index_scale_max| {
+            let mut position = position(PositionType::Long, 10_000, 50);
+            position.index_scale_max = index_scale_max;
+            position.profit_fee_bps = 0;
+            let (payout, _) = position_payout(&position, 60, &pool.config.payout_config(&position)).unwrap();
+            payout
+        };
+        // Ten points is 10% of the old scale but 5% of the new one
+        assert_eq!(payout_on(100), 11_000);
+        assert_eq!(payout_on(200), 10_500);
+        // Positions predating the field were opened on the default scale
+        assert_eq!(payout_on(0), 11_000);
+    }
+
+    #[test]
+    fn max_liability_uses_the_position_scale() {
+        let mut pool = pool();
+        pool.index_scale_max = 200;
+        let mut old = position(PositionType::Long, 10_000, 50);
+        old.index_scale_max = 100;
+        old.profit_fee_bps = 0;
+        let mut new = old.clone();
+        new.index_scale_max = 200;
+        // Best exits are 100 and 200: 50 points of 100 and 150 of 200
+        assert_eq!(pool.max_liability(&old).unwrap(), 15_000);
+        assert_eq!(pool.max_liability(&new).unwrap(), 17_500);
+    }
+
+    #[test]
+    fn mul_div_rounds_halves_up_only_when_asked() {
+        use RoundingMode::{RoundHalfUp, Truncate};
+        assert_eq!(mul_div(1, 1, 2, Truncate).unwrap(), 0);
+        assert_eq!(mul_div(1, 1, 2, RoundHalfUp).unwrap(), 1);
+        // Below a half rounds down either way
+        assert_eq!(mul_div(1, 1, 3, Truncate).unwrap(), 0);
+        assert_eq!(mul_div(1, 1, 3, RoundHalfUp).unwrap(), 0);
+        // Above a half
+        assert_eq!(mul_div(2, 1, 3, Truncate).unwrap(), 0);
+        assert_eq!(mul_div(2, 1, 3, RoundHalfUp).unwrap(), 1);
+        // Exact results are unaffected
+        assert_eq!(mul_div(10, 3, 5, Truncate).unwrap(), 6);
+        assert_eq!(mul_div(10, 3, 5, RoundHalfUp).unwrap(), 6);
+    }
+
+    #[test]
+    fn mul_div_rejects_overflow_and_zero_denominators() {
+        use RoundingMode::{RoundHalfUp, Truncate};
+        // The intermediate product may exceed u64 as long as the result fits
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX, Truncate).unwrap(), u64::MAX);
+        assert_eq!(mul_div(u64::MAX, 1, 1, RoundHalfUp).unwrap(), u64::MAX);
+        assert!(mul_div(u64::MAX, 2, 1, Truncate).is_err());
+        assert!(mul_div(1, 1, 0, Truncate).is_err());
+    }
+
+    #[test]
+    fn payout_rounding_mode_decides_a_half_unit_of_profit() {
+        // 1.5 units of profit on a one-point move
+        let payout_with = |rounding_mode| {
+            let config = PayoutConfig {
+                rounding_mode,
+                ..payout_config()
+            };
+            compute_payout(&PositionType::Long, 50, 51, 150, &config).unwrap()
+        };
+        assert_eq!(payout_with(RoundingMode::Truncate), (151, 0));
+        assert_eq!(payout_with(RoundingMode::RoundHalfUp), (152, 0));
+    }
+
+    #[test]
+    fn profit_fee_applies_from_the_min_profit_threshold() {
+        // A threshold of 0.01 tokens, for 6 and 9 decimal mints
+        for decimals in [6u32, 9] {
+            let threshold = 10u64.pow(decimals) / 100;
+            let config = PayoutConfig {
+                min_profit_for_fee: threshold,
+                ..payout_config()
+            };
+            // A one-point move earns 1% of the stake
+            let below = 100 * (threshold - 1);
+            let at = 100 * threshold;
+            assert_eq!(
+                compute_payout(&PositionType::Long, 50, 51, below, &config).unwrap(),
+                (below + threshold - 1, 0),
+                "{decimals} decimals below the threshold"
+            );
+            assert_eq!(
+                compute_payout(&PositionType::Long, 50, 51, at, &config).unwrap(),
+                (at + threshold - threshold / 50, threshold / 50),
+                "{decimals} decimals at the threshold"
+            );
+        }
+    }
+
+    fn pool_with_samples(samples: &[u8]) -> MomentumPool {
+        let mut pool = pool();
+        pool.config.volatility_deadband_bps = 10_000;
+        for (i, &index) in samples.iter().enumerate() {
+            pool.apply_momentum_update(index, i as i64, None);
+        }
+        pool
+    }
+
+    #[test]
+    fn deadband_widens_with_volatility() {
+        let calm = pool_with_samples(&[50, 51, 50, 51, 50]);
+        let volatile = pool_with_samples(&[50, 60, 50, 60, 50]);
+        assert_eq!(calm.deadband(), 1);
+        assert_eq!(volatile.deadband(), 10);
+
+        // A five-point move wins in a calm window but is noise in a volatile one
+        let payout_in = |pool: &MomentumPool| {
+            let position = position(PositionType::Long, 10_000, 50);
+            compute_payout(&PositionType::Long, 50, 55, 10_000, &pool.payout_config(&position))
+                .unwrap()
+                .0
+        };
+        assert_eq!(payout_in(&calm), 10_490);
+        assert_eq!(payout_in(&volatile), 10_000);
+    }
+
+    #[test]
+    fn deadband_widens_as_oracle_confidence_drops() {
+        let mut pool = pool_with_samples(&[50, 54, 50, 54, 50]);
+        assert_eq!(pool.deadband(), 4);
+        pool.oracle_confidence = FULL_CONFIDENCE / 2;
+        assert_eq!(pool.deadband(), 8);
+    }
+
+    #[test]
+    fn deadband_is_zero_without_samples_or_when_disabled() {
+        assert_eq!(pool_with_samples(&[]).deadband(), 0);
+        assert_eq!(pool_with_samples(&[70]).deadband(), 0);
+        let mut pool = pool_with_samples(&[50, 60, 50]);
+        pool.config.volatility_deadband_bps = 0;
+        assert_eq!(pool.deadband(), 0);
+    }
+
+    /// Make the pool its own position's payout recipient, so only the
+    /// self-transfer guards stand between a settle and the vault
+    fn pay_the_pool(pool: &mut MomentumPool, position: &mut TradingPosition) {
+        position.payout_recipient =
+            Pubkey::find_program_address(&[b"momentum_pool", pool.match_id.as_bytes()], &crate::ID).0;
+    }
+
+    #[tokio::test]
+    async fn settle_into_the_pool_vault_is_rejected() {
+        let mut fixture = SettleFixture::new(10, pay_the_pool).await;
+        let mut accounts = fixture.settle_accounts();
+        accounts.user_token_account = fixture.vault;
+        assert_eq!(fixture.send(accounts).await, Err(TradingError::InvalidTokenAccount.into()));
+
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(!position.is_settled);
+        assert_eq!(fixture.token_balance(fixture.vault).await, VAULT_BALANCE);
+    }
+
+    #[tokio::test]
+    async fn settle_into_another_pool_owned_account_is_rejected() {
+        let mut fixture = SettleFixture::new(10, pay_the_pool).await;
+        let pool_owned = Pubkey::new_unique();
+        let account = token_account(fixture.mint, fixture.pool, 0, spl_token::state::AccountState::Initialized);
+        fixture.context.set_account(&pool_owned, &account.into());
+        let mut accounts = fixture.settle_accounts();
+        accounts.user_token_account = pool_owned;
+        assert_eq!(fixture.send(accounts).await, Err(TradingError::InvalidTokenAccount.into()));
+        assert_eq!(fixture.token_balance(fixture.vault).await, VAULT_BALANCE);
+    }
+
+    /// 1-10 points pay 1x the stake, 11-25 pay 1.5x and 26+ pay 2x
+    fn tiered_bands() -> [PayoutBand; MAX_PAYOUT_BANDS] {
+        let band = |threshold, multiplier_bps| PayoutBand {
+            threshold,
+            multiplier_bps,
+        };
+        [band(1, 10_000), band(11, 15_000), band(26, 20_000), band(0, 0)]
+    }
+
+    #[test]
+    fn band_multiplier_changes_at_each_band_threshold() {
+        let config = PayoutConfig {
+            payout_bands: tiered_bands(),
+            ..payout_config()
+        };
+        let cases = [(0, 0), (1, 10_000), (10, 10_000), (11, 15_000), (25, 15_000), (26, 20_000), (100, 20_000)];
+        for (change, multiplier_bps) in cases {
+            assert_eq!(config.band_multiplier_bps(change), Some(multiplier_bps), "{change} points");
+        }
+        assert_eq!(payout_config().band_multiplier_bps(10), None);
+    }
+
+    #[test]
+    fn banded_payouts_follow_the_band_of_the_move() {
+        let config = PayoutConfig {
+            payout_bands: tiered_bands(),
+            profit_fee_bps: 0,
+            ..payout_config()
+        };
+        let payout = |exit| compute_payout(&PositionType::Short, 50, exit, 1_000, &config).unwrap().0;
+        assert_eq!(payout(40), 2_000);
+        assert_eq!(payout(39), 2_500);
+        assert_eq!(payout(25), 2_500);
+        assert_eq!(payout(24), 3_000);
+        assert_eq!(payout(0), 3_000);
+    }
+
+    #[test]
+    fn bands_must_rise_monotonically() {
+        let limits = ProtocolLimits::default();
+        let with_bands = |payout_bands| PoolConfig {
+            payout_bands,
+            ..Default::default()
+        };
+        assert!(with_bands(tiered_bands()).validate(&limits).is_ok());
+
+        let mut falling_threshold = tiered_bands();
+        falling_threshold[2].threshold = 11;
+        assert!(with_bands(falling_threshold).validate(&limits).is_err());
+
+        let mut falling_multiplier = tiered_bands();
+        falling_multiplier[2].multiplier_bps = 12_000;
+        assert!(with_bands(falling_multiplier).validate(&limits).is_err());
+
+        let mut gap = tiered_bands();
+        gap[1] = PayoutBand::default();
+        assert!(with_bands(gap).validate(&limits).is_err());
+    }
+
+    #[test]
+    fn claims_expire_strictly_after_the_claim_period() {
+        let mut pool = pool();
+        assert!(!pool.is_claim_expired(1_000, i64::MAX));
+        pool.config.claim_period = 300;
+        assert!(!pool.is_claim_expired(1_000, 1_000));
+        assert!(!pool.is_claim_expired(1_000, 1_300));
+        assert!(pool.is_claim_expired(1_000, 1_301));
+    }
+
+    #[test]
+    fn pari_mutuel_claims_run_from_finalization() {
+        let mut pool = pool();
+        pool.settlement_mode = SettlementMode::PariMutuel;
+        pool.config.claim_period = 300;
+        pool.finalized_at = 5_000;
+        assert!(!pool.is_claim_expired(1_000, 5_300));
+        assert!(pool.is_claim_expired(1_000, 5_301));
+    }
+
+    #[tokio::test]
+    async fn winner_settling_before_the_deadline_is_paid() {
+        let mut fixture = SettleFixture::new(10, |pool, _| pool.config.claim_period = 300).await;
+        fixture.settle().await.unwrap();
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(position.pnl > 0);
+        assert!(fixture.token_balance(fixture.user_token_account).await > STAKE);
+    }
+
+    #[tokio::test]
+    async fn winner_settling_after_the_deadline_forfeits() {
+        let mut fixture = SettleFixture::new(400, |pool, _| pool.config.claim_period = 300).await;
+        fixture.settle().await.unwrap();
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(position.is_settled);
+        assert_eq!(position.pnl, -(STAKE as i64));
+        assert_eq!(fixture.token_balance(fixture.user_token_account).await, 0);
+        assert_eq!(fixture.token_balance(fixture.vault).await, VAULT_BALANCE);
+    }
+
+    #[test]
+    fn compute_payout_covers_the_full_index_range_in_both_directions() {
+        let config = PayoutConfig {
+            profit_fee_bps: 0,
+            ..payout_config()
+        };
+        for entry in 0..=100u8 {
+            for exit in 0..=100u8 {
+                let (long, _) = compute_payout(&PositionType::Long, entry, exit, 100, &config).unwrap();
+                let (short, _) = compute_payout(&PositionType::Short, entry, exit, 100, &config).unwrap();
+                match exit.cmp(&entry) {
+                    std::cmp::Ordering::Greater => {
+                        assert_eq!(long, 100 + (exit - entry) as u64, "long {entry} -> {exit}");
+                        assert_eq!(short, 0, "short {entry} -> {exit}");
+                    }
+                    std::cmp::Ordering::Less => {
+                        assert_eq!(long, 0, "long {entry} -> {exit}");
+                        assert_eq!(short, 100 + (entry - exit) as u64, "short {entry} -> {exit}");
+                    }
+                    std::cmp::Ordering::Equal => assert_eq!((long, short), (0, 0), "{entry} -> {exit}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compute_payout_never_panics_across_every_u8_index() {
+        let config = PayoutConfig {
+            index_scale_max: u8::MAX as u64,
+            ..payout_config()
+        };
+        for entry in 0..=u8::MAX {
+            for exit in 0..=u8::MAX {
+                for side in [PositionType::Long, PositionType::Short, PositionType::Straddle] {
+                    assert!(compute_payout(&side, entry, exit, u32::MAX as u64, &config).is_ok());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn index_at_counts_an_update_stamped_exactly_at_the_time() {
+        let mut pool = pool();
+        pool.apply_momentum_update(50, 100, None);
+        pool.apply_momentum_update(60, 200, None);
+        pool.apply_momentum_update(70, 300, None);
+        assert_eq!(pool.index_at(99), None);
+        assert_eq!(pool.index_at(100), Some(50));
+        assert_eq!(pool.index_at(199), Some(50));
+        assert_eq!(pool.index_at(200), Some(60));
+        assert_eq!(pool.index_at(201), Some(60));
+        assert_eq!(pool.index_at(300), Some(70));
+    }
+
+    #[test]
+    fn exit_index_ignores_updates_landing_after_the_window_end() {
+        let mut pool = pool();
+        pool.apply_momentum_update(50, 100, None);
+        pool.apply_momentum_update(60, 200, None);
+        let mut position = position(PositionType::Long, STAKE, 50);
+        position.window_end_time = 200;
+
+        // Settling in the end second itself could race an update stamped in it
+        assert_eq!(pool.exit_index(&position, 200, None), Err(TradingError::InGracePeriod.into()));
+        assert_eq!(pool.exit_index(&position, 201, None).unwrap(), 60);
+
+        // An update after the end, applied before or after the settle,
+        // doesn't change the exit index
+        pool.apply_momentum_update(90, 201, None);
+        assert_eq!(pool.exit_index(&position, 201, None).unwrap(), 60);
+        assert_eq!(pool.exit_index(&position, 500, None).unwrap(), 60);
+    }
+
+    #[test]
+    fn entry_spread_is_clamped_at_the_index_bounds() {
+        let mut pool = pool();
+        pool.config.spread_points = 5;
+        let entry_at = |pool: &mut MomentumPool, current, position_type| {
+            pool.current_momentum_index = current;
+            pool.entry_index(&position_type)
+        };
+        assert_eq!(entry_at(&mut pool, 50, PositionType::Long), (55, 5));
+        assert_eq!(entry_at(&mut pool, 50, PositionType::Short), (45, 5));
+        assert_eq!(entry_at(&mut pool, 50, PositionType::Straddle), (50, 0));
+        // Clamped spreads are recorded as applied
+        assert_eq!(entry_at(&mut pool, 98, PositionType::Long), (100, 2));
+        assert_eq!(entry_at(&mut pool, 100, PositionType::Long), (100, 0));
+        assert_eq!(entry_at(&mut pool, 2, PositionType::Short), (0, 2));
+        assert_eq!(entry_at(&mut pool, 0, PositionType::Short), (0, 0));
+    }
+
+    #[test]
+    fn entry_spread_clamps_to_a_wider_scale() {
+        let mut pool = pool();
+        pool.config.spread_points = 5;
+        pool.index_scale_max = u8::MAX as u16;
+        pool.current_momentum_index = 253;
+        assert_eq!(pool.entry_index(&PositionType::Long), (u8::MAX, 2));
+    }
+
+    #[test]
+    fn window_end_time_rejects_extreme_durations_and_clocks() {
+        let now = 1_700_000_000;
+        assert_eq!(window_end_time(now, 60).unwrap(), now + 60);
+        assert_eq!(window_end_time(now, MAX_WINDOW_DURATION).unwrap(), now + MAX_WINDOW_DURATION);
+        for duration in [0, -1, i64::MIN, MAX_WINDOW_DURATION + 1, i64::MAX] {
+            assert_eq!(
+                window_end_time(now, duration),
+                Err(TradingError::InvalidTimestamp.into()),
+                "duration {duration}"
+            );
+        }
+        for clock in [0, -1, i64::MIN, i64::MAX] {
+            assert_eq!(
+                window_end_time(clock, 60),
+                Err(TradingError::InvalidTimestamp.into()),
+                "clock {clock}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn settling_a_settled_position_again_succeeds_without_effect() {
+        let mut fixture = SettleFixture::new(10, |_, _| {}).await;
+        fixture.settle().await.unwrap();
+        let settled: TradingPosition = fixture.fetch(fixture.position).await;
+        let pool: MomentumPool = fixture.fetch(fixture.pool).await;
+        let paid = fixture.token_balance(fixture.user_token_account).await;
+
+        // A retry in a later block, as a client whose confirmation was lost would send
+        fixture.context.get_new_latest_blockhash().await.unwrap();
+        fixture.settle().await.unwrap();
+
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert_eq!(position.settled_at, settled.settled_at);
+        assert_eq!(position.pnl, settled.pnl);
+        let pool_after: MomentumPool = fixture.fetch(fixture.pool).await;
+        assert_eq!(pool_after.outstanding_liability, pool.outstanding_liability);
+        assert_eq!(pool_after.fees_collected, pool.fees_collected);
+        assert_eq!(fixture.token_balance(fixture.user_token_account).await, paid);
+    }
+
+    fn counterparty_pool(long: u64, short: u64, straddle: u64) -> MomentumPool {
+        let mut pool = pool();
+        pool.config.counterparty_funded = true;
+        pool.total_long_volume = long;
+        pool.total_short_volume = short;
+        pool.total_straddle_volume = straddle;
+        pool
+    }
+
+    #[test]
+    fn counterparty_funded_winner_without_counterparty_is_refunded() {
+        let pool = counterparty_pool(STAKE, 0, 0);
+        let long = position(PositionType::Long, STAKE, 50);
+        assert_eq!(pool.counterparty_profit_cap(&long), 0);
+        let config = pool.payout_config(&long);
+        assert_eq!(compute_payout(&long.position_type, 50, 100, STAKE, &config).unwrap(), (STAKE, 0));
+    }
+
+    #[test]
+    fn counterparty_funded_winner_is_capped_at_the_opposing_volume() {
+        let pool = counterparty_pool(STAKE, 200, 0);
+        let long = position(PositionType::Long, STAKE, 50);
+        let config = pool.payout_config(&long);
+        assert_eq!(config.max_profit, Some(200));
+        // Uncapped profit would be 500; the 200 available pays a 2% fee
+        assert_eq!(compute_payout(&long.position_type, 50, 100, STAKE, &config).unwrap(), (STAKE + 196, 4));
+    }
+
+    #[test]
+    fn counterparty_funded_winner_with_ample_counterparty_is_uncapped() {
+        let pool = counterparty_pool(STAKE, 10 * STAKE, 0);
+        let long = position(PositionType::Long, STAKE, 50);
+        let config = pool.payout_config(&long);
+        assert_eq!(config.max_profit, Some(10 * STAKE));
+        assert_eq!(compute_payout(&long.position_type, 50, 100, STAKE, &config).unwrap(), (STAKE + 490, 10));
+    }
+
+    #[test]
+    fn counterparty_profit_cap_is_pro_rata_to_the_own_side() {
+        let pool = counterparty_pool(4 * STAKE, 300, 0);
+        assert_eq!(pool.counterparty_profit_cap(&position(PositionType::Long, STAKE, 50)), 75);
+        assert_eq!(pool.counterparty_profit_cap(&position(PositionType::Long, 4 * STAKE, 50)), 300);
+        // Shorts are funded by the long side
+        let pool = counterparty_pool(300, 4 * STAKE, 0);
+        assert_eq!(pool.counterparty_profit_cap(&position(PositionType::Short, STAKE, 50)), 75);
+    }
+
+    #[test]
+    fn counterparty_profit_cap_funds_straddles_from_both_directional_sides() {
+        let pool = counterparty_pool(100, 200, 2 * STAKE);
+        assert_eq!(pool.counterparty_profit_cap(&position(PositionType::Straddle, STAKE, 50)), 150);
+        assert_eq!(counterparty_pool(0, 0, STAKE).counterparty_profit_cap(&position(PositionType::Straddle, STAKE, 50)), 0);
+    }
+
+    #[test]
+    fn payout_cap_is_the_tighter_of_counterparty_and_multiplier_caps() {
+        let mut pool = counterparty_pool(STAKE, 200, 0);
+        pool.protocol_limits.max_payout_multiplier_bps = 11_000;
+        let long = position(PositionType::Long, STAKE, 50);
+        assert_eq!(pool.payout_config(&long).max_profit, Some(100));
+        pool.protocol_limits.max_payout_multiplier_bps = 15_000;
+        assert_eq!(pool.payout_config(&long).max_profit, Some(200));
+        pool.config.counterparty_funded = false;
+        assert_eq!(pool.payout_config(&long).max_profit, Some(500));
+    }
+
+    #[test]
+    fn settlement_failures_map_to_their_kind() {
+        for error in SettlementFailureKind::TRANSIENT {
+            assert_eq!(SettlementFailureKind::from_error_code(error.into()), SettlementFailureKind::Transient, "{error:?}");
+        }
+        for error in SettlementFailureKind::INVALID_REQUEST {
+            assert_eq!(
+                SettlementFailureKind::from_error_code(error.into()),
+                SettlementFailureKind::InvalidRequest,
+                "{error:?}"
+            );
+        }
+        for error in [TradingError::AlreadySettled, TradingError::MathOverflow, TradingError::InvalidTokenAccount] {
+            assert_eq!(SettlementFailureKind::from_error_code(error.into()), SettlementFailureKind::Permanent, "{error:?}");
+        }
+    }
+
+    #[test]
+    fn unknown_error_codes_are_permanent_settlement_failures() {
+        let anchor_error = u32::from(anchor_lang::error::ErrorCode::ConstraintHasOne);
+        for code in [0, anchor_error, u32::MAX] {
+            assert_eq!(SettlementFailureKind::from_error_code(code), SettlementFailureKind::Permanent);
+        }
+    }
+
+    #[test]
+    fn settlement_failure_kinds_do_not_overlap() {
+        for error in SettlementFailureKind::TRANSIENT {
+            let code = u32::from(error);
+            assert!(!SettlementFailureKind::INVALID_REQUEST.iter().any(|&other| u32::from(other) == code), "{error:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn settling_an_open_window_fails_transiently() {
+        let mut fixture = SettleFixture::new(-10, |_, _| {}).await;
+        let code = fixture.settle().await.unwrap_err();
+        assert_eq!(code, u32::from(TradingError::WindowNotEnded));
+        assert_eq!(SettlementFailureKind::from_error_code(code), SettlementFailureKind::Transient);
+    }
+
+    #[test]
+    fn straddle_pays_on_moves_in_either_direction() {
+        let config = payout_config();
+        let up = compute_payout(&PositionType::Straddle, 50, 70, 10_000, &config).unwrap();
+        let down = compute_payout(&PositionType::Straddle, 50, 30, 10_000, &config).unwrap();
+        assert_eq!(up, (11_960, 40));
+        assert_eq!(down, up);
+        assert_eq!(compute_payout(&PositionType::Straddle, 0, 100, 10_000, &config).unwrap(), (19_800, 200));
+        assert_eq!(compute_payout(&PositionType::Straddle, 100, 0, 10_000, &config).unwrap(), (19_800, 200));
+    }
+
+    #[test]
+    fn straddle_loses_the_stake_on_a_flat_window() {
+        let config = payout_config();
+        for index in [0, 50, 100] {
+            assert_eq!(compute_payout(&PositionType::Straddle, index, index, 10_000, &config).unwrap(), (0, 0));
+        }
+    }
+
+    #[test]
+    fn straddle_within_the_deadband_is_refunded() {
+        let config = PayoutConfig {
+            deadband: 2,
+            ..payout_config()
+        };
+        for exit in [48, 50, 52] {
+            assert_eq!(compute_payout(&PositionType::Straddle, 50, exit, 10_000, &config).unwrap(), (10_000, 0));
+        }
+        assert_eq!(compute_payout(&PositionType::Straddle, 50, 47, 10_000, &config).unwrap(), (10_294, 6));
+    }
+
+    #[test]
+    fn straddle_liability_assumes_the_farther_end_of_the_scale() {
+        let pool = pool();
+        let near_bottom = position(PositionType::Straddle, 10_000, 20);
+        let near_top = position(PositionType::Straddle, 10_000, 80);
+        assert_eq!(pool.max_liability(&near_bottom).unwrap(), 17_840);
+        assert_eq!(pool.max_liability(&near_top).unwrap(), 17_840);
+        assert_eq!(pool.max_liability(&position(PositionType::Straddle, 10_000, 50)).unwrap(), 14_900);
+    }
+
+    #[test]
+    fn straddle_entry_takes_no_side_and_pays_the_straddle_fee() {
+        let mut pool = skewed_pool(5_000, 0);
+        pool.config.straddle_fee_bps = 150;
+        assert_eq!(pool.entry_fee_bps(&PositionType::Straddle), 350);
+        let straddle = position(PositionType::Straddle, STAKE, 50);
+        assert_eq!(pool.funding_index(&straddle.position_type), 0);
+    }
+
+    #[test]
+    fn fully_populated_pool_serializes_to_exactly_its_fixed_len() {
+        let pool = pool().fully_populated();
+        let names_len = 4 + pool.match_id.len() + 4 + pool.home_team.len() + 4 + pool.away_team.len();
+        assert_eq!(pool.try_to_vec().unwrap().len(), MomentumPool::FIXED_LEN + names_len);
+        assert!(self::pool().try_to_vec().unwrap().len() < MomentumPool::FIXED_LEN + names_len);
+    }
+
+    #[test]
+    fn fully_populated_position_serializes_to_exactly_its_len() {
+        let position = position(PositionType::Long, STAKE, 50);
+        assert_eq!(position.fully_populated().try_to_vec().unwrap().len(), TradingPosition::LEN);
+        assert!(position.try_to_vec().unwrap().len() < TradingPosition::LEN);
+    }
+
+    #[test]
+    fn account_size_check_accepts_exactly_the_allocated_space() {
+        let position = position(PositionType::Long, STAKE, 50).fully_populated();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        for (len, fits) in [(8 + TradingPosition::LEN, true), (8 + TradingPosition::LEN - 1, false)] {
+            let mut data = vec![0u8; len];
+            let account = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &crate::ID, false, 0);
+            assert_eq!(check_account_size(&position, &account).is_ok(), fits, "{len}");
+        }
+    }
+
+    #[test]
+    fn swaps_are_only_allowed_through_the_spl_token_swap_program() {
+        let limits = ProtocolLimits::default();
+        let with_swap_program = |swap_program| PoolConfig {
+            swap_enabled: true,
+            swap_program,
+            swap_pool: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        assert!(with_swap_program(SPL_TOKEN_SWAP_PROGRAM_ID).validate(&limits).is_ok());
+        assert!(with_swap_program(Pubkey::new_unique()).validate(&limits).is_err());
+        assert!(with_swap_program(Pubkey::default()).validate(&limits).is_err());
+    }
+
+    async fn force_settle(fixture: &mut SettleFixture) -> std::result::Result<(), u32> {
+        let accounts = crate::accounts::ForceSettleExpired {
+            settle: fixture.settle_accounts(),
+            trader: fixture.trader.pubkey(),
+        };
+        let data = crate::instruction::ForceSettleExpired { close_account: true };
+        fixture.send_instruction(accounts, data).await
+    }
+
+    #[tokio::test]
+    async fn force_settle_keeps_a_position_holding_an_escrowed_payout() {
+        let mut fixture = SettleFixture::new(20, |pool, _| {
+            pool.config.force_settle_delay = 10;
+            pool.config.escrow_threshold = 100;
+        })
+        .await;
+        force_settle(&mut fixture).await.unwrap();
+
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert!(position.is_settled);
+        assert!(position.escrowed_payout > 100);
+        let pool: MomentumPool = fixture.fetch(fixture.pool).await;
+        assert_eq!(pool.outstanding_liability, position.escrowed_payout);
+        assert_eq!(fixture.token_balance(fixture.user_token_account).await, 0);
+    }
+
+    #[tokio::test]
+    async fn force_settle_closes_a_position_with_nothing_escrowed() {
+        let mut fixture = SettleFixture::new(20, |pool, _| {
+            pool.config.force_settle_delay = 10;
+            pool.config.escrow_threshold = 100;
+            // A loser owes nothing
+            pool.current_momentum_index = 40;
+        })
+        .await;
+        force_settle(&mut fixture).await.unwrap();
+        let account = fixture.context.banks_client.get_account(fixture.position).await.unwrap();
+        assert!(account.is_none());
+    }
+
+    #[test]
+    fn shortened_window_must_end_strictly_after_the_grace_period() {
+        let mut pool = pool();
+        let now = 1_000;
+        assert!(!pool.is_unknown_exit(now - 1, now));
+        assert!(!pool.is_unknown_exit(now, now));
+        assert!(!pool.is_unknown_exit(now + 1, now));
+        assert!(pool.is_unknown_exit(now + 2, now));
+
+        pool.config.settlement_grace_period = 30;
+        assert!(!pool.is_unknown_exit(now + 30, now));
+        assert!(pool.is_unknown_exit(now + 31, now));
+        assert!(!pool.is_unknown_exit(i64::MAX, i64::MAX));
+    }
+
+    #[tokio::test]
+    async fn shortening_a_window_to_end_now_is_rejected() {
+        let mut fixture = SettleFixture::new(-100, |_, position| {
+            position.window_duration = 200;
+            position.entry_time = position.window_end_time - position.window_duration;
+        })
+        .await;
+        let now = fixture.context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp;
+        let (position, pool, trader) = (fixture.position, fixture.pool, fixture.trader.pubkey());
+        let accounts = || crate::accounts::ShortenWindow {
+            trading_position: position,
+            momentum_pool: pool,
+            trader,
+        };
+        for new_window_end_time in [now, now + 1] {
+            let data = crate::instruction::ShortenWindow { new_window_end_time };
+            assert_eq!(
+                fixture.send_instruction(accounts(), data).await,
+                Err(TradingError::InvalidWindow.into())
+            );
+        }
+
+        let data = crate::instruction::ShortenWindow { new_window_end_time: now + 10 };
+        fixture.send_instruction(accounts(), data).await.unwrap();
+        let position: TradingPosition = fixture.fetch(fixture.position).await;
+        assert_eq!(position.window_end_time, now + 10);
+    }
+
+    #[tokio::test]
+    async fn only_the_protocol_admin_may_create_the_insurance_fund() {
+        let mut fixture = SettleFixture::new(10, |_, _| {}).await;
+        let (insurance_fund, _) = Pubkey::find_program_address(&[b"insurance_fund"], &crate::ID);
+        let insurance_vault = Pubkey::new_unique();
+        let initialized = spl_token::state::AccountState::Initialized;
+        fixture
+            .context
+            .set_account(&insurance_vault, &token_account(fixture.mint, insurance_fund, 0, initialized).into());
+
+        // The fixture's protocol admin isn't the payer
+        let accounts = crate::accounts::InitializeInsuranceFund {
+            insurance_fund,
+            insurance_vault,
+            protocol_config: fixture.protocol_config,
+            admin: fixture.context.payer.pubkey(),
+            system_program: anchor_lang::system_program::ID,
+        };
+        let data = crate::instruction::InitializeInsuranceFund { max_draw_per_settlement: 0 };
+        assert_eq!(
+            fixture.send_instruction(accounts, data).await,
+            Err(anchor_lang::error::ErrorCode::ConstraintHasOne.into())
+        );
+        let account = fixture.context.banks_client.get_account(insurance_fund).await.unwrap();
+        assert!(account.is_none());
+    }
+
+
+    #[test]
+    fn settlement_fee_discount_is_taken_off_the_position_fee() {
+        let pool = pool();
+        let mut position = position(PositionType::Long, STAKE, 50);
+        position.profit_fee_bps = 1_000;
+        assert_eq!(pool.settlement_payout_config(&position, 0).profit_fee_bps, 1_000);
+        assert_eq!(pool.settlement_payout_config(&position, 100).profit_fee_bps, 900);
+        assert_eq!(pool.settlement_payout_config(&position, u16::MAX).profit_fee_bps, 0);
+
+        // 100 profit at the discounted 9% fee
+        let config = pool.settlement_payout_config(&position, 100);
+        assert_eq!(compute_payout(&position.position_type, 50, 60, STAKE, &config).unwrap(), (STAKE + 91, 9));
+    }
+
+    #[test]
+    fn insurance_fee_is_its_share_of_the_fee_within_what_is_available() {
+        let mut pool = pool();
+        assert_eq!(pool.insurance_fee(9, u64::MAX).unwrap(), 0);
+        pool.config.insurance_fee_bps = 5_000;
+        assert_eq!(pool.insurance_fee(9, u64::MAX).unwrap(), 4);
+        assert_eq!(pool.insurance_fee(9, 3).unwrap(), 3);
+        assert_eq!(pool.insurance_fee(u64::MAX, u64::MAX).unwrap(), u64::MAX / 2);
+    }
+
+    #[test]
+    fn collected_fees_exclude_the_shares_paid_out() {
+        let mut pool = pool();
+        pool.collect_fee(9, 4).unwrap();
+        pool.collect_fee(9, 0).unwrap();
+        assert_eq!(pool.fees_collected, 14);
+        assert!(pool.collect_fee(9, 10).is_err());
+
+        pool.fees_collected = u64::MAX;
+        assert!(pool.collect_fee(1, 0).is_err());
+        assert_eq!(pool.fees_collected, u64::MAX);
+    }
+
+    #[test]
+    fn recorded_pnl_accumulates_and_rejects_overflow() {
+        let mut position = position(PositionType::Long, STAKE, 50);
+        position.record_pnl(1_500, 1_000).unwrap();
+        position.record_pnl(0, 200).unwrap();
+        assert_eq!(position.pnl, 300);
+
+        assert!(position.record_pnl(u64::MAX, 0).is_err());
+        position.pnl = i64::MIN;
+        assert!(position.record_pnl(0, 1).is_err());
+        assert_eq!(position.pnl, i64::MIN);
+    }
+
+    #[test]
+    fn added_volume_is_tracked_per_side_and_rejects_overflow() {
+        let mut pool = pool();
+        pool.add_volume(PositionType::Long, 1_000).unwrap();
+        pool.add_volume(PositionType::Straddle, 300).unwrap();
+        assert_eq!(pool.total_long_volume, 1_000);
+        assert_eq!(pool.total_short_volume, 0);
+        assert_eq!(pool.total_straddle_volume, 300);
+
+        assert!(pool.add_volume(PositionType::Long, u64::MAX).is_err());
+        assert_eq!(pool.total_long_volume, 1_000);
+    }
+
+    #[test]
+    fn recorded_losses_fund_the_winners_pot_and_reject_overflow() {
+        let mut pool = pool();
+        pool.record_outcome(1_000, 400).unwrap();
+        assert_eq!((pool.winners_pot, pool.total_losses), (600, 600));
+        pool.record_outcome(1_000, 1_200).unwrap();
+        assert_eq!((pool.winners_pot, pool.total_losses), (400, 600));
+
+        pool.winners_pot = u64::MAX;
+        assert!(pool.record_outcome(1, 0).is_err());
+    }
 }